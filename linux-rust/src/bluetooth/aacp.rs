@@ -1,24 +1,21 @@
-use crate::devices::airpods::AirPodsInformation;
+pub mod codec;
+
+use crate::bluetooth::managers::device_state_changed;
+use crate::bluetooth::transport::{L2capTransport, PacketTransport};
+use crate::devices::desired_settings::DesiredSettings;
 use crate::devices::enums::{DeviceData, DeviceInformation, DeviceType};
 use crate::utils::get_devices_path;
-use bluer::{
-    Address, AddressType, Error, Result,
-    l2cap::{SeqPacket, Socket, SocketAddr},
-};
-use log::{debug, error, info};
+use bluer::{Address, Error, Result};
+use codec::AacpPacket;
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::{Mutex, Notify, mpsc};
 use tokio::task::JoinSet;
-use tokio::time::{Instant, sleep};
 
 const PSM: u16 = 0x1001;
-const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
-const POLL_INTERVAL: Duration = Duration::from_millis(200);
-const HEADER_BYTES: [u8; 4] = [0x04, 0x00, 0x04, 0x00];
 
 pub mod opcodes {
     pub const SET_FEATURE_FLAGS: u8 = 0x4D;
@@ -49,7 +46,7 @@ pub struct ControlCommandStatus {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ControlCommandIdentifiers {
     MicMode = 0x01,
     ButtonSendMode = 0x05,
@@ -198,7 +195,7 @@ impl ProximityKeyType {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StemPressType {
     SinglePress = 0x05,
     DoublePress = 0x06,
@@ -207,13 +204,20 @@ pub enum StemPressType {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StemPressBudType {
     Left = 0x01,
     Right = 0x02,
 }
 
 impl StemPressType {
+    pub const ALL: [StemPressType; 4] = [
+        StemPressType::SinglePress,
+        StemPressType::DoublePress,
+        StemPressType::TriplePress,
+        StemPressType::LongPress,
+    ];
+
     fn from_u8(value: u8) -> Option<Self> {
         match value {
             0x05 => Some(Self::SinglePress),
@@ -223,9 +227,33 @@ impl StemPressType {
             _ => None,
         }
     }
+
+    /// Bit of this press in the StemConfig value. Presses with their bit set are reported to
+    /// us instead of being handled by the AirPods.
+    pub fn stem_config_bit(self) -> u8 {
+        match self {
+            StemPressType::SinglePress => 0x01,
+            StemPressType::DoublePress => 0x02,
+            StemPressType::TriplePress => 0x04,
+            StemPressType::LongPress => 0x08,
+        }
+    }
+}
+
+impl std::fmt::Display for StemPressType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StemPressType::SinglePress => write!(f, "Single Press"),
+            StemPressType::DoublePress => write!(f, "Double Press"),
+            StemPressType::TriplePress => write!(f, "Triple Press"),
+            StemPressType::LongPress => write!(f, "Press and Hold"),
+        }
+    }
 }
 
 impl StemPressBudType {
+    pub const ALL: [StemPressBudType; 2] = [StemPressBudType::Left, StemPressBudType::Right];
+
     fn from_u8(value: u8) -> Option<Self> {
         match value {
             0x01 => Some(Self::Left),
@@ -235,6 +263,15 @@ impl StemPressBudType {
     }
 }
 
+impl std::fmt::Display for StemPressBudType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StemPressBudType::Left => write!(f, "Left"),
+            StemPressBudType::Right => write!(f, "Right"),
+        }
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioSourceType {
@@ -253,7 +290,7 @@ pub enum BatteryComponent {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub enum BatteryStatus {
     Charging = 1,
     NotCharging = 2,
@@ -261,7 +298,7 @@ pub enum BatteryStatus {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub enum EarDetectionStatus {
     InEar = 0x00,
     OutOfEar = 0x01,
@@ -269,6 +306,41 @@ pub enum EarDetectionStatus {
     Disconnected = 0x03,
 }
 
+impl BatteryComponent {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::Headphone),
+            0x02 => Some(Self::Right),
+            0x04 => Some(Self::Left),
+            0x08 => Some(Self::Case),
+            _ => None,
+        }
+    }
+}
+
+impl BatteryStatus {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::Charging),
+            0x02 => Some(Self::NotCharging),
+            0x04 => Some(Self::Disconnected),
+            _ => None,
+        }
+    }
+}
+
+impl EarDetectionStatus {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(Self::InEar),
+            0x01 => Some(Self::OutOfEar),
+            0x02 => Some(Self::InCase),
+            0x03 => Some(Self::Disconnected),
+            _ => None,
+        }
+    }
+}
+
 impl AudioSourceType {
     fn from_u8(value: u8) -> Option<Self> {
         match value {
@@ -293,6 +365,81 @@ pub struct BatteryInfo {
     pub status: BatteryStatus,
 }
 
+/// Adaptive noise control strength goes from 0 to this.
+pub const AUTO_ANC_STRENGTH_MAX: u8 = 100;
+
+pub const HEADPHONE_ACCOMMODATION_BANDS: usize = 8;
+
+/// Headphone Accommodation (the EQ_DATA packet): an EQ applied to phone calls and/or media.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadphoneAccommodation {
+    pub phone: bool,
+    pub media: bool,
+    pub eq: [f32; HEADPHONE_ACCOMMODATION_BANDS],
+}
+
+/// Listening modes the stem long press cycles through (`ListeningModeConfigs`), a bitmask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ListeningModeCycle(u8);
+
+impl ListeningModeCycle {
+    pub const OFF: Self = Self(0x01);
+    pub const NOISE_CANCELLATION: Self = Self(0x02);
+    pub const TRANSPARENCY: Self = Self(0x04);
+    pub const ADAPTIVE: Self = Self(0x08);
+    /// The AirPods need something to cycle between.
+    pub const MIN_MODES: u32 = 2;
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(
+            bits & (Self::OFF.0
+                | Self::NOISE_CANCELLATION.0
+                | Self::TRANSPARENCY.0
+                | Self::ADAPTIVE.0),
+        )
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn with(self, other: Self, enabled: bool) -> Self {
+        if enabled {
+            Self(self.0 | other.0)
+        } else {
+            Self(self.0 & !other.0)
+        }
+    }
+
+    pub fn mode_count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub fn is_valid(self) -> bool {
+        self.mode_count() >= Self::MIN_MODES
+    }
+
+    /// The ListeningMode value (0x01 Off to 0x04 Adaptive) that comes after `mode` in the cycle.
+    pub fn next_mode(self, mode: u8) -> u8 {
+        (1..=4)
+            .map(|offset| (mode.saturating_sub(1) + offset) % 4 + 1)
+            .find(|next| self.0 & (1 << (next - 1)) != 0)
+            .unwrap_or(mode)
+    }
+}
+
+impl std::ops::BitOr for ListeningModeCycle {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectedDevice {
     pub mac: String,
@@ -312,9 +459,15 @@ pub enum AACPEvent {
     ConnectedDevices(Vec<ConnectedDevice>, Vec<ConnectedDevice>),
     OwnershipToFalseRequest,
     StemPress(StemPressType, StemPressBudType),
+    HeadTracking {
+        orientation: [i16; 3],
+        accel_h: i16,
+        accel_v: i16,
+    },
+    HeadphoneAccommodation(HeadphoneAccommodation),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AirPodsLEKeys {
     pub irk: String,
     pub enc_key: String,
@@ -336,6 +489,10 @@ pub struct AACPManagerState {
     event_tx: Option<mpsc::UnboundedSender<AACPEvent>>,
     pub devices: HashMap<String, DeviceData>,
     pub airpods_mac: Option<Address>,
+    pub head_tracking: bool,
+    pub headphone_accommodation: Option<HeadphoneAccommodation>,
+    // presses reported to us instead of handled by the AirPods, see StemPressType::stem_config_bit
+    pub stem_config: u8,
 }
 
 impl AACPManagerState {
@@ -359,6 +516,9 @@ impl AACPManagerState {
             event_tx: None,
             devices,
             airpods_mac: None,
+            head_tracking: false,
+            headphone_accommodation: None,
+            stem_config: StemPressType::SinglePress.stem_config_bit(),
         }
     }
 }
@@ -367,6 +527,8 @@ impl AACPManagerState {
 pub struct AACPManager {
     pub state: Arc<Mutex<AACPManagerState>>,
     tasks: Arc<Mutex<JoinSet<()>>>,
+    // signalled when the remote closes the channel
+    closed: Arc<Notify>,
 }
 
 impl AACPManager {
@@ -374,74 +536,61 @@ impl AACPManager {
         AACPManager {
             state: Arc::new(Mutex::new(AACPManagerState::new())),
             tasks: Arc::new(Mutex::new(JoinSet::new())),
+            closed: Arc::new(Notify::new()),
         }
     }
 
-    pub async fn connect(&mut self, addr: Address) {
+    pub async fn connect(&mut self, addr: Address) -> Result<()> {
         info!("AACPManager connecting to {} on PSM {:#06X}...", addr, PSM);
-        let target_sa = SocketAddr::new(addr, AddressType::BrEdr, PSM);
-
-        {
-            let mut state = self.state.lock().await;
-            state.airpods_mac = Some(addr);
-        }
-
-        let socket = match Socket::new_seq_packet() {
-            Ok(s) => s,
-            Err(e) => {
-                error!("Failed to create L2CAP socket: {}", e);
-                return;
-            }
-        };
+        let transport = L2capTransport::connect(addr, PSM).await?;
+        self.connect_with_transport(addr, Arc::new(transport)).await;
+        Ok(())
+    }
 
-        let seq_packet =
-            match tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(target_sa)).await {
-                Ok(Ok(s)) => Arc::new(s),
-                Ok(Err(e)) => {
-                    error!("L2CAP connect failed: {}", e);
-                    return;
-                }
-                Err(_) => {
-                    error!("L2CAP connect timed out");
-                    return;
-                }
-            };
+    pub async fn is_connected(&self) -> bool {
+        self.state.lock().await.sender.is_some()
+    }
 
-        // Wait for connection to be fully established
-        let start = Instant::now();
-        loop {
-            match seq_packet.peer_addr() {
-                Ok(peer) if peer.cid != 0 => break,
-                Ok(_) => { /* still waiting */ }
-                Err(e) => {
-                    if e.raw_os_error() == Some(107) {
-                        // ENOTCONN
-                        error!("Peer has disconnected during connection setup.");
-                        return;
-                    }
-                    error!("Error getting peer address: {}", e);
-                }
-            }
-            if start.elapsed() >= CONNECT_TIMEOUT {
-                error!("Timed out waiting for L2CAP connection to be fully established.");
-                return;
-            }
-            sleep(POLL_INTERVAL).await;
-        }
+    /// Waits until the channel is closed by the remote, fails or is disconnected.
+    pub async fn wait_closed(&self) {
+        self.closed.notified().await;
+    }
 
-        info!("L2CAP connection established with {}", addr);
+    /// Stops the receive and send tasks and drops every channel handed out, so the tasks
+    /// listening on them finish as well.
+    pub async fn disconnect(&self) {
+        self.tasks.lock().await.abort_all();
+        let mut state = self.state.lock().await;
+        state.sender = None;
+        state.event_tx = None;
+        state.control_command_subscribers.clear();
+        state.control_command_status_list.clear();
+        state.connected_devices.clear();
+        state.owns = false;
+        state.head_tracking = false;
+        drop(state);
+        // wake up whoever waits for the channel, the receive task can't anymore
+        self.closed.notify_one();
+    }
 
+    /// Starts the manager on an already established transport, e.g. a `MemoryTransport`.
+    pub async fn connect_with_transport<T: PacketTransport>(
+        &mut self,
+        addr: Address,
+        transport: Arc<T>,
+    ) {
         let (tx, rx) = mpsc::channel(128);
 
         let manager_clone = self.clone();
         {
             let mut state = self.state.lock().await;
+            state.airpods_mac = Some(addr);
             state.sender = Some(tx);
         }
 
         let mut tasks = self.tasks.lock().await;
-        tasks.spawn(recv_thread(manager_clone, seq_packet.clone()));
-        tasks.spawn(send_thread(rx, seq_packet));
+        tasks.spawn(recv_thread(manager_clone, transport.clone()));
+        tasks.spawn(send_thread(rx, transport));
     }
 
     async fn send_packet(&self, data: &[u8]) -> Result<()> {
@@ -463,11 +612,6 @@ impl AACPManager {
         }
     }
 
-    async fn send_data_packet(&self, data: &[u8]) -> Result<()> {
-        let packet = [HEADER_BYTES.as_slice(), data].concat();
-        self.send_packet(&packet).await
-    }
-
     pub async fn set_event_channel(&self, tx: mpsc::UnboundedSender<AACPEvent>) {
         let mut state = self.state.lock().await;
         state.event_tx = Some(tx);
@@ -501,61 +645,16 @@ impl AACPManager {
     }
 
     pub async fn receive_packet(&self, packet: &[u8]) {
-        if !packet.starts_with(&HEADER_BYTES) {
-            debug!(
-                "Received packet does not start with expected header: {}",
-                hex::encode(packet)
-            );
-            return;
-        }
-        if packet.len() < 5 {
-            debug!("Received packet too short: {}", hex::encode(packet));
-            return;
-        }
-
-        let opcode = packet[4];
-        let payload = &packet[4..];
+        let packet = match AacpPacket::decode(packet) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("Failed to decode packet ({}): {}", e, hex::encode(packet));
+                return;
+            }
+        };
 
-        match opcode {
-            opcodes::BATTERY_INFO => {
-                if payload.len() < 3 {
-                    error!("Battery Info packet too short: {}", hex::encode(payload));
-                    return;
-                }
-                let count = payload[2] as usize;
-                if payload.len() < 3 + count * 5 {
-                    error!(
-                        "Battery Info packet length mismatch: {}",
-                        hex::encode(payload)
-                    );
-                    return;
-                }
-                let mut batteries = Vec::with_capacity(count);
-                for i in 0..count {
-                    let base_index = 3 + i * 5;
-                    batteries.push(BatteryInfo {
-                        component: match payload[base_index] {
-                            0x01 => BatteryComponent::Headphone,
-                            0x02 => BatteryComponent::Right,
-                            0x04 => BatteryComponent::Left,
-                            0x08 => BatteryComponent::Case,
-                            _ => {
-                                error!("Unknown battery component: {:#04x}", payload[base_index]);
-                                continue;
-                            }
-                        },
-                        level: payload[base_index + 2],
-                        status: match payload[base_index + 3] {
-                            0x01 => BatteryStatus::Charging,
-                            0x02 => BatteryStatus::NotCharging,
-                            0x04 => BatteryStatus::Disconnected,
-                            _ => {
-                                error!("Unknown battery status: {:#04x}", payload[base_index + 3]);
-                                continue;
-                            }
-                        },
-                    });
-                }
+        match packet {
+            AacpPacket::BatteryInfo(batteries) => {
                 let mut state = self.state.lock().await;
                 state.battery_info = batteries.clone();
                 if let Some(ref tx) = state.event_tx {
@@ -563,82 +662,38 @@ impl AACPManager {
                 }
                 info!("Received Battery Info: {:?}", state.battery_info);
             }
-            opcodes::CONTROL_COMMAND => {
-                if payload.len() < 7 {
-                    error!("Control Command packet too short: {}", hex::encode(payload));
-                    return;
+            AacpPacket::ControlCommand(status) => {
+                let identifier = status.identifier;
+                let value = status.value.clone();
+                let mut state = self.state.lock().await;
+                if let Some(existing) = state
+                    .control_command_status_list
+                    .iter_mut()
+                    .find(|s| s.identifier == identifier)
+                {
+                    existing.value = value.clone();
+                } else {
+                    state.control_command_status_list.push(status.clone());
                 }
-                let identifier_byte = payload[2];
-                let value_bytes = &payload[3..7];
-
-                let last_non_zero = value_bytes.iter().rposition(|&b| b != 0);
-                let value = match last_non_zero {
-                    Some(i) => value_bytes[..=i].to_vec(),
-                    None => vec![0],
-                };
-
-                if let Some(identifier) = ControlCommandIdentifiers::from_u8(identifier_byte) {
-                    let status = ControlCommandStatus {
-                        identifier,
-                        value: value.clone(),
-                    };
-                    let mut state = self.state.lock().await;
-                    if let Some(existing) = state
-                        .control_command_status_list
-                        .iter_mut()
-                        .find(|s| s.identifier == identifier)
-                    {
-                        existing.value = value.clone();
-                    } else {
-                        state.control_command_status_list.push(status.clone());
-                    }
-                    if identifier == ControlCommandIdentifiers::OwnsConnection {
-                        state.owns = value_bytes[0] != 0;
-                    }
-                    if let Some(subscribers) = state.control_command_subscribers.get(&identifier) {
-                        for sub in subscribers {
-                            let _ = sub.send(value.clone());
-                        }
-                    }
-                    if let Some(ref tx) = state.event_tx {
-                        let _ = tx.send(AACPEvent::ControlCommand(status));
+                if identifier == ControlCommandIdentifiers::OwnsConnection {
+                    state.owns = value[0] != 0;
+                }
+                if let Some(subscribers) = state.control_command_subscribers.get(&identifier) {
+                    for sub in subscribers {
+                        let _ = sub.send(value.clone());
                     }
-                    info!(
-                        "Received Control Command: {:?}, value: {}",
-                        identifier,
-                        hex::encode(&value)
-                    );
-                } else {
-                    debug!(
-                        "Unknown Control Command identifier: {:#04x}",
-                        identifier_byte
-                    );
                 }
+                if let Some(ref tx) = state.event_tx {
+                    let _ = tx.send(AACPEvent::ControlCommand(status));
+                }
+                info!(
+                    "Received Control Command: {:?}, value: {}",
+                    identifier,
+                    hex::encode(&value)
+                );
             }
-            opcodes::EAR_DETECTION => {
-                let primary_status = packet[6];
-                let secondary_status = packet[7];
-                let mut statuses = Vec::new();
-                statuses.push(match primary_status {
-                    0x00 => EarDetectionStatus::InEar,
-                    0x01 => EarDetectionStatus::OutOfEar,
-                    0x02 => EarDetectionStatus::InCase,
-                    0x03 => EarDetectionStatus::Disconnected,
-                    _ => {
-                        error!("Unknown ear detection status: {:#04x}", primary_status);
-                        EarDetectionStatus::OutOfEar
-                    }
-                });
-                statuses.push(match secondary_status {
-                    0x00 => EarDetectionStatus::InEar,
-                    0x01 => EarDetectionStatus::OutOfEar,
-                    0x02 => EarDetectionStatus::InCase,
-                    0x03 => EarDetectionStatus::Disconnected,
-                    _ => {
-                        error!("Unknown ear detection status: {:#04x}", secondary_status);
-                        EarDetectionStatus::OutOfEar
-                    }
-                });
+            AacpPacket::EarDetection(primary, secondary) => {
+                let statuses = vec![primary, secondary];
                 let mut state = self.state.lock().await;
                 state.old_ear_detection_status = state.ear_detection_status.clone();
                 state.ear_detection_status = statuses.clone();
@@ -658,67 +713,15 @@ impl AACPManager {
                     state.ear_detection_status
                 );
             }
-            opcodes::CONVERSATION_AWARENESS => {
-                if packet.len() == 10 {
-                    let status = packet[9];
-                    let mut state = self.state.lock().await;
-                    state.conversational_awareness_status = status;
-                    if let Some(ref tx) = state.event_tx {
-                        let _ = tx.send(AACPEvent::ConversationalAwareness(status));
-                    }
-                    info!("Received Conversation Awareness: {}", status);
-                } else {
-                    info!(
-                        "Received Conversation Awareness packet with unexpected length: {}",
-                        packet.len()
-                    );
+            AacpPacket::ConversationalAwareness(status) => {
+                let mut state = self.state.lock().await;
+                state.conversational_awareness_status = status;
+                if let Some(ref tx) = state.event_tx {
+                    let _ = tx.send(AACPEvent::ConversationalAwareness(status));
                 }
+                info!("Received Conversation Awareness: {}", status);
             }
-            opcodes::INFORMATION => {
-                if payload.len() < 6 {
-                    error!("Information packet too short: {}", hex::encode(payload));
-                    return;
-                }
-                let data = &payload[4..];
-                let mut index = 0;
-                while index < data.len() && data[index] != 0x00 {
-                    index += 1;
-                }
-                let mut strings = Vec::new();
-                while index < data.len() {
-                    while index < data.len() && data[index] == 0x00 {
-                        index += 1;
-                    }
-                    if index >= data.len() {
-                        break;
-                    }
-                    let start = index;
-                    while index < data.len() && data[index] != 0x00 {
-                        index += 1;
-                    }
-                    let str_bytes = &data[start..index];
-                    if let Ok(s) = std::str::from_utf8(str_bytes) {
-                        strings.push(s.to_string());
-                    }
-                }
-                strings.remove(0);
-                let info = AirPodsInformation {
-                    name: strings.first().cloned().unwrap_or_default(),
-                    model_number: strings.get(1).cloned().unwrap_or_default(),
-                    manufacturer: strings.get(2).cloned().unwrap_or_default(),
-                    serial_number: strings.get(3).cloned().unwrap_or_default(),
-                    version1: strings.get(4).cloned().unwrap_or_default(),
-                    version2: strings.get(5).cloned().unwrap_or_default(),
-                    hardware_revision: strings.get(6).cloned().unwrap_or_default(),
-                    updater_identifier: strings.get(7).cloned().unwrap_or_default(),
-                    left_serial_number: strings.get(8).cloned().unwrap_or_default(),
-                    right_serial_number: strings.get(9).cloned().unwrap_or_default(),
-                    version3: strings.get(10).cloned().unwrap_or_default(),
-                    le_keys: AirPodsLEKeys {
-                        irk: "".to_string(),
-                        enc_key: "".to_string(),
-                    },
-                };
+            AacpPacket::Information(info) => {
                 let mut state = self.state.lock().await;
                 if let Some(mac) = state.airpods_mac
                     && let Some(device_data) = state.devices.get_mut(&mac.to_string())
@@ -738,41 +741,7 @@ impl AACPManager {
                 }
                 info!("Received Information: {:?}", info);
             }
-
-            opcodes::PROXIMITY_KEYS_RSP => {
-                if payload.len() < 4 {
-                    error!(
-                        "Proximity Keys Response packet too short: {}",
-                        hex::encode(payload)
-                    );
-                    return;
-                }
-                let key_count = payload[2] as usize;
-                debug!("Proximity Keys Response contains {} keys.", key_count);
-                let mut offset = 3;
-                let mut keys = Vec::new();
-                for _ in 0..key_count {
-                    if offset + 3 >= payload.len() {
-                        error!(
-                            "Proximity Keys Response packet too short while parsing keys: {}",
-                            hex::encode(payload)
-                        );
-                        return;
-                    }
-                    let key_type = payload[offset];
-                    let key_length = payload[offset + 2] as usize;
-                    offset += 4;
-                    if offset + key_length > payload.len() {
-                        error!(
-                            "Proximity Keys Response packet too short for key data: {}",
-                            hex::encode(payload)
-                        );
-                        return;
-                    }
-                    let key_data = payload[offset..offset + key_length].to_vec();
-                    keys.push((key_type, key_data));
-                    offset += key_length;
-                }
+            AacpPacket::ProximityKeysResponse(keys) => {
                 info!(
                     "Received Proximity Keys Response: {:?}",
                     keys.iter()
@@ -824,49 +793,26 @@ impl AACPManager {
                     error!("Failed to save devices: {}", e);
                 }
             }
-            opcodes::STEM_PRESS => {
-                if packet.len() >= 8 {
-                    if let (Some(press_type), Some(bud_type)) = (
-                        StemPressType::from_u8(packet[6]),
-                        StemPressBudType::from_u8(packet[7]),
-                    ) {
-                        info!("Stem Press: {:?} on {:?}", press_type, bud_type);
-                        let state = self.state.lock().await;
-                        if let Some(ref tx) = state.event_tx {
-                            let _ = tx.send(AACPEvent::StemPress(press_type, bud_type));
-                        }
-                        drop(state);
-                        // Re-enable stem press detection after receiving a press
-                        if let Err(e) = self
-                            .send_control_command(
-                                ControlCommandIdentifiers::StemConfig,
-                                &[0x01, 0, 0, 0],
-                            )
-                            .await
-                        {
-                            error!("Failed to re-enable stem press: {}", e);
-                        }
-                    } else {
-                        debug!(
-                            "Unknown stem press values: type={:#04x}, bud={:#04x}",
-                            packet[6], packet[7]
-                        );
-                    }
-                } else {
-                    error!("Stem Press packet too short: {}", hex::encode(packet));
+            AacpPacket::StemPress(press_type, bud_type) => {
+                info!("Stem Press: {:?} on {:?}", press_type, bud_type);
+                let state = self.state.lock().await;
+                if let Some(ref tx) = state.event_tx {
+                    let _ = tx.send(AACPEvent::StemPress(press_type, bud_type));
                 }
-            }
-            opcodes::AUDIO_SOURCE => {
-                if payload.len() < 9 {
-                    error!("Audio Source packet too short: {}", hex::encode(payload));
-                    return;
+                let stem_config = state.stem_config;
+                drop(state);
+                // Re-enable stem press detection after receiving a press
+                if let Err(e) = self
+                    .send_control_command(
+                        ControlCommandIdentifiers::StemConfig,
+                        &[stem_config, 0, 0, 0],
+                    )
+                    .await
+                {
+                    error!("Failed to re-enable stem press: {}", e);
                 }
-                let mac = format!(
-                    "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                    payload[7], payload[6], payload[5], payload[4], payload[3], payload[2]
-                );
-                let typ = AudioSourceType::from_u8(payload[8]).unwrap_or(AudioSourceType::None);
-                let audio_source = AudioSource { mac, r#type: typ };
+            }
+            AacpPacket::AudioSource(audio_source) => {
                 let mut state = self.state.lock().await;
                 state.audio_source = Some(audio_source.clone());
                 if let Some(ref tx) = state.event_tx {
@@ -874,43 +820,7 @@ impl AACPManager {
                 }
                 info!("Received Audio Source: {:?}", state.audio_source);
             }
-            opcodes::CONNECTED_DEVICES => {
-                if payload.len() < 3 {
-                    error!(
-                        "Connected Devices packet too short: {}",
-                        hex::encode(payload)
-                    );
-                    return;
-                }
-                let count = payload[2] as usize;
-                if payload.len() < 3 + count * 8 {
-                    error!(
-                        "Connected Devices packet length mismatch: {}",
-                        hex::encode(payload)
-                    );
-                    return;
-                }
-                let mut devices = Vec::with_capacity(count);
-                for i in 0..count {
-                    let base = 5 + i * 8;
-                    let mac = format!(
-                        "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                        payload[base],
-                        payload[base + 1],
-                        payload[base + 2],
-                        payload[base + 3],
-                        payload[base + 4],
-                        payload[base + 5]
-                    );
-                    let info1 = payload[base + 6];
-                    let info2 = payload[base + 7];
-                    devices.push(ConnectedDevice {
-                        mac,
-                        info1,
-                        info2,
-                        r#type: None,
-                    });
-                }
+            AacpPacket::ConnectedDevices(devices) => {
                 let mut state = self.state.lock().await;
                 state.old_connected_devices = state.connected_devices.clone();
                 state.connected_devices = devices.clone();
@@ -922,8 +832,8 @@ impl AACPManager {
                 }
                 info!("Received Connected Devices: {:?}", state.connected_devices);
             }
-            opcodes::SMART_ROUTING_RESP => {
-                let packet_string = String::from_utf8_lossy(&payload[2..]);
+            AacpPacket::SmartRoutingResponse(data) => {
+                let packet_string = String::from_utf8_lossy(&data);
                 info!("Received Smart Routing Response: {}", packet_string);
                 if packet_string.contains("SetOwnershipToFalse") {
                     info!("Received OwnershipToFalse request");
@@ -932,58 +842,63 @@ impl AACPManager {
                     }
                 }
             }
-            opcodes::EQ_DATA => {
-                debug!("Received EQ Data");
+            AacpPacket::HeadTrackingSample {
+                orientation,
+                accel_h,
+                accel_v,
+            } => {
+                let state = self.state.lock().await;
+                if let Some(ref tx) = state.event_tx {
+                    let _ = tx.send(AACPEvent::HeadTracking {
+                        orientation,
+                        accel_h,
+                        accel_v,
+                    });
+                }
+            }
+            AacpPacket::HeadphoneAccommodation(accommodation) => {
+                debug!("Received Headphone Accommodation: {:?}", accommodation);
+                let mut state = self.state.lock().await;
+                state.headphone_accommodation = Some(accommodation);
+                if let Some(ref tx) = state.event_tx {
+                    let _ = tx.send(AACPEvent::HeadphoneAccommodation(accommodation));
+                }
             }
-            _ => debug!("Received unknown packet with opcode {:#04x}", opcode),
+            other => debug!("Received unhandled packet: {:?}", other),
         }
     }
 
+    pub async fn send_aacp_packet(&self, packet: &AacpPacket) -> Result<()> {
+        self.send_packet(&packet.encode()).await
+    }
+
     pub async fn send_notification_request(&self) -> Result<()> {
-        let opcode = [opcodes::REQUEST_NOTIFICATIONS, 0x00];
-        let data = [0xFF, 0xFF, 0xFF, 0xFF];
-        let packet = [opcode.as_slice(), data.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::RequestNotifications([0xFF, 0xFF, 0xFF, 0xFF]))
+            .await
     }
 
     pub async fn send_set_feature_flags_packet(&self) -> Result<()> {
-        let opcode = [opcodes::SET_FEATURE_FLAGS, 0x00];
-        // let data = [0xD7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
-        let data = [0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]; // adaptive volume is actually useful, seeing if it works
-        let packet = [opcode.as_slice(), data.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        // let flags = [0xD7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let flags = [0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]; // adaptive volume is actually useful, seeing if it works
+        self.send_aacp_packet(&AacpPacket::SetFeatureFlags(flags))
+            .await
     }
 
     pub async fn send_handshake(&self) -> Result<()> {
-        let packet = [
-            0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00,
-        ];
-        self.send_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::Handshake).await
     }
 
     pub async fn send_proximity_keys_request(
         &self,
         key_types: Vec<ProximityKeyType>,
     ) -> Result<()> {
-        let opcode = [opcodes::PROXIMITY_KEYS_REQ, 0x00];
-        let mut data = Vec::with_capacity(2);
-        data.push(key_types.iter().fold(0u8, |acc, kt| acc | (*kt as u8)));
-        data.push(0x00);
-        let packet = [opcode.as_slice(), data.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::ProximityKeysRequest(key_types))
+            .await
     }
 
     pub async fn send_rename_packet(&self, name: &str) -> Result<()> {
-        let name_bytes = name.as_bytes();
-        let size = name_bytes.len();
-        let mut packet = Vec::with_capacity(5 + size);
-        packet.push(opcodes::RENAME);
-        packet.push(0x00);
-        packet.push(size as u8);
-        packet.push(0x00);
-        packet.extend_from_slice(name_bytes);
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::Rename(name.to_string()))
+            .await
     }
 
     pub async fn send_control_command(
@@ -991,13 +906,16 @@ impl AACPManager {
         identifier: ControlCommandIdentifiers,
         value: &[u8],
     ) -> Result<()> {
-        let opcode = [opcodes::CONTROL_COMMAND, 0x00];
-        let mut data = vec![identifier as u8];
-        for i in 0..4 {
-            data.push(value.get(i).copied().unwrap_or(0));
+        self.send_aacp_packet(&AacpPacket::ControlCommand(ControlCommandStatus {
+            identifier,
+            value: value.to_vec(),
+        }))
+        .await?;
+        // whatever is set from here is what the AirPods should be brought back to later
+        if let Some(mac) = self.state.lock().await.airpods_mac {
+            DesiredSettings::record(&mac.to_string(), identifier, value);
         }
-        let packet = [opcode.as_slice(), data.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        Ok(())
     }
 
     pub async fn send_media_information_new_device(
@@ -1005,72 +923,16 @@ impl AACPManager {
         self_mac_address: &str,
         target_mac_address: &str,
     ) -> Result<()> {
-        let opcode = [opcodes::SMART_ROUTING, 0x00];
-        let mut buffer = Vec::with_capacity(112);
-        let target_mac_bytes: Vec<u8> = target_mac_address
-            .split(':')
-            .map(|s| u8::from_str_radix(s, 16).unwrap())
-            .collect();
-        buffer.extend_from_slice(&target_mac_bytes.iter().rev().cloned().collect::<Vec<u8>>());
-
-        buffer.extend_from_slice(&[0x68, 0x00]);
-        buffer.extend_from_slice(&[0x01, 0xE5, 0x4A]);
-        buffer.extend_from_slice(b"playingApp");
-        buffer.push(0x42);
-        buffer.extend_from_slice(b"NA");
-        buffer.push(0x52);
-        buffer.extend_from_slice(b"hostStreamingState");
-        buffer.push(0x42);
-        buffer.extend_from_slice(b"NO");
-        buffer.push(0x49);
-        buffer.extend_from_slice(b"btAddress");
-        buffer.push(0x51);
-        buffer.extend_from_slice(self_mac_address.as_bytes());
-        buffer.push(0x46);
-        buffer.extend_from_slice(b"btName");
-        buffer.push(0x43);
-        buffer.extend_from_slice(b"Mac");
-        buffer.push(0x58);
-        buffer.extend_from_slice(b"otherDevice");
-        buffer.extend_from_slice(b"AudioCategory");
-        buffer.extend_from_slice(&[0x30, 0x64]);
-
-        let packet = [opcode.as_slice(), buffer.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::media_information_new_device(
+            self_mac_address,
+            target_mac_address,
+        ))
+        .await
     }
 
     pub async fn send_hijack_request(&self, target_mac_address: &str) -> Result<()> {
-        let opcode = [opcodes::SMART_ROUTING, 0x00];
-        let mut buffer = Vec::with_capacity(106);
-        let target_mac_bytes: Vec<u8> = target_mac_address
-            .split(':')
-            .map(|s| u8::from_str_radix(s, 16).unwrap())
-            .collect();
-        buffer.extend_from_slice(&target_mac_bytes.iter().rev().cloned().collect::<Vec<u8>>());
-        buffer.extend_from_slice(&[0x62, 0x00]);
-        buffer.extend_from_slice(&[0x01, 0xE5]);
-        buffer.push(0x4A);
-        buffer.extend_from_slice(b"localscore");
-        buffer.extend_from_slice(&[0x30, 0x64]);
-        buffer.push(0x46);
-        buffer.extend_from_slice(b"reason");
-        buffer.push(0x48);
-        buffer.extend_from_slice(b"Hijackv2");
-        buffer.push(0x51);
-        buffer.extend_from_slice(b"audioRoutingScore");
-        buffer.extend_from_slice(&[0x31, 0x2D, 0x01, 0x5F]);
-        buffer.extend_from_slice(b"audioRoutingSetOwnershipToFalse");
-        buffer.push(0x01);
-        buffer.push(0x4B);
-        buffer.extend_from_slice(b"remotescore");
-        buffer.push(0xA5);
-
-        while buffer.len() < 106 {
-            buffer.push(0x00);
-        }
-
-        let packet = [opcode.as_slice(), buffer.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::hijack_request(target_mac_address))
+            .await
     }
 
     pub async fn send_media_information(
@@ -1079,104 +941,22 @@ impl AACPManager {
         target_mac_address: &str,
         streaming_state: bool,
     ) -> Result<()> {
-        let opcode = [opcodes::SMART_ROUTING, 0x00];
-        let mut buffer = Vec::with_capacity(138);
-        let target_mac_bytes: Vec<u8> = target_mac_address
-            .split(':')
-            .map(|s| u8::from_str_radix(s, 16).unwrap())
-            .collect();
-        buffer.extend_from_slice(&target_mac_bytes.iter().rev().cloned().collect::<Vec<u8>>());
-        buffer.extend_from_slice(&[0x82, 0x00]);
-        buffer.extend_from_slice(&[0x01, 0xE5, 0x4A]);
-        buffer.extend_from_slice(b"PlayingApp");
-        buffer.push(0x56);
-        buffer.extend_from_slice(b"com.google.ios.youtube");
-        buffer.push(0x52);
-        buffer.extend_from_slice(b"HostStreamingState");
-        buffer.push(0x42);
-        buffer.extend_from_slice(if streaming_state { b"YES" } else { b"NO" });
-        buffer.push(0x49);
-        buffer.extend_from_slice(b"btAddress");
-        buffer.push(0x51);
-        buffer.extend_from_slice(self_mac_address.as_bytes());
-        buffer.extend_from_slice(b"btName");
-        buffer.push(0x43);
-        buffer.extend_from_slice(b"Mac");
-        buffer.push(0x58);
-        buffer.extend_from_slice(b"otherDevice");
-        buffer.extend_from_slice(b"AudioCategory");
-        buffer.extend_from_slice(&[0x31, 0x2D, 0x01]);
-
-        while buffer.len() < 138 {
-            buffer.push(0x00);
-        }
-        let packet = [opcode.as_slice(), buffer.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::media_information(
+            self_mac_address,
+            target_mac_address,
+            streaming_state,
+        ))
+        .await
     }
 
     pub async fn send_smart_routing_show_ui(&self, target_mac_address: &str) -> Result<()> {
-        let opcode = [opcodes::SMART_ROUTING, 0x00];
-        let mut buffer = Vec::with_capacity(134);
-        let target_mac_bytes: Vec<u8> = target_mac_address
-            .split(':')
-            .map(|s| u8::from_str_radix(s, 16).unwrap())
-            .collect();
-        buffer.extend_from_slice(&target_mac_bytes.iter().rev().cloned().collect::<Vec<u8>>());
-        buffer.extend_from_slice(&[0x7E, 0x00]);
-        buffer.extend_from_slice(&[0x01, 0xE6, 0x5B]);
-        buffer.extend_from_slice(b"SmartRoutingKeyShowNearbyUI");
-        buffer.push(0x01);
-        buffer.push(0x4A);
-        buffer.extend_from_slice(b"localscore");
-        buffer.extend_from_slice(&[0x31, 0x2D]);
-        buffer.push(0x01);
-        buffer.push(0x46);
-        buffer.extend_from_slice(b"reasonHhijackv2");
-        buffer.push(0x51);
-        buffer.extend_from_slice(b"audioRoutingScore");
-        buffer.push(0xA2);
-        buffer.push(0x5F);
-        buffer.extend_from_slice(b"audioRoutingSetOwnershipToFalse");
-        buffer.push(0x01);
-        buffer.push(0x4B);
-        buffer.extend_from_slice(b"remotescore");
-        buffer.push(0xA2);
-
-        while buffer.len() < 134 {
-            buffer.push(0x00);
-        }
-
-        let packet = [opcode.as_slice(), buffer.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::smart_routing_show_ui(target_mac_address))
+            .await
     }
 
     pub async fn send_hijack_reversed(&self, target_mac_address: &str) -> Result<()> {
-        let opcode = [opcodes::SMART_ROUTING, 0x00];
-        let mut buffer = Vec::with_capacity(97);
-        let target_mac_bytes: Vec<u8> = target_mac_address
-            .split(':')
-            .map(|s| u8::from_str_radix(s, 16).unwrap())
-            .collect();
-        buffer.extend_from_slice(&target_mac_bytes.iter().rev().cloned().collect::<Vec<u8>>());
-        buffer.extend_from_slice(&[0x59, 0x00]);
-        buffer.extend_from_slice(&[0x01, 0xE3]);
-        buffer.push(0x5F);
-        buffer.extend_from_slice(b"audioRoutingSetOwnershipToFalse");
-        buffer.push(0x01);
-        buffer.push(0x59);
-        buffer.extend_from_slice(b"audioRoutingShowReverseUI");
-        buffer.push(0x01);
-        buffer.push(0x46);
-        buffer.extend_from_slice(b"reason");
-        buffer.push(0x53);
-        buffer.extend_from_slice(b"ReverseBannerTapped");
-
-        while buffer.len() < 97 {
-            buffer.push(0x00);
-        }
-
-        let packet = [opcode.as_slice(), buffer.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::hijack_reversed(target_mac_address))
+            .await
     }
 
     pub async fn send_add_tipi_device(
@@ -1184,42 +964,125 @@ impl AACPManager {
         self_mac_address: &str,
         target_mac_address: &str,
     ) -> Result<()> {
-        let opcode = [opcodes::SMART_ROUTING, 0x00];
-        let mut buffer = Vec::with_capacity(86);
-        let target_mac_bytes: Vec<u8> = target_mac_address
-            .split(':')
-            .map(|s| u8::from_str_radix(s, 16).unwrap())
-            .collect();
-        buffer.extend_from_slice(&target_mac_bytes.iter().rev().cloned().collect::<Vec<u8>>());
-        buffer.extend_from_slice(&[0x4E, 0x00]);
-        buffer.extend_from_slice(&[0x01, 0xE5]);
-        buffer.extend_from_slice(&[0x48, 0x69]);
-        buffer.extend_from_slice(b"idleTime");
-        buffer.extend_from_slice(&[0x08, 0x47]);
-        buffer.extend_from_slice(b"newTipi");
-        buffer.extend_from_slice(&[0x01, 0x49]);
-        buffer.extend_from_slice(b"btAddress");
-        buffer.push(0x51);
-        buffer.extend_from_slice(self_mac_address.as_bytes());
-        buffer.push(0x46);
-        buffer.extend_from_slice(b"btName");
-        buffer.push(0x43);
-        buffer.extend_from_slice(b"Mac");
-        buffer.push(0x50);
-        buffer.extend_from_slice(b"nearbyAudioScore");
-        buffer.push(0x0E);
-
-        let packet = [opcode.as_slice(), buffer.as_slice()].concat();
-        self.send_data_packet(&packet).await
+        self.send_aacp_packet(&AacpPacket::add_tipi_device(
+            self_mac_address,
+            target_mac_address,
+        ))
+        .await
     }
 
-    pub async fn send_some_packet(&self) -> Result<()> {
-        self.send_data_packet(&[0x29, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])
+    pub async fn start_head_tracking(&self) -> Result<()> {
+        self.send_aacp_packet(&AacpPacket::head_tracking_start())
+            .await?;
+        self.state.lock().await.head_tracking = true;
+        info!("Head tracking started");
+        Ok(())
+    }
+
+    pub async fn stop_head_tracking(&self) -> Result<()> {
+        self.send_aacp_packet(&AacpPacket::head_tracking_stop())
+            .await?;
+        self.state.lock().await.head_tracking = false;
+        info!("Head tracking stopped");
+        Ok(())
+    }
+
+    pub async fn send_headphone_accommodation(
+        &self,
+        accommodation: HeadphoneAccommodation,
+    ) -> Result<()> {
+        self.send_aacp_packet(&AacpPacket::HeadphoneAccommodation(accommodation))
+            .await?;
+        self.state.lock().await.headphone_accommodation = Some(accommodation);
+        Ok(())
+    }
+
+    /// Sets which stem presses the AirPods report to us instead of handling them, a mask of
+    /// StemPressType::stem_config_bit values.
+    pub async fn set_stem_config(&self, stem_config: u8) -> Result<()> {
+        self.state.lock().await.stem_config = stem_config;
+        self.send_control_command(
+            ControlCommandIdentifiers::StemConfig,
+            &[stem_config, 0, 0, 0],
+        )
+        .await
+    }
+
+    /// Switches to the listening mode after the current one, out of those enabled in
+    /// ListeningModeConfigs.
+    pub async fn cycle_listening_mode(&self) -> Result<()> {
+        let (current, cycle) = {
+            let state = self.state.lock().await;
+            let value_of = |identifier| {
+                state
+                    .control_command_status_list
+                    .iter()
+                    .find(|s| s.identifier == identifier)
+                    .and_then(|s| s.value.first().copied())
+            };
+            (
+                value_of(ControlCommandIdentifiers::ListeningMode),
+                value_of(ControlCommandIdentifiers::ListeningModeConfigs)
+                    .map(ListeningModeCycle::from_bits),
+            )
+        };
+        let cycle = cycle
+            .filter(|c| c.is_valid())
+            .unwrap_or(ListeningModeCycle::NOISE_CANCELLATION | ListeningModeCycle::TRANSPARENCY);
+        let next = cycle.next_mode(current.unwrap_or(0x01));
+        self.send_control_command(ControlCommandIdentifiers::ListeningMode, &[next])
             .await
     }
+
+    pub async fn send_auto_anc_strength(&self, strength: u8) -> Result<()> {
+        self.send_control_command(
+            ControlCommandIdentifiers::AutoAncStrength,
+            &[strength.min(AUTO_ANC_STRENGTH_MAX)],
+        )
+        .await
+    }
+
+    pub async fn send_listening_mode_cycle(&self, cycle: ListeningModeCycle) -> Result<()> {
+        if !cycle.is_valid() {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Listening mode cycle needs at least {} modes, got {:#04x}",
+                    ListeningModeCycle::MIN_MODES,
+                    cycle.bits()
+                ),
+            )));
+        }
+        self.send_control_command(
+            ControlCommandIdentifiers::ListeningModeConfigs,
+            &[cycle.bits()],
+        )
+        .await
+    }
+
+    /// Sends the current Headphone Accommodation again, which the AirPods expect after the
+    /// transparency settings were changed.
+    pub async fn resend_headphone_accommodation(&self) -> Result<()> {
+        let accommodation = self.state.lock().await.headphone_accommodation;
+        match accommodation {
+            Some(accommodation) => self.send_headphone_accommodation(accommodation).await,
+            None => {
+                warn!("No Headphone Accommodation received yet, not sending it");
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn send_some_packet(&self) -> Result<()> {
+        self.send_aacp_packet(&AacpPacket::Unknown {
+            opcode: 0x29,
+            data: vec![0xFF; 8],
+        })
+        .await
+    }
 }
 
-async fn recv_thread(manager: AACPManager, sp: Arc<SeqPacket>) {
+async fn recv_thread<T: PacketTransport>(manager: AACPManager, sp: Arc<T>) {
     let mut buf = vec![0u8; 1024];
     loop {
         match sp.recv(&mut buf).await {
@@ -1231,14 +1094,16 @@ async fn recv_thread(manager: AACPManager, sp: Arc<SeqPacket>) {
                 let data = &buf[..n];
                 debug!("Received {} bytes: {}", n, hex::encode(data));
                 manager.receive_packet(data).await;
+                device_state_changed();
             }
             Err(e) => {
                 error!("Read error: {}", e);
                 debug!(
-                    "We have probably disconnected, clearing state variables (owns=false, connected_devices=empty, control_command_status_list=empty)."
+                    "We have probably disconnected, clearing state variables (owns=false, connected_devices=empty, control_command_status_list=empty, head_tracking=false)."
                 );
                 let mut state = manager.state.lock().await;
                 state.owns = false;
+                state.head_tracking = false;
                 state.connected_devices.clear();
                 state.control_command_status_list.clear();
                 break;
@@ -1247,9 +1112,11 @@ async fn recv_thread(manager: AACPManager, sp: Arc<SeqPacket>) {
     }
     let mut state = manager.state.lock().await;
     state.sender = None;
+    drop(state);
+    manager.closed.notify_one();
 }
 
-async fn send_thread(mut rx: mpsc::Receiver<Vec<u8>>, sp: Arc<SeqPacket>) {
+async fn send_thread<T: PacketTransport>(mut rx: mpsc::Receiver<Vec<u8>>, sp: Arc<T>) {
     while let Some(data) = rx.recv().await {
         if let Err(e) = sp.send(&data).await {
             error!("Failed to send data: {}", e);
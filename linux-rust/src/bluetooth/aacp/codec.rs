@@ -0,0 +1,963 @@
+use crate::bluetooth::aacp::{
+    AirPodsLEKeys, AudioSource, AudioSourceType, BatteryComponent, BatteryInfo, BatteryStatus,
+    ConnectedDevice, ControlCommandIdentifiers, ControlCommandStatus, EarDetectionStatus,
+    HEADPHONE_ACCOMMODATION_BANDS, HeadphoneAccommodation, ProximityKeyType, StemPressBudType,
+    StemPressType, opcodes,
+};
+use crate::devices::airpods::AirPodsInformation;
+use log::error;
+
+pub const HEADER_BYTES: [u8; 4] = [0x04, 0x00, 0x04, 0x00];
+const HANDSHAKE_BYTES: [u8; 16] = [
+    0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+// bytes between the opcode and the first string of an information packet, meaning unknown
+const INFORMATION_PREFIX: [u8; 5] = [0x02, 0xD5, 0x00, 0x04, 0x00];
+const HEAD_TRACKING_START: [u8; 22] = [
+    0x00, 0x00, 0x10, 0x00, 0x10, 0x00, 0x08, 0xA1, 0x02, 0x42, 0x0B, 0x08, 0x0E, 0x10, 0x02, 0x1A,
+    0x05, 0x01, 0x40, 0x9C, 0x00, 0x00,
+];
+const HEAD_TRACKING_STOP: [u8; 23] = [
+    0x00, 0x00, 0x10, 0x00, 0x11, 0x00, 0x08, 0x7E, 0x10, 0x02, 0x42, 0x0B, 0x08, 0x4E, 0x10, 0x02,
+    0x1A, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00,
+];
+// sensor data packets start with `04 00 04 00 17 00 00 00 10 00 45 00` (or `44 00`)
+const HEAD_TRACKING_SAMPLE_PREFIX: [u8; 4] = [0x00, 0x00, 0x10, 0x00];
+const HEAD_TRACKING_SAMPLE_MIN_LEN: usize = 80;
+// offsets into the full packet, including the header
+const HEAD_TRACKING_ORIENTATION_OFFSET: usize = 43;
+const HEAD_TRACKING_ACCEL_H_OFFSET: usize = 51;
+const HEAD_TRACKING_ACCEL_V_OFFSET: usize = 53;
+// `84 00 02 02`, followed by the phone and media flags
+const HEADPHONE_ACCOMMODATION_PREFIX: [u8; 4] = [0x84, 0x00, 0x02, 0x02];
+// the EQ is repeated this many times, only the first copy is read
+const HEADPHONE_ACCOMMODATION_EQ_COPIES: usize = 4;
+const HEADPHONE_ACCOMMODATION_LEN: usize = HEADER_BYTES.len()
+    + 2
+    + HEADPHONE_ACCOMMODATION_PREFIX.len()
+    + 2
+    + HEADPHONE_ACCOMMODATION_EQ_COPIES * HEADPHONE_ACCOMMODATION_BANDS * 4;
+
+/// A single AACP packet, decoded from or encoded to the bytes sent over L2CAP.
+///
+/// `decode` takes the full packet including the `04 00 04 00` header and `encode` produces it,
+/// so `AacpPacket::decode(&packet.encode())` gives back the same packet, except for:
+///
+/// - control command values, which are read without their trailing zero bytes as every value
+///   is padded to four bytes (a value of only zeros reads as `[0]`)
+/// - the LE keys of `Information`, which come with the proximity keys and not in that packet,
+///   so they are never encoded and read back empty
+/// - the `type` of connected devices, which isn't sent either, and MAC addresses, which are
+///   read back in upper case
+#[derive(Debug, Clone, PartialEq)]
+pub enum AacpPacket {
+    Handshake,
+    SetFeatureFlags([u8; 8]),
+    RequestNotifications([u8; 4]),
+    BatteryInfo(Vec<BatteryInfo>),
+    ControlCommand(ControlCommandStatus),
+    EarDetection(EarDetectionStatus, EarDetectionStatus), // primary, secondary
+    ConversationalAwareness(u8),
+    Information(AirPodsInformation),
+    Rename(String),
+    ProximityKeysRequest(Vec<ProximityKeyType>),
+    ProximityKeysResponse(Vec<(u8, Vec<u8>)>),
+    StemPress(StemPressType, StemPressBudType),
+    AudioSource(AudioSource),
+    ConnectedDevices(Vec<ConnectedDevice>),
+    SmartRouting(Vec<u8>),
+    SmartRoutingResponse(Vec<u8>),
+    HeadTracking(Vec<u8>),
+    HeadTrackingSample {
+        orientation: [i16; 3],
+        accel_h: i16,
+        accel_v: i16,
+    },
+    HeadphoneAccommodation(HeadphoneAccommodation),
+    Unknown {
+        opcode: u8,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    MissingHeader,
+    TooShort {
+        opcode: u8,
+        len: usize,
+    },
+    LengthMismatch {
+        opcode: u8,
+        expected: usize,
+        actual: usize,
+    },
+    UnknownControlCommand(u8),
+    UnknownValue {
+        opcode: u8,
+        value: u8,
+    },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::MissingHeader => write!(f, "packet does not start with the AACP header"),
+            DecodeError::TooShort { opcode, len } => {
+                write!(
+                    f,
+                    "packet with opcode {:#04x} too short ({} bytes)",
+                    opcode, len
+                )
+            }
+            DecodeError::LengthMismatch {
+                opcode,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "packet with opcode {:#04x} has length {}, expected {}",
+                opcode, actual, expected
+            ),
+            DecodeError::UnknownControlCommand(id) => {
+                write!(f, "unknown control command identifier {:#04x}", id)
+            }
+            DecodeError::UnknownValue { opcode, value } => write!(
+                f,
+                "unknown value {:#04x} in packet with opcode {:#04x}",
+                value, opcode
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl AacpPacket {
+    pub fn decode(packet: &[u8]) -> Result<AacpPacket, DecodeError> {
+        if packet.starts_with(&HANDSHAKE_BYTES[..8]) {
+            return Ok(AacpPacket::Handshake);
+        }
+        if !packet.starts_with(&HEADER_BYTES) {
+            return Err(DecodeError::MissingHeader);
+        }
+        if packet.len() < 6 {
+            return Err(DecodeError::TooShort {
+                opcode: packet.get(4).copied().unwrap_or(0),
+                len: packet.len(),
+            });
+        }
+
+        // payload[0] is the opcode, payload[1] is always 0x00
+        let payload = &packet[4..];
+        let opcode = payload[0];
+        let too_short = |min: usize| -> Result<(), DecodeError> {
+            if payload.len() < min {
+                Err(DecodeError::TooShort {
+                    opcode,
+                    len: packet.len(),
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        match opcode {
+            opcodes::SET_FEATURE_FLAGS => {
+                too_short(10)?;
+                Ok(AacpPacket::SetFeatureFlags(
+                    payload[2..10].try_into().unwrap(),
+                ))
+            }
+            opcodes::REQUEST_NOTIFICATIONS => {
+                too_short(6)?;
+                Ok(AacpPacket::RequestNotifications(
+                    payload[2..6].try_into().unwrap(),
+                ))
+            }
+            opcodes::BATTERY_INFO => {
+                too_short(3)?;
+                let count = payload[2] as usize;
+                if payload.len() < 3 + count * 5 {
+                    return Err(DecodeError::LengthMismatch {
+                        opcode,
+                        expected: 4 + 3 + count * 5,
+                        actual: packet.len(),
+                    });
+                }
+                let mut batteries = Vec::with_capacity(count);
+                for i in 0..count {
+                    let base_index = 3 + i * 5;
+                    let Some(component) = BatteryComponent::from_u8(payload[base_index]) else {
+                        error!("Unknown battery component: {:#04x}", payload[base_index]);
+                        continue;
+                    };
+                    let Some(status) = BatteryStatus::from_u8(payload[base_index + 3]) else {
+                        error!("Unknown battery status: {:#04x}", payload[base_index + 3]);
+                        continue;
+                    };
+                    batteries.push(BatteryInfo {
+                        component,
+                        level: payload[base_index + 2],
+                        status,
+                    });
+                }
+                Ok(AacpPacket::BatteryInfo(batteries))
+            }
+            opcodes::CONTROL_COMMAND => {
+                too_short(7)?;
+                let identifier_byte = payload[2];
+                let value_bytes = &payload[3..7];
+                let value = match value_bytes.iter().rposition(|&b| b != 0) {
+                    Some(i) => value_bytes[..=i].to_vec(),
+                    None => vec![0],
+                };
+                let identifier = ControlCommandIdentifiers::from_u8(identifier_byte)
+                    .ok_or(DecodeError::UnknownControlCommand(identifier_byte))?;
+                Ok(AacpPacket::ControlCommand(ControlCommandStatus {
+                    identifier,
+                    value,
+                }))
+            }
+            opcodes::EAR_DETECTION => {
+                too_short(4)?;
+                let status = |byte: u8| {
+                    EarDetectionStatus::from_u8(byte).unwrap_or_else(|| {
+                        error!("Unknown ear detection status: {:#04x}", byte);
+                        EarDetectionStatus::OutOfEar
+                    })
+                };
+                Ok(AacpPacket::EarDetection(
+                    status(payload[2]),
+                    status(payload[3]),
+                ))
+            }
+            opcodes::CONVERSATION_AWARENESS => {
+                if packet.len() != 10 {
+                    return Err(DecodeError::LengthMismatch {
+                        opcode,
+                        expected: 10,
+                        actual: packet.len(),
+                    });
+                }
+                Ok(AacpPacket::ConversationalAwareness(payload[5]))
+            }
+            opcodes::INFORMATION => {
+                too_short(6)?;
+                // the strings follow a length-like byte between nulls, each string ends in a
+                // single null so empty ones keep their place
+                let data = &payload[4..];
+                let start = data.iter().position(|&b| b != 0x00).unwrap_or(data.len());
+                let strings_start = data[start..]
+                    .iter()
+                    .position(|&b| b == 0x00)
+                    .map_or(data.len(), |i| start + i + 1);
+                let mut strings = data[strings_start..]
+                    .split(|&b| b == 0x00)
+                    .map(|s| String::from_utf8_lossy(s).to_string());
+                let mut field = || strings.next().unwrap_or_default();
+                Ok(AacpPacket::Information(AirPodsInformation {
+                    name: field(),
+                    model_number: field(),
+                    manufacturer: field(),
+                    serial_number: field(),
+                    version1: field(),
+                    version2: field(),
+                    hardware_revision: field(),
+                    updater_identifier: field(),
+                    left_serial_number: field(),
+                    right_serial_number: field(),
+                    version3: field(),
+                    le_keys: AirPodsLEKeys {
+                        irk: "".to_string(),
+                        enc_key: "".to_string(),
+                    },
+                }))
+            }
+            opcodes::RENAME => {
+                too_short(4)?;
+                let size = payload[2] as usize;
+                if payload.len() < 4 + size {
+                    return Err(DecodeError::LengthMismatch {
+                        opcode,
+                        expected: 4 + 4 + size,
+                        actual: packet.len(),
+                    });
+                }
+                Ok(AacpPacket::Rename(
+                    String::from_utf8_lossy(&payload[4..4 + size]).to_string(),
+                ))
+            }
+            opcodes::PROXIMITY_KEYS_REQ => {
+                too_short(3)?;
+                let mask = payload[2];
+                let key_types = [ProximityKeyType::Irk, ProximityKeyType::EncKey]
+                    .into_iter()
+                    .filter(|kt| mask & (*kt as u8) != 0)
+                    .collect();
+                Ok(AacpPacket::ProximityKeysRequest(key_types))
+            }
+            opcodes::PROXIMITY_KEYS_RSP => {
+                too_short(4)?;
+                let key_count = payload[2] as usize;
+                let mut offset = 3;
+                let mut keys = Vec::with_capacity(key_count);
+                for _ in 0..key_count {
+                    if offset + 3 >= payload.len() {
+                        return Err(DecodeError::TooShort {
+                            opcode,
+                            len: packet.len(),
+                        });
+                    }
+                    let key_type = payload[offset];
+                    let key_length = payload[offset + 2] as usize;
+                    offset += 4;
+                    if offset + key_length > payload.len() {
+                        return Err(DecodeError::TooShort {
+                            opcode,
+                            len: packet.len(),
+                        });
+                    }
+                    keys.push((key_type, payload[offset..offset + key_length].to_vec()));
+                    offset += key_length;
+                }
+                Ok(AacpPacket::ProximityKeysResponse(keys))
+            }
+            opcodes::STEM_PRESS => {
+                too_short(4)?;
+                let press_type =
+                    StemPressType::from_u8(payload[2]).ok_or(DecodeError::UnknownValue {
+                        opcode,
+                        value: payload[2],
+                    })?;
+                let bud_type =
+                    StemPressBudType::from_u8(payload[3]).ok_or(DecodeError::UnknownValue {
+                        opcode,
+                        value: payload[3],
+                    })?;
+                Ok(AacpPacket::StemPress(press_type, bud_type))
+            }
+            opcodes::AUDIO_SOURCE => {
+                too_short(9)?;
+                let mac = format!(
+                    "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+                    payload[7], payload[6], payload[5], payload[4], payload[3], payload[2]
+                );
+                let r#type = AudioSourceType::from_u8(payload[8]).unwrap_or(AudioSourceType::None);
+                Ok(AacpPacket::AudioSource(AudioSource { mac, r#type }))
+            }
+            opcodes::CONNECTED_DEVICES => {
+                too_short(3)?;
+                let count = payload[2] as usize;
+                if payload.len() < 5 + count * 8 {
+                    return Err(DecodeError::LengthMismatch {
+                        opcode,
+                        expected: 4 + 5 + count * 8,
+                        actual: packet.len(),
+                    });
+                }
+                let devices = (0..count)
+                    .map(|i| {
+                        let base = 5 + i * 8;
+                        ConnectedDevice {
+                            mac: format!(
+                                "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+                                payload[base],
+                                payload[base + 1],
+                                payload[base + 2],
+                                payload[base + 3],
+                                payload[base + 4],
+                                payload[base + 5]
+                            ),
+                            info1: payload[base + 6],
+                            info2: payload[base + 7],
+                            r#type: None,
+                        }
+                    })
+                    .collect();
+                Ok(AacpPacket::ConnectedDevices(devices))
+            }
+            opcodes::SMART_ROUTING => Ok(AacpPacket::SmartRouting(payload[2..].to_vec())),
+            opcodes::SMART_ROUTING_RESP => {
+                Ok(AacpPacket::SmartRoutingResponse(payload[2..].to_vec()))
+            }
+            opcodes::HEADTRACKING => {
+                let is_sample = packet.len() >= HEAD_TRACKING_SAMPLE_MIN_LEN
+                    && payload[2..6] == HEAD_TRACKING_SAMPLE_PREFIX
+                    && matches!(payload[6..8], [0x44, 0x00] | [0x45, 0x00]);
+                if !is_sample {
+                    return Ok(AacpPacket::HeadTracking(payload[2..].to_vec()));
+                }
+                let read_i16 =
+                    |offset: usize| i16::from_le_bytes([packet[offset], packet[offset + 1]]);
+                Ok(AacpPacket::HeadTrackingSample {
+                    orientation: [
+                        read_i16(HEAD_TRACKING_ORIENTATION_OFFSET),
+                        read_i16(HEAD_TRACKING_ORIENTATION_OFFSET + 2),
+                        read_i16(HEAD_TRACKING_ORIENTATION_OFFSET + 4),
+                    ],
+                    accel_h: read_i16(HEAD_TRACKING_ACCEL_H_OFFSET),
+                    accel_v: read_i16(HEAD_TRACKING_ACCEL_V_OFFSET),
+                })
+            }
+            opcodes::EQ_DATA => {
+                if packet.len() != HEADPHONE_ACCOMMODATION_LEN {
+                    return Err(DecodeError::LengthMismatch {
+                        opcode,
+                        expected: HEADPHONE_ACCOMMODATION_LEN,
+                        actual: packet.len(),
+                    });
+                }
+                if payload[2..6] != HEADPHONE_ACCOMMODATION_PREFIX {
+                    return Err(DecodeError::UnknownValue {
+                        opcode,
+                        value: payload[2],
+                    });
+                }
+                // 0x01 is enabled, 0x02 disabled
+                let phone = payload[6] == 0x01;
+                let media = payload[7] == 0x01;
+                let mut eq = [0.0; HEADPHONE_ACCOMMODATION_BANDS];
+                for (band, bytes) in eq.iter_mut().zip(payload[8..].chunks_exact(4)) {
+                    *band = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                }
+                Ok(AacpPacket::HeadphoneAccommodation(HeadphoneAccommodation {
+                    phone,
+                    media,
+                    eq,
+                }))
+            }
+            _ => Ok(AacpPacket::Unknown {
+                opcode,
+                data: payload[2..].to_vec(),
+            }),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (opcode, data): (u8, Vec<u8>) = match self {
+            AacpPacket::Handshake => return HANDSHAKE_BYTES.to_vec(),
+            AacpPacket::SetFeatureFlags(flags) => (opcodes::SET_FEATURE_FLAGS, flags.to_vec()),
+            AacpPacket::RequestNotifications(mask) => {
+                (opcodes::REQUEST_NOTIFICATIONS, mask.to_vec())
+            }
+            AacpPacket::BatteryInfo(batteries) => {
+                let mut data = vec![batteries.len() as u8];
+                for b in batteries {
+                    data.extend_from_slice(&[
+                        b.component as u8,
+                        0x01,
+                        b.level,
+                        b.status as u8,
+                        0x01,
+                    ]);
+                }
+                (opcodes::BATTERY_INFO, data)
+            }
+            AacpPacket::ControlCommand(status) => {
+                let mut data = vec![status.identifier as u8];
+                for i in 0..4 {
+                    data.push(status.value.get(i).copied().unwrap_or(0));
+                }
+                (opcodes::CONTROL_COMMAND, data)
+            }
+            AacpPacket::EarDetection(primary, secondary) => (
+                opcodes::EAR_DETECTION,
+                vec![*primary as u8, *secondary as u8],
+            ),
+            AacpPacket::ConversationalAwareness(level) => (
+                opcodes::CONVERSATION_AWARENESS,
+                vec![0x02, 0x00, 0x01, *level],
+            ),
+            AacpPacket::Information(info) => {
+                let mut data = INFORMATION_PREFIX.to_vec();
+                for s in [
+                    &info.name,
+                    &info.model_number,
+                    &info.manufacturer,
+                    &info.serial_number,
+                    &info.version1,
+                    &info.version2,
+                    &info.hardware_revision,
+                    &info.updater_identifier,
+                    &info.left_serial_number,
+                    &info.right_serial_number,
+                    &info.version3,
+                ] {
+                    data.extend_from_slice(s.as_bytes());
+                    data.push(0x00);
+                }
+                (opcodes::INFORMATION, data)
+            }
+            AacpPacket::Rename(name) => {
+                let name_bytes = name.as_bytes();
+                let mut data = Vec::with_capacity(2 + name_bytes.len());
+                data.push(name_bytes.len() as u8);
+                data.push(0x00);
+                data.extend_from_slice(name_bytes);
+                (opcodes::RENAME, data)
+            }
+            AacpPacket::ProximityKeysRequest(key_types) => (
+                opcodes::PROXIMITY_KEYS_REQ,
+                vec![
+                    key_types.iter().fold(0u8, |acc, kt| acc | (*kt as u8)),
+                    0x00,
+                ],
+            ),
+            AacpPacket::ProximityKeysResponse(keys) => {
+                let mut data = vec![keys.len() as u8];
+                for (key_type, key_data) in keys {
+                    data.extend_from_slice(&[*key_type, 0x00, key_data.len() as u8, 0x00]);
+                    data.extend_from_slice(key_data);
+                }
+                (opcodes::PROXIMITY_KEYS_RSP, data)
+            }
+            AacpPacket::StemPress(press_type, bud_type) => (
+                opcodes::STEM_PRESS,
+                vec![*press_type as u8, *bud_type as u8],
+            ),
+            AacpPacket::AudioSource(source) => {
+                let mut data = mac_to_bytes(&source.mac).to_vec();
+                data.reverse();
+                data.push(source.r#type as u8);
+                (opcodes::AUDIO_SOURCE, data)
+            }
+            AacpPacket::ConnectedDevices(devices) => {
+                let mut data = vec![devices.len() as u8, 0x00, 0x00];
+                for d in devices {
+                    data.extend_from_slice(&mac_to_bytes(&d.mac));
+                    data.push(d.info1);
+                    data.push(d.info2);
+                }
+                (opcodes::CONNECTED_DEVICES, data)
+            }
+            AacpPacket::SmartRouting(data) => (opcodes::SMART_ROUTING, data.clone()),
+            AacpPacket::SmartRoutingResponse(data) => (opcodes::SMART_ROUTING_RESP, data.clone()),
+            AacpPacket::HeadTracking(data) => (opcodes::HEADTRACKING, data.clone()),
+            AacpPacket::HeadTrackingSample {
+                orientation,
+                accel_h,
+                accel_v,
+            } => {
+                let mut packet = vec![0u8; HEAD_TRACKING_SAMPLE_MIN_LEN];
+                packet[..4].copy_from_slice(&HEADER_BYTES);
+                packet[4] = opcodes::HEADTRACKING;
+                packet[6..10].copy_from_slice(&HEAD_TRACKING_SAMPLE_PREFIX);
+                packet[10] = 0x45;
+                for (i, value) in orientation.iter().enumerate() {
+                    let offset = HEAD_TRACKING_ORIENTATION_OFFSET + i * 2;
+                    packet[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+                }
+                packet[HEAD_TRACKING_ACCEL_H_OFFSET..HEAD_TRACKING_ACCEL_H_OFFSET + 2]
+                    .copy_from_slice(&accel_h.to_le_bytes());
+                packet[HEAD_TRACKING_ACCEL_V_OFFSET..HEAD_TRACKING_ACCEL_V_OFFSET + 2]
+                    .copy_from_slice(&accel_v.to_le_bytes());
+                return packet;
+            }
+            AacpPacket::HeadphoneAccommodation(accommodation) => {
+                let flag = |enabled: bool| if enabled { 0x01 } else { 0x02 };
+                let mut data = HEADPHONE_ACCOMMODATION_PREFIX.to_vec();
+                data.push(flag(accommodation.phone));
+                data.push(flag(accommodation.media));
+                for _ in 0..HEADPHONE_ACCOMMODATION_EQ_COPIES {
+                    for band in accommodation.eq {
+                        data.extend_from_slice(&band.to_le_bytes());
+                    }
+                }
+                (opcodes::EQ_DATA, data)
+            }
+            AacpPacket::Unknown { opcode, data } => (*opcode, data.clone()),
+        };
+        let mut packet = Vec::with_capacity(HEADER_BYTES.len() + 2 + data.len());
+        packet.extend_from_slice(&HEADER_BYTES);
+        packet.push(opcode);
+        packet.push(0x00);
+        packet.extend_from_slice(&data);
+        packet
+    }
+
+    pub fn head_tracking_start() -> Self {
+        AacpPacket::HeadTracking(HEAD_TRACKING_START.to_vec())
+    }
+
+    pub fn head_tracking_stop() -> Self {
+        AacpPacket::HeadTracking(HEAD_TRACKING_STOP.to_vec())
+    }
+
+    pub fn media_information_new_device(self_mac_address: &str, target_mac_address: &str) -> Self {
+        let mut buffer = Vec::with_capacity(112);
+        buffer.extend_from_slice(&reversed_mac(target_mac_address));
+        buffer.extend_from_slice(&[0x68, 0x00]);
+        buffer.extend_from_slice(&[0x01, 0xE5, 0x4A]);
+        buffer.extend_from_slice(b"playingApp");
+        buffer.push(0x42);
+        buffer.extend_from_slice(b"NA");
+        buffer.push(0x52);
+        buffer.extend_from_slice(b"hostStreamingState");
+        buffer.push(0x42);
+        buffer.extend_from_slice(b"NO");
+        buffer.push(0x49);
+        buffer.extend_from_slice(b"btAddress");
+        buffer.push(0x51);
+        buffer.extend_from_slice(self_mac_address.as_bytes());
+        buffer.push(0x46);
+        buffer.extend_from_slice(b"btName");
+        buffer.push(0x43);
+        buffer.extend_from_slice(b"Mac");
+        buffer.push(0x58);
+        buffer.extend_from_slice(b"otherDevice");
+        buffer.extend_from_slice(b"AudioCategory");
+        buffer.extend_from_slice(&[0x30, 0x64]);
+        AacpPacket::SmartRouting(buffer)
+    }
+
+    pub fn hijack_request(target_mac_address: &str) -> Self {
+        let mut buffer = Vec::with_capacity(106);
+        buffer.extend_from_slice(&reversed_mac(target_mac_address));
+        buffer.extend_from_slice(&[0x62, 0x00]);
+        buffer.extend_from_slice(&[0x01, 0xE5]);
+        buffer.push(0x4A);
+        buffer.extend_from_slice(b"localscore");
+        buffer.extend_from_slice(&[0x30, 0x64]);
+        buffer.push(0x46);
+        buffer.extend_from_slice(b"reason");
+        buffer.push(0x48);
+        buffer.extend_from_slice(b"Hijackv2");
+        buffer.push(0x51);
+        buffer.extend_from_slice(b"audioRoutingScore");
+        buffer.extend_from_slice(&[0x31, 0x2D, 0x01, 0x5F]);
+        buffer.extend_from_slice(b"audioRoutingSetOwnershipToFalse");
+        buffer.push(0x01);
+        buffer.push(0x4B);
+        buffer.extend_from_slice(b"remotescore");
+        buffer.push(0xA5);
+        buffer.resize(buffer.len().max(106), 0x00);
+        AacpPacket::SmartRouting(buffer)
+    }
+
+    pub fn media_information(
+        self_mac_address: &str,
+        target_mac_address: &str,
+        streaming_state: bool,
+    ) -> Self {
+        let mut buffer = Vec::with_capacity(138);
+        buffer.extend_from_slice(&reversed_mac(target_mac_address));
+        buffer.extend_from_slice(&[0x82, 0x00]);
+        buffer.extend_from_slice(&[0x01, 0xE5, 0x4A]);
+        buffer.extend_from_slice(b"PlayingApp");
+        buffer.push(0x56);
+        buffer.extend_from_slice(b"com.google.ios.youtube");
+        buffer.push(0x52);
+        buffer.extend_from_slice(b"HostStreamingState");
+        buffer.push(0x42);
+        buffer.extend_from_slice(if streaming_state { b"YES" } else { b"NO" });
+        buffer.push(0x49);
+        buffer.extend_from_slice(b"btAddress");
+        buffer.push(0x51);
+        buffer.extend_from_slice(self_mac_address.as_bytes());
+        buffer.extend_from_slice(b"btName");
+        buffer.push(0x43);
+        buffer.extend_from_slice(b"Mac");
+        buffer.push(0x58);
+        buffer.extend_from_slice(b"otherDevice");
+        buffer.extend_from_slice(b"AudioCategory");
+        buffer.extend_from_slice(&[0x31, 0x2D, 0x01]);
+        buffer.resize(buffer.len().max(138), 0x00);
+        AacpPacket::SmartRouting(buffer)
+    }
+
+    pub fn smart_routing_show_ui(target_mac_address: &str) -> Self {
+        let mut buffer = Vec::with_capacity(134);
+        buffer.extend_from_slice(&reversed_mac(target_mac_address));
+        buffer.extend_from_slice(&[0x7E, 0x00]);
+        buffer.extend_from_slice(&[0x01, 0xE6, 0x5B]);
+        buffer.extend_from_slice(b"SmartRoutingKeyShowNearbyUI");
+        buffer.push(0x01);
+        buffer.push(0x4A);
+        buffer.extend_from_slice(b"localscore");
+        buffer.extend_from_slice(&[0x31, 0x2D]);
+        buffer.push(0x01);
+        buffer.push(0x46);
+        buffer.extend_from_slice(b"reasonHhijackv2");
+        buffer.push(0x51);
+        buffer.extend_from_slice(b"audioRoutingScore");
+        buffer.push(0xA2);
+        buffer.push(0x5F);
+        buffer.extend_from_slice(b"audioRoutingSetOwnershipToFalse");
+        buffer.push(0x01);
+        buffer.push(0x4B);
+        buffer.extend_from_slice(b"remotescore");
+        buffer.push(0xA2);
+        buffer.resize(buffer.len().max(134), 0x00);
+        AacpPacket::SmartRouting(buffer)
+    }
+
+    pub fn hijack_reversed(target_mac_address: &str) -> Self {
+        let mut buffer = Vec::with_capacity(97);
+        buffer.extend_from_slice(&reversed_mac(target_mac_address));
+        buffer.extend_from_slice(&[0x59, 0x00]);
+        buffer.extend_from_slice(&[0x01, 0xE3]);
+        buffer.push(0x5F);
+        buffer.extend_from_slice(b"audioRoutingSetOwnershipToFalse");
+        buffer.push(0x01);
+        buffer.push(0x59);
+        buffer.extend_from_slice(b"audioRoutingShowReverseUI");
+        buffer.push(0x01);
+        buffer.push(0x46);
+        buffer.extend_from_slice(b"reason");
+        buffer.push(0x53);
+        buffer.extend_from_slice(b"ReverseBannerTapped");
+        buffer.resize(buffer.len().max(97), 0x00);
+        AacpPacket::SmartRouting(buffer)
+    }
+
+    pub fn add_tipi_device(self_mac_address: &str, target_mac_address: &str) -> Self {
+        let mut buffer = Vec::with_capacity(86);
+        buffer.extend_from_slice(&reversed_mac(target_mac_address));
+        buffer.extend_from_slice(&[0x4E, 0x00]);
+        buffer.extend_from_slice(&[0x01, 0xE5]);
+        buffer.extend_from_slice(&[0x48, 0x69]);
+        buffer.extend_from_slice(b"idleTime");
+        buffer.extend_from_slice(&[0x08, 0x47]);
+        buffer.extend_from_slice(b"newTipi");
+        buffer.extend_from_slice(&[0x01, 0x49]);
+        buffer.extend_from_slice(b"btAddress");
+        buffer.push(0x51);
+        buffer.extend_from_slice(self_mac_address.as_bytes());
+        buffer.push(0x46);
+        buffer.extend_from_slice(b"btName");
+        buffer.push(0x43);
+        buffer.extend_from_slice(b"Mac");
+        buffer.push(0x50);
+        buffer.extend_from_slice(b"nearbyAudioScore");
+        buffer.push(0x0E);
+        AacpPacket::SmartRouting(buffer)
+    }
+}
+
+fn mac_to_bytes(mac: &str) -> [u8; 6] {
+    let mut bytes = [0u8; 6];
+    for (i, part) in mac.split(':').take(6).enumerate() {
+        bytes[i] = u8::from_str_radix(part, 16).unwrap_or(0);
+    }
+    bytes
+}
+
+fn reversed_mac(mac: &str) -> [u8; 6] {
+    let mut bytes = mac_to_bytes(mac);
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        hex::decode(hex.replace(' ', "")).unwrap()
+    }
+
+    /// Decodes an example packet and checks that it encodes back to the same bytes.
+    fn round_trip(example: &str) -> AacpPacket {
+        let packet = bytes(example);
+        let decoded = AacpPacket::decode(&packet).unwrap();
+        assert_eq!(decoded.encode(), packet);
+        decoded
+    }
+
+    // the examples below are the ones in `AAP Definitions.md`, placeholders filled in
+
+    #[test]
+    fn handshake() {
+        assert_eq!(
+            round_trip("00 00 04 00 01 00 02 00 00 00 00 00 00 00 00 00"),
+            AacpPacket::Handshake
+        );
+    }
+
+    #[test]
+    fn set_feature_flags() {
+        assert_eq!(
+            round_trip("04 00 04 00 4d 00 ff 00 00 00 00 00 00 00"),
+            AacpPacket::SetFeatureFlags([0xFF, 0, 0, 0, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn request_notifications() {
+        assert_eq!(
+            round_trip("04 00 04 00 0F 00 FF FF FE FF"),
+            AacpPacket::RequestNotifications([0xFF, 0xFF, 0xFE, 0xFF])
+        );
+        assert_eq!(
+            round_trip("04 00 04 00 0F 00 FF FF FF FF"),
+            AacpPacket::RequestNotifications([0xFF, 0xFF, 0xFF, 0xFF])
+        );
+    }
+
+    #[test]
+    fn battery_info() {
+        assert_eq!(
+            round_trip("04 00 04 00 04 00 03 02 01 64 02 01 04 01 63 01 01 08 01 11 02 01"),
+            AacpPacket::BatteryInfo(vec![
+                BatteryInfo {
+                    component: BatteryComponent::Right,
+                    level: 100,
+                    status: BatteryStatus::NotCharging,
+                },
+                BatteryInfo {
+                    component: BatteryComponent::Left,
+                    level: 99,
+                    status: BatteryStatus::Charging,
+                },
+                BatteryInfo {
+                    component: BatteryComponent::Case,
+                    level: 17,
+                    status: BatteryStatus::NotCharging,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn control_commands() {
+        for (example, identifier, value) in [
+            (
+                "04 00 04 00 09 00 0D 02 00 00 00",
+                ControlCommandIdentifiers::ListeningMode,
+                0x02,
+            ),
+            (
+                "04 00 04 00 09 00 28 01 00 00 00",
+                ControlCommandIdentifiers::ConversationDetectConfig,
+                0x01,
+            ),
+            (
+                "04 00 04 00 09 00 2E 32 00 00 00",
+                ControlCommandIdentifiers::AutoAncStrength,
+                0x32,
+            ),
+        ] {
+            assert_eq!(
+                round_trip(example),
+                AacpPacket::ControlCommand(ControlCommandStatus {
+                    identifier,
+                    value: vec![value],
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn control_command_values_lose_trailing_zeros() {
+        let packet = AacpPacket::ControlCommand(ControlCommandStatus {
+            identifier: ControlCommandIdentifiers::ListeningModeConfigs,
+            value: vec![0x0E, 0x00],
+        });
+        assert_eq!(
+            AacpPacket::decode(&packet.encode()),
+            Ok(AacpPacket::ControlCommand(ControlCommandStatus {
+                identifier: ControlCommandIdentifiers::ListeningModeConfigs,
+                value: vec![0x0E],
+            }))
+        );
+    }
+
+    #[test]
+    fn ear_detection() {
+        assert_eq!(
+            round_trip("04 00 04 00 06 00 00 01"),
+            AacpPacket::EarDetection(EarDetectionStatus::InEar, EarDetectionStatus::OutOfEar)
+        );
+    }
+
+    #[test]
+    fn conversational_awareness() {
+        assert_eq!(
+            round_trip("04 00 04 00 4B 00 02 00 01 03"),
+            AacpPacket::ConversationalAwareness(0x03)
+        );
+    }
+
+    #[test]
+    fn information() {
+        // the example ends in encrypted data that isn't decoded, so it can't encode back to
+        // the same bytes, but what was read has to survive a round trip
+        let packet = bytes(
+            "040004001d0002d5000400416972506f64732050726f004133303438004170706c6520496e632e00\
+             51584e524848595850360036312e313836383034303030323030303030302e323731330036312e31\
+             3836383034303030323030303030302e3237313300312e302e3000636f6d2e6170706c652e616363\
+             6573736f72792e757064617465722e6170702e3731004859394c5432454632364a59004833504c57\
+             48444a32364b3000363335373533360089312a6567a5400f84a3ca234947efd40b90d78436ae5946\
+             748d70273e66066a2589300035333935303630363400",
+        );
+        let AacpPacket::Information(info) = AacpPacket::decode(&packet).unwrap() else {
+            panic!("not an information packet");
+        };
+        assert_eq!(info.name, "AirPods Pro");
+        assert_eq!(info.model_number, "A3048");
+        assert_eq!(info.manufacturer, "Apple Inc.");
+        assert_eq!(info.serial_number, "QXNRHHYXP6");
+        assert_eq!(info.version1, "61.1868040002000000.2713");
+        assert_eq!(info.version2, "61.1868040002000000.2713");
+        assert_eq!(info.hardware_revision, "1.0.0");
+        assert_eq!(
+            info.updater_identifier,
+            "com.apple.accessory.updater.app.71"
+        );
+        assert_eq!(info.left_serial_number, "HY9LT2EF26JY");
+        assert_eq!(info.right_serial_number, "H3PLWHDJ26K0");
+        assert_eq!(info.version3, "6357536");
+
+        let decoded = AacpPacket::Information(info);
+        assert_eq!(AacpPacket::decode(&decoded.encode()), Ok(decoded));
+    }
+
+    #[test]
+    fn information_with_empty_fields() {
+        let packet = AacpPacket::Information(AirPodsInformation {
+            name: String::new(),
+            model_number: "A3048".to_string(),
+            manufacturer: String::new(),
+            serial_number: String::new(),
+            version1: "1".to_string(),
+            version2: String::new(),
+            hardware_revision: String::new(),
+            updater_identifier: String::new(),
+            left_serial_number: String::new(),
+            right_serial_number: "R".to_string(),
+            version3: String::new(),
+            le_keys: AirPodsLEKeys {
+                irk: String::new(),
+                enc_key: String::new(),
+            },
+        });
+        assert_eq!(AacpPacket::decode(&packet.encode()), Ok(packet));
+    }
+
+    #[test]
+    fn head_tracking() {
+        assert_eq!(
+            round_trip(
+                "04 00 04 00 17 00 00 00 10 00 10 00 08 A1 02 42 0B 08 0E 10 02 1A 05 01 40 9C 00 00"
+            ),
+            AacpPacket::head_tracking_start()
+        );
+        assert_eq!(
+            round_trip(
+                "04 00 04 00 17 00 00 00 10 00 11 00 08 7E 10 02 42 0B 08 4E 10 02 1A 05 01 00 00 00 00"
+            ),
+            AacpPacket::head_tracking_stop()
+        );
+    }
+
+    #[test]
+    fn headphone_accommodation() {
+        let packet = AacpPacket::HeadphoneAccommodation(HeadphoneAccommodation {
+            phone: true,
+            media: false,
+            eq: [10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0],
+        });
+        assert_eq!(AacpPacket::decode(&packet.encode()), Ok(packet));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::utils::load_app_setting;
+use bluer::{Adapter, Address, Session};
+use futures::StreamExt;
+use log::{debug, info};
+use std::sync::{OnceLock, RwLock};
+use tokio::time::{Duration, sleep, timeout};
+
+pub const ADAPTER_SETTING_KEY: &str = "adapter";
+
+// power and rfkill changes aren't watched, so they are polled this often
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// the adapter asked for with --adapter or in the settings, None for the default one
+static PREFERRED_ADAPTER: OnceLock<Option<String>> = OnceLock::new();
+// the adapter devices are handled through, while it is usable
+static ACTIVE_ADAPTER: RwLock<Option<ActiveAdapter>> = RwLock::new(None);
+
+#[derive(Debug, Clone)]
+pub struct ActiveAdapter {
+    pub name: String,
+    pub address: Address,
+}
+
+/// Picks the adapter to use, `--adapter` wins over the setting. Without either the default
+/// adapter is used.
+pub fn init_preferred_adapter(from_args: Option<String>) {
+    let name = from_args
+        .or_else(|| load_app_setting::<String>(ADAPTER_SETTING_KEY))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    if let Some(name) = &name {
+        info!("Using Bluetooth adapter {} when available", name);
+    }
+    let _ = PREFERRED_ADAPTER.set(name);
+}
+
+fn preferred_adapter() -> Option<String> {
+    PREFERRED_ADAPTER.get().cloned().flatten()
+}
+
+pub fn active_adapter() -> Option<ActiveAdapter> {
+    ACTIVE_ADAPTER.read().ok().and_then(|active| active.clone())
+}
+
+pub fn set_active_adapter(active: Option<ActiveAdapter>) {
+    if let Ok(mut current) = ACTIVE_ADAPTER.write() {
+        *current = active;
+    }
+}
+
+/// The adapter devices are handled through: the active one, otherwise the preferred or default
+/// adapter.
+pub async fn open_adapter(session: &Session) -> bluer::Result<Adapter> {
+    match active_adapter()
+        .map(|active| active.name)
+        .or_else(preferred_adapter)
+    {
+        Some(name) => session.adapter(&name),
+        None => session.default_adapter().await,
+    }
+}
+
+/// Whether rfkill blocks the adapter, either soft (airplane mode, `rfkill block`) or hard.
+fn rfkill_blocked(adapter_name: &str) -> bool {
+    let Ok(entries) = std::fs::read_dir("/sys/class/rfkill") else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        let read = |file: &str| {
+            std::fs::read_to_string(path.join(file))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        read("type") == "bluetooth"
+            && read("name") == adapter_name
+            && (read("soft") == "1" || read("hard") == "1")
+    })
+}
+
+/// Why the adapter can't be used right now, None if it can.
+async fn unusable_reason(adapter: &Adapter) -> Option<&'static str> {
+    if rfkill_blocked(adapter.name()) {
+        return Some("blocked by rfkill");
+    }
+    match adapter.is_powered().await {
+        Ok(true) => None,
+        Ok(false) => Some("powered off"),
+        Err(_) => Some("gone"),
+    }
+}
+
+/// Waits until the adapter is there, powered and not blocked by rfkill. Powering it on is left
+/// to the user, a powered off adapter is only waited for.
+pub async fn wait_for_adapter(session: &Session) -> bluer::Result<Adapter> {
+    let mut events = session.events().await?;
+    let mut last_reason = String::new();
+    loop {
+        let reason = match open_adapter(session).await {
+            Ok(adapter) => match unusable_reason(&adapter).await {
+                None => return Ok(adapter),
+                Some(reason) => format!("Bluetooth adapter {} is {}", adapter.name(), reason),
+            },
+            Err(e) => format!("No Bluetooth adapter available: {}", e),
+        };
+        if reason != last_reason {
+            info!("{}, waiting", reason);
+            last_reason = reason;
+        }
+        // adapters appearing wake this up right away, power changes on the next poll
+        if let Ok(Some(event)) = timeout(POLL_INTERVAL, events.next()).await {
+            debug!("Bluetooth session event: {:?}", event);
+        }
+    }
+}
+
+/// Returns once the adapter can't be used anymore: unplugged, bluetoothd stopped, powered off
+/// or blocked by rfkill.
+pub async fn wait_for_adapter_loss(adapter: &Adapter) -> &'static str {
+    loop {
+        if let Some(reason) = unusable_reason(adapter).await {
+            return reason;
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
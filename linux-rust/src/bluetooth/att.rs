@@ -1,17 +1,16 @@
-use bluer::l2cap::{SeqPacket, Socket, SocketAddr};
-use bluer::{Address, AddressType, Error, Result};
+use crate::bluetooth::transport::{L2capTransport, PacketTransport};
+use bluer::{Address, Error, ErrorKind, Result};
 use hex;
 use log::{debug, error, info};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::{Mutex, Notify, mpsc};
 use tokio::task::JoinSet;
-use tokio::time::{Duration, Instant, sleep};
+use tokio::time::Duration;
 
 const PSM_ATT: u16 = 0x001F;
-const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
-const POLL_INTERVAL: Duration = Duration::from_millis(200);
 
+const OPCODE_ERROR_RESPONSE: u8 = 0x01;
 const OPCODE_READ_REQUEST: u8 = 0x0A;
 const OPCODE_WRITE_REQUEST: u8 = 0x12;
 const OPCODE_HANDLE_VALUE_NTF: u8 = 0x1B;
@@ -68,6 +67,8 @@ pub struct ATTManager {
     response_rx: Arc<Mutex<mpsc::UnboundedReceiver<Vec<u8>>>>,
     response_tx: mpsc::UnboundedSender<Vec<u8>>,
     tasks: Arc<Mutex<JoinSet<()>>>,
+    // signalled when the remote closes the channel
+    closed: Arc<Notify>,
 }
 
 impl ATTManager {
@@ -78,61 +79,39 @@ impl ATTManager {
             response_rx: Arc::new(Mutex::new(rx)),
             response_tx: tx,
             tasks: Arc::new(Mutex::new(JoinSet::new())),
+            closed: Arc::new(Notify::new()),
         }
     }
 
+    /// Waits until the channel is closed by the remote, fails or is disconnected.
+    pub async fn wait_closed(&self) {
+        self.closed.notified().await;
+    }
+
+    /// Stops the receive and send tasks and drops the notification listeners, which ends the
+    /// tasks forwarding them.
+    pub async fn disconnect(&self) {
+        self.tasks.lock().await.abort_all();
+        let mut state = self.state.lock().await;
+        state.sender = None;
+        state.listeners.clear();
+        drop(state);
+        // wake up whoever waits for the channel, the receive task can't anymore
+        self.closed.notify_one();
+    }
+
     pub async fn connect(&mut self, addr: Address) -> Result<()> {
         info!(
             "ATTManager connecting to {} on PSM {:#06X}...",
             addr, PSM_ATT
         );
-        let target_sa = SocketAddr::new(addr, AddressType::BrEdr, PSM_ATT);
-
-        let socket = Socket::new_seq_packet()?;
-        let seq_packet_result =
-            tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(target_sa)).await;
-        let seq_packet = match seq_packet_result {
-            Ok(Ok(s)) => Arc::new(s),
-            Ok(Err(e)) => {
-                error!("L2CAP connect failed: {}", e);
-                return Err(e.into());
-            }
-            Err(_) => {
-                error!("L2CAP connect timed out");
-                return Err(Error::from(std::io::Error::new(
-                    std::io::ErrorKind::TimedOut,
-                    "Connection timeout",
-                )));
-            }
-        };
-
-        // Wait for connection to be fully established
-        let start = Instant::now();
-        loop {
-            match seq_packet.peer_addr() {
-                Ok(peer) if peer.cid != 0 => break,
-                Ok(_) => {}
-                Err(e) => {
-                    if e.raw_os_error() == Some(107) {
-                        // ENOTCONN
-                        error!("Peer has disconnected during connection setup.");
-                        return Err(e.into());
-                    }
-                    error!("Error getting peer address: {}", e);
-                }
-            }
-            if start.elapsed() >= CONNECT_TIMEOUT {
-                error!("Timed out waiting for L2CAP connection to be fully established.");
-                return Err(Error::from(std::io::Error::new(
-                    std::io::ErrorKind::TimedOut,
-                    "Connection timeout",
-                )));
-            }
-            sleep(POLL_INTERVAL).await;
-        }
-
-        info!("L2CAP connection established with {}", addr);
+        let transport = L2capTransport::connect(addr, PSM_ATT).await?;
+        self.connect_with_transport(Arc::new(transport)).await;
+        Ok(())
+    }
 
+    /// Starts the manager on an already established transport, e.g. a `MemoryTransport`.
+    pub async fn connect_with_transport<T: PacketTransport>(&mut self, transport: Arc<T>) {
         let (tx, rx) = mpsc::channel(128);
         let state = ATTManagerState::new();
         {
@@ -143,10 +122,8 @@ impl ATTManager {
 
         let manager_clone = self.clone();
         let mut tasks = self.tasks.lock().await;
-        tasks.spawn(recv_thread(manager_clone, seq_packet.clone()));
-        tasks.spawn(send_thread(rx, seq_packet));
-
-        Ok(())
+        tasks.spawn(recv_thread(manager_clone, transport.clone()));
+        tasks.spawn(send_thread(rx, transport));
     }
 
     pub async fn register_listener(&self, handle: ATTHandles, tx: mpsc::UnboundedSender<Vec<u8>>) {
@@ -162,8 +139,7 @@ impl ATTManager {
         let lsb = (handle as u16 & 0xFF) as u8;
         let msb = ((handle as u16 >> 8) & 0xFF) as u8;
         let pdu = vec![OPCODE_READ_REQUEST, lsb, msb];
-        self.send_packet(&pdu).await?;
-        self.read_response().await
+        self.request(&pdu).await
     }
 
     pub async fn write(&self, handle: ATTHandles, value: &[u8]) -> Result<()> {
@@ -171,8 +147,7 @@ impl ATTManager {
         let msb = ((handle as u16 >> 8) & 0xFF) as u8;
         let mut pdu = vec![OPCODE_WRITE_REQUEST, lsb, msb];
         pdu.extend_from_slice(value);
-        self.send_packet(&pdu).await?;
-        self.read_response().await?;
+        self.request(&pdu).await?;
         Ok(())
     }
 
@@ -181,8 +156,7 @@ impl ATTManager {
         let msb = ((handle as u16 >> 8) & 0xFF) as u8;
         let mut pdu = vec![OPCODE_WRITE_REQUEST, lsb, msb];
         pdu.extend_from_slice(value);
-        self.send_packet(&pdu).await?;
-        self.read_response().await?;
+        self.request(&pdu).await?;
         Ok(())
     }
 
@@ -205,11 +179,21 @@ impl ATTManager {
         }
     }
 
-    async fn read_response(&self) -> Result<Vec<u8>> {
-        debug!("Waiting for response...");
+    /// Sends a request and waits for its response, the value without the opcode. ATT allows
+    /// only one outstanding request, so the response receiver stays locked until the response
+    /// arrived or timed out. An Error Response fails the request with its error code.
+    async fn request(&self, pdu: &[u8]) -> Result<Vec<u8>> {
         let mut rx = self.response_rx.lock().await;
+        // drop responses that arrived after an earlier request timed out
+        while rx.try_recv().is_ok() {}
+        self.send_packet(pdu).await?;
+        debug!("Waiting for response...");
         match tokio::time::timeout(Duration::from_millis(RESPONSE_TIMEOUT), rx.recv()).await {
-            Ok(Some(resp)) => Ok(resp),
+            Ok(Some(resp)) => match resp.first() {
+                Some(&OPCODE_ERROR_RESPONSE) => Err(error_response(&resp)),
+                Some(&OPCODE_WRITE_RESPONSE) => Ok(vec![]),
+                _ => Ok(resp.get(1..).unwrap_or_default().to_vec()),
+            },
             Ok(None) => Err(Error::from(std::io::Error::new(
                 std::io::ErrorKind::UnexpectedEof,
                 "Response channel closed",
@@ -222,7 +206,38 @@ impl ATTManager {
     }
 }
 
-async fn recv_thread(manager: ATTManager, sp: Arc<SeqPacket>) {
+/// Turns an Error Response (`01 [request opcode] [handle] [error code]`) into an error that
+/// names the ATT error code.
+fn error_response(pdu: &[u8]) -> Error {
+    let (Some(&request), Some(&lsb), Some(&msb), Some(&code)) =
+        (pdu.get(1), pdu.get(2), pdu.get(3), pdu.get(4))
+    else {
+        return Error {
+            kind: ErrorKind::Failed,
+            message: format!("Malformed ATT error response: {}", hex::encode(pdu)),
+        };
+    };
+    let kind = match code {
+        // Read Not Permitted, Write Not Permitted
+        0x02 | 0x03 => ErrorKind::NotPermitted,
+        // Insufficient Authentication, Insufficient Authorization
+        0x05 | 0x08 => ErrorKind::NotAuthorized,
+        // Request Not Supported
+        0x06 => ErrorKind::NotSupported,
+        _ => ErrorKind::Failed,
+    };
+    Error {
+        kind,
+        message: format!(
+            "ATT error {:#04x} for request {:#04x} on handle {:#06x}",
+            code,
+            request,
+            u16::from_le_bytes([lsb, msb])
+        ),
+    }
+}
+
+async fn recv_thread<T: PacketTransport>(manager: ATTManager, sp: Arc<T>) {
     let mut buf = vec![0u8; 1024];
     loop {
         match sp.recv(&mut buf).await {
@@ -246,11 +261,9 @@ async fn recv_thread(manager: ATTManager, sp: Arc<SeqPacket>) {
                             let _ = listener.send(value.clone());
                         }
                     }
-                } else if data[0] == OPCODE_WRITE_RESPONSE {
-                    let _ = manager.response_tx.send(vec![]);
                 } else {
-                    // Response
-                    let _ = manager.response_tx.send(data[1..].to_vec());
+                    // Response, including Error Responses, told apart by the request
+                    let _ = manager.response_tx.send(data.to_vec());
                 }
             }
             Err(e) => {
@@ -261,9 +274,11 @@ async fn recv_thread(manager: ATTManager, sp: Arc<SeqPacket>) {
     }
     let mut state = manager.state.lock().await;
     state.sender = None;
+    drop(state);
+    manager.closed.notify_one();
 }
 
-async fn send_thread(mut rx: mpsc::Receiver<Vec<u8>>, sp: Arc<SeqPacket>) {
+async fn send_thread<T: PacketTransport>(mut rx: mpsc::Receiver<Vec<u8>>, sp: Arc<T>) {
     while let Some(data) = rx.recv().await {
         if let Err(e) = sp.send(&data).await {
             error!("Failed to send data: {}", e);
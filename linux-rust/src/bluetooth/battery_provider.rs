@@ -0,0 +1,358 @@
+use crate::bluetooth::aacp::{BatteryComponent, BatteryInfo, BatteryStatus};
+use crate::bluetooth::adapter::active_adapter;
+use crate::bluetooth::managers::DeviceManagers;
+use crate::utils::{SharedSetting, load_app_setting, save_app_setting};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::blocking::stdintf::org_freedesktop_dbus::{
+    ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved, PropertiesPropertiesChanged,
+};
+use dbus::message::{MessageType, SignalArgs};
+use dbus::{Message, Path};
+use dbus_crossroads::{Crossroads, IfaceToken};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+
+const SETTINGS_KEY: &str = "battery_provider";
+
+static CURRENT: SharedSetting<BatteryProviderSettings> =
+    SharedSetting::new(BatteryProviderSettings::load);
+
+const PROVIDER_PATH: &str = "/me/librepods/battery";
+const PROVIDER_INTERFACE: &str = "org.bluez.BatteryProvider1";
+const PROVIDER_MANAGER_INTERFACE: &str = "org.bluez.BatteryProviderManager1";
+const SOURCE: &str = "LibrePods";
+
+// levels are compared against what was reported this often
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+// a registration BlueZ rejected is tried again after this, doubling up to the maximum
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// How the levels of both buds become the single level BlueZ knows about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BatteryPolicy {
+    #[default]
+    Lowest,
+    Average,
+}
+
+impl BatteryPolicy {
+    pub const ALL: [BatteryPolicy; 2] = [BatteryPolicy::Lowest, BatteryPolicy::Average];
+
+    /// The level to report, None while no part in use has one. The case never counts, and a
+    /// single bud in use is reported as is.
+    pub fn level(&self, battery: &[BatteryInfo]) -> Option<u8> {
+        let in_use = |component: BatteryComponent| {
+            battery
+                .iter()
+                .find(|b| b.component == component && b.status != BatteryStatus::Disconnected)
+                .map(|b| b.level)
+        };
+        if let Some(level) = in_use(BatteryComponent::Headphone) {
+            return Some(level);
+        }
+        match (
+            in_use(BatteryComponent::Left),
+            in_use(BatteryComponent::Right),
+        ) {
+            (Some(left), Some(right)) => Some(match self {
+                BatteryPolicy::Lowest => left.min(right),
+                BatteryPolicy::Average => (left as u16 + right as u16).div_ceil(2) as u8,
+            }),
+            (left, right) => left.or(right),
+        }
+    }
+}
+
+impl std::fmt::Display for BatteryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatteryPolicy::Lowest => write!(f, "Lowest bud"),
+            BatteryPolicy::Average => write!(f, "Average of both buds"),
+        }
+    }
+}
+
+/// Whether the battery level is handed to BlueZ, stored under `battery_provider` in the app
+/// settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatteryProviderSettings {
+    pub enabled: bool,
+    pub policy: BatteryPolicy,
+}
+
+impl Default for BatteryProviderSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            policy: BatteryPolicy::Lowest,
+        }
+    }
+}
+
+impl BatteryProviderSettings {
+    pub fn load() -> Self {
+        load_app_setting(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        save_app_setting(SETTINGS_KEY, serde_json::to_value(self).unwrap_or_default());
+    }
+
+    /// The settings in use, the file is only read the first time.
+    pub fn current() -> Self {
+        CURRENT.get()
+    }
+
+    /// Puts the settings in use right away, without saving them.
+    pub fn publish(&self) {
+        CURRENT.publish(self.clone());
+    }
+}
+
+/// What is reported for one device.
+#[derive(Debug, Clone, PartialEq)]
+struct ProvidedBattery {
+    device: Path<'static>,
+    percentage: u8,
+}
+
+/// Registers a battery provider with BlueZ on the system bus, so the desktop's Bluetooth and
+/// power panels (through UPower) show the AirPods battery. BlueZ only takes a single level per
+/// device, the case and each bud are on `me.librepods.AirPods` on the session bus. Runs on its
+/// own thread, as the dbus crate is blocking.
+pub fn start_battery_provider(device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>) {
+    let runtime = Handle::current();
+    std::thread::spawn(move || {
+        if let Err(e) = run(device_managers, runtime) {
+            error!("Battery provider stopped: {}", e);
+        }
+    });
+}
+
+fn run(
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    runtime: Handle,
+) -> Result<(), dbus::Error> {
+    let conn = Connection::new_system()?;
+    let mut cr = Crossroads::new();
+    let battery_iface = register_battery_interface(&mut cr);
+    let object_manager = cr.object_manager::<()>();
+    cr.insert(PROVIDER_PATH, &[object_manager], ());
+
+    // the adapter the provider is registered on, and the serial of the pending registration
+    let mut registered_on: Option<String> = None;
+    let mut registration: Option<u32> = None;
+    let mut provided: HashMap<String, ProvidedBattery> = HashMap::new();
+    let mut retry_at: Option<Instant> = None;
+    let mut retry_delay = FIRST_RETRY_DELAY;
+    // changed from the Settings tab while running
+    let settings_rx = CURRENT.watch();
+    loop {
+        conn.channel()
+            .read_write(Some(SYNC_INTERVAL))
+            .map_err(|_| dbus::Error::new_failed("Lost the connection to the system bus"))?;
+        while let Some(mut msg) = conn.channel().pop_message() {
+            match msg.msg_type() {
+                MessageType::MethodCall => {
+                    let _ = cr.handle_message(msg, &conn);
+                }
+                MessageType::Error | MessageType::MethodReturn
+                    if registration.is_some() && msg.get_reply_serial() == registration =>
+                {
+                    registration = None;
+                    match msg.as_result() {
+                        Ok(_) => {
+                            info!("Reporting battery levels to BlueZ");
+                            retry_delay = FIRST_RETRY_DELAY;
+                        }
+                        Err(e) => {
+                            warn!(
+                                "BlueZ didn't accept the battery provider, desktop battery widgets won't show the AirPods, retrying in {}s: {}",
+                                retry_delay.as_secs(),
+                                e
+                            );
+                            for (mac, _) in provided.drain() {
+                                remove_battery(&mut cr, &conn, &mac);
+                            }
+                            registered_on = None;
+                            retry_at = Some(Instant::now() + retry_delay);
+                            retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let settings = settings_rx.borrow().clone();
+        let adapter = active_adapter()
+            .map(|adapter| adapter.name)
+            .filter(|_| settings.enabled);
+        // only unregistered while waiting to retry, so nothing needs to be torn down
+        let retry_pending = retry_at.is_some_and(|at| Instant::now() < at);
+        if adapter != registered_on && !retry_pending {
+            // a new registration starts out empty, BlueZ asks for the objects itself
+            for (mac, _) in provided.drain() {
+                remove_battery(&mut cr, &conn, &mac);
+            }
+            if let Some(old) = &registered_on {
+                debug!("Unregistering the battery provider from {}", old);
+                call_provider_manager(&conn, old, "UnregisterBatteryProvider");
+            }
+            registration = adapter
+                .as_deref()
+                .and_then(|new| call_provider_manager(&conn, new, "RegisterBatteryProvider"));
+            registered_on = adapter;
+        }
+        let Some(adapter) = &registered_on else {
+            continue;
+        };
+
+        let levels = runtime.block_on(battery_levels(&device_managers, settings.policy));
+
+        provided.retain(|mac, _| {
+            if levels.contains_key(mac) {
+                return true;
+            }
+            remove_battery(&mut cr, &conn, mac);
+            false
+        });
+
+        for (mac, percentage) in levels {
+            let path = battery_path(&mac);
+            match provided.get_mut(&mac) {
+                Some(battery) if battery.percentage == percentage => {}
+                Some(battery) => {
+                    debug!("Reporting {}% for {} to BlueZ", percentage, mac);
+                    battery.percentage = percentage;
+                    if let Some(data) = cr.data_mut::<ProvidedBattery>(&path) {
+                        *data = battery.clone();
+                    }
+                    let mut changed_properties = PropMap::new();
+                    changed_properties.insert(
+                        "Percentage".to_string(),
+                        Variant(Box::new(percentage) as Box<dyn RefArg>),
+                    );
+                    let signal = PropertiesPropertiesChanged {
+                        interface_name: PROVIDER_INTERFACE.to_string(),
+                        changed_properties,
+                        invalidated_properties: Vec::new(),
+                    };
+                    let _ = conn.channel().send(signal.to_emit_message(&path));
+                }
+                None => {
+                    debug!("Reporting {}% for {} to BlueZ", percentage, mac);
+                    let battery = ProvidedBattery {
+                        device: Path::from(format!(
+                            "/org/bluez/{}/dev_{}",
+                            adapter,
+                            mac.replace(':', "_")
+                        )),
+                        percentage,
+                    };
+                    cr.insert(path.clone(), &[battery_iface], battery.clone());
+                    let signal = ObjectManagerInterfacesAdded {
+                        object: path,
+                        interfaces: HashMap::from([(
+                            PROVIDER_INTERFACE.to_string(),
+                            battery_properties(&battery),
+                        )]),
+                    };
+                    let _ = conn
+                        .channel()
+                        .send(signal.to_emit_message(&Path::from(PROVIDER_PATH)));
+                    provided.insert(mac, battery);
+                }
+            }
+        }
+    }
+}
+
+fn register_battery_interface(cr: &mut Crossroads) -> IfaceToken<ProvidedBattery> {
+    cr.register(PROVIDER_INTERFACE, |b| {
+        b.property("Device")
+            .get(|_, battery: &mut ProvidedBattery| Ok(battery.device.clone()));
+        b.property("Percentage")
+            .get(|_, battery: &mut ProvidedBattery| Ok(battery.percentage));
+        b.property("Source")
+            .get(|_, _: &mut ProvidedBattery| Ok(SOURCE.to_string()));
+    })
+}
+
+fn battery_properties(battery: &ProvidedBattery) -> PropMap {
+    PropMap::from([
+        (
+            "Device".to_string(),
+            Variant(Box::new(battery.device.clone()) as Box<dyn RefArg>),
+        ),
+        (
+            "Percentage".to_string(),
+            Variant(Box::new(battery.percentage) as Box<dyn RefArg>),
+        ),
+        (
+            "Source".to_string(),
+            Variant(Box::new(SOURCE.to_string()) as Box<dyn RefArg>),
+        ),
+    ])
+}
+
+fn battery_path(mac: &str) -> Path<'static> {
+    Path::from(format!("{}/dev_{}", PROVIDER_PATH, mac.replace(':', "_")))
+}
+
+fn remove_battery(cr: &mut Crossroads, conn: &Connection, mac: &str) {
+    debug!("No longer reporting a battery level for {} to BlueZ", mac);
+    let path = battery_path(mac);
+    cr.remove::<ProvidedBattery>(&path);
+    let signal = ObjectManagerInterfacesRemoved {
+        object: path,
+        interfaces: vec![PROVIDER_INTERFACE.to_string()],
+    };
+    let _ = conn
+        .channel()
+        .send(signal.to_emit_message(&Path::from(PROVIDER_PATH)));
+}
+
+/// Calls the provider manager of an adapter without waiting, BlueZ asks for the provider's
+/// objects before it replies and those requests are answered on this same thread. Returns the
+/// serial of the call.
+fn call_provider_manager(conn: &Connection, adapter: &str, method: &str) -> Option<u32> {
+    let msg = Message::new_method_call(
+        "org.bluez",
+        format!("/org/bluez/{}", adapter),
+        PROVIDER_MANAGER_INTERFACE,
+        method,
+    )
+    .ok()?
+    .append1(Path::from(PROVIDER_PATH));
+    conn.channel().send(msg).ok()
+}
+
+/// The level to report for every connected device that has one.
+async fn battery_levels(
+    device_managers: &RwLock<HashMap<String, DeviceManagers>>,
+    policy: BatteryPolicy,
+) -> HashMap<String, u8> {
+    let entries: Vec<_> = device_managers
+        .read()
+        .await
+        .iter()
+        .filter_map(|(mac, m)| Some((mac.clone(), m.get_aacp()?)))
+        .collect();
+    let mut levels = HashMap::new();
+    for (mac, aacp) in entries {
+        let state = aacp.state.lock().await;
+        if let Some(level) = policy.level(&state.battery_info) {
+            levels.insert(mac, level);
+        }
+    }
+    levels
+}
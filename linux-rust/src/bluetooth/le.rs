@@ -1,12 +1,12 @@
 use crate::bluetooth::aacp::BatteryStatus;
 use crate::devices::enums::{DeviceData, DeviceInformation, DeviceType};
 use crate::ui::tray::MyTray;
-use crate::utils::{ah, get_devices_path, get_preferences_path};
+use crate::utils::{ah, get_devices_path, load_device_preference};
 use aes::Aes128;
 use aes::cipher::generic_array::GenericArray;
 use aes::cipher::{BlockDecrypt, KeyInit};
 use bluer::monitor::{Monitor, MonitorEvent, Pattern};
-use bluer::{Address, Session};
+use bluer::{Adapter, Address};
 use futures::StreamExt;
 use hex;
 use log::{debug, info};
@@ -46,11 +46,10 @@ fn verify_rpa(addr: &str, irk: &[u8; 16]) -> bool {
     hash == computed_hash
 }
 
-pub async fn start_le_monitor(tray_handle: Option<ksni::Handle<MyTray>>) -> bluer::Result<()> {
-    let session = Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
-
+pub async fn start_le_monitor(
+    adapter: Adapter,
+    tray_handle: Option<ksni::Handle<MyTray>>,
+) -> bluer::Result<()> {
     let all_devices: HashMap<String, DeviceData> = std::fs::read_to_string(get_devices_path())
         .ok()
         .and_then(|s| serde_json::from_str(&s).ok())
@@ -143,6 +142,11 @@ pub async fn start_le_monitor(tray_handle: Option<ksni::Handle<MyTray>>) -> blue
             if matched_airpods_mac.is_some() {
                 let mut events = dev.events().await?;
                 let tray_handle_clone = tray_handle.clone();
+                let device_name = matched_airpods_mac
+                    .as_ref()
+                    .and_then(|mac| all_devices.get(mac))
+                    .map(|d| d.name.clone())
+                    .unwrap_or_default();
                 let connecting_macs_clone = Arc::clone(&connecting_macs);
                 tokio::spawn(async move {
                     while let Some(ev) = events.next().await {
@@ -167,19 +171,11 @@ pub async fn start_le_monitor(tray_handle: Option<ksni::Handle<MyTray>>) -> blue
                                         let connection_state = apple_data[10] as usize;
                                         debug!("Connection state: {}", connection_state);
                                         if connection_state == 0x00 {
-                                            let pref_path = get_preferences_path();
-                                            let preferences: HashMap<
-                                                String,
-                                                HashMap<String, bool>,
-                                            > = std::fs::read_to_string(&pref_path)
-                                                .ok()
-                                                .and_then(|s| serde_json::from_str(&s).ok())
-                                                .unwrap_or_default();
-                                            let auto_connect = preferences
-                                                .get(matched_airpods_mac.as_ref().unwrap())
-                                                .and_then(|prefs| prefs.get("autoConnect"))
-                                                .copied()
-                                                .unwrap_or(true);
+                                            let auto_connect = load_device_preference::<bool>(
+                                                matched_airpods_mac.as_ref().unwrap(),
+                                                "autoConnect",
+                                            )
+                                            .unwrap_or(true);
                                             debug!(
                                                 "Auto-connect preference for {}: {}",
                                                 matched_airpods_mac.as_ref().unwrap(),
@@ -296,6 +292,11 @@ pub async fn start_le_monitor(tray_handle: Option<ksni::Handle<MyTray>>) -> blue
                                         if let Some(handle) = &tray_handle_clone {
                                             handle
                                                 .update(|tray: &mut MyTray| {
+                                                    let mac = matched_airpods_mac.as_ref().unwrap();
+                                                    let tray = tray.device_mut(mac);
+                                                    if tray.name.is_empty() {
+                                                        tray.name = device_name.clone();
+                                                    }
                                                     tray.battery_l = if left_byte == 0xff {
                                                         None
                                                     } else {
@@ -0,0 +1,284 @@
+use crate::bluetooth::aacp::AACPManager;
+use crate::bluetooth::att::ATTManager;
+use crate::bluetooth::managers::{DeviceManagers, device_state_changed};
+use crate::devices::enums::DeviceData;
+use crate::notifications::notify_disconnected;
+use crate::ui::messages::BluetoothUIMessage;
+use crate::ui::tray::MyTray;
+use crate::utils::get_devices_path;
+use bluer::Address;
+use ksni::Handle;
+use log::{debug, info};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::UnboundedSender;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Opening the L2CAP channels.
+    Connecting,
+    /// Channels are open, setting the device up (handshake, notifications, information).
+    Handshaking,
+    Ready,
+    Disconnected,
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "Connecting"),
+            ConnectionState::Handshaking => write!(f, "Handshaking"),
+            ConnectionState::Ready => write!(f, "Ready"),
+            ConnectionState::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// Moves one device through Connecting → Handshaking → Ready → Disconnected, keeping the
+/// device managers, the tray and the UI in sync with it.
+///
+/// A device only has a `DeviceManagers` entry while it isn't disconnected, so tearing down
+/// twice (BlueZ reporting the disconnect and the L2CAP channel closing) is harmless. The entry
+/// remembers which lifecycle claimed it with `begin`, so a connection attempt that outlived its
+/// device can't touch the managers of a newer one.
+#[derive(Clone)]
+pub struct ConnectionLifecycle {
+    id: u64,
+    address: Address,
+    mac: String,
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+    tray_handle: Option<Handle<MyTray>>,
+}
+
+impl ConnectionLifecycle {
+    pub fn new(
+        mac_address: Address,
+        device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+        ui_tx: UnboundedSender<BluetoothUIMessage>,
+        tray_handle: Option<Handle<MyTray>>,
+    ) -> Self {
+        Self {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            address: mac_address,
+            mac: mac_address.to_string(),
+            device_managers,
+            ui_tx,
+            tray_handle,
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Claims the device and marks it as connecting. Returns false if another connection is
+    /// already taking care of it.
+    pub async fn begin(&self) -> bool {
+        {
+            let mut managers = self.device_managers.write().await;
+            if managers.contains_key(&self.mac) {
+                debug!("{} is already being connected", self.mac);
+                return false;
+            }
+            managers.insert(self.mac.clone(), DeviceManagers::connecting(self.id));
+        }
+        self.notify(ConnectionState::Connecting);
+        true
+    }
+
+    /// Whether the device still belongs to this connection, i.e. it wasn't disconnected or
+    /// claimed by a newer connection since.
+    pub async fn is_current(&self) -> bool {
+        self.device_managers
+            .read()
+            .await
+            .get(&self.mac)
+            .is_some_and(|m| m.connection_id() == self.id)
+    }
+
+    /// Marks the device as connecting or handshaking. Ready and Disconnected have their own
+    /// methods as they change the managers.
+    pub async fn set_state(&self, state: ConnectionState) {
+        debug_assert!(matches!(
+            state,
+            ConnectionState::Connecting | ConnectionState::Handshaking
+        ));
+        {
+            let mut managers = self.device_managers.write().await;
+            let Some(entry) = managers
+                .get_mut(&self.mac)
+                .filter(|m| m.connection_id() == self.id)
+            else {
+                return;
+            };
+            entry.set_connection_state(state);
+        }
+        self.notify(state);
+    }
+
+    /// Registers the connected managers and tells the UI the device can be used. If the device
+    /// went away while handshaking the managers are stopped instead and false is returned.
+    pub async fn ready(&self, aacp: Option<AACPManager>, att: Option<ATTManager>) -> bool {
+        {
+            let mut managers = self.device_managers.write().await;
+            let Some(entry) = managers
+                .get_mut(&self.mac)
+                .filter(|m| m.connection_id() == self.id)
+            else {
+                drop(managers);
+                info!("{} disconnected during the handshake", self.mac);
+                if let Some(aacp) = aacp {
+                    aacp.disconnect().await;
+                }
+                if let Some(att) = att {
+                    att.disconnect().await;
+                }
+                return false;
+            };
+            if let Some(aacp) = aacp {
+                entry.set_aacp(aacp);
+            }
+            // the AirPods ATT channel is attached on its own, maybe before this
+            if att.is_some() {
+                entry.replace_att(att);
+            }
+            entry.set_connection_state(ConnectionState::Ready);
+        }
+        if let Some(handle) = &self.tray_handle {
+            let name = device_name(&self.mac).await;
+            handle
+                .update(|tray: &mut MyTray| tray.device_connected(&self.mac, name))
+                .await;
+        }
+        self.notify(ConnectionState::Ready);
+        let _ = self
+            .ui_tx
+            .send(BluetoothUIMessage::DeviceConnected(self.mac.clone()));
+        true
+    }
+
+    /// Hands the device an ATT channel that was opened after the connection. If the device no
+    /// longer belongs to this connection the manager is stopped instead and false is returned.
+    pub async fn attach_att(&self, att: ATTManager) -> bool {
+        {
+            let mut managers = self.device_managers.write().await;
+            if let Some(entry) = managers
+                .get_mut(&self.mac)
+                .filter(|m| m.connection_id() == self.id)
+            {
+                entry.replace_att(Some(att));
+                return true;
+            }
+        }
+        att.disconnect().await;
+        false
+    }
+
+    /// The channels dropped but the device is still connected: tears the managers down like
+    /// `disconnected`, but keeps the device claimed and connecting so it can be reconnected.
+    /// Returns false if the device no longer belongs to this connection.
+    pub async fn connection_lost(&self) -> bool {
+        let old = {
+            let mut managers = self.device_managers.write().await;
+            if !managers
+                .get(&self.mac)
+                .is_some_and(|m| m.connection_id() == self.id)
+            {
+                return false;
+            }
+            managers.insert(self.mac.clone(), DeviceManagers::connecting(self.id))
+        };
+        info!("Lost the connection to {}", self.mac);
+        if let Some(old) = old {
+            self.tear_down(old).await;
+        }
+        self.notify(ConnectionState::Connecting);
+        true
+    }
+
+    /// Tears the connection down: stops the manager tasks, drops the managers, resets the tray
+    /// and tells the UI. Does nothing if the device is already disconnected.
+    pub async fn disconnected(&self) {
+        let Some(managers) = self.device_managers.write().await.remove(&self.mac) else {
+            debug!("{} is already disconnected", self.mac);
+            return;
+        };
+        info!("{} disconnected, tearing down", self.mac);
+        // a connection that is retried was already lost, only AirPods in use are reported
+        if managers.connection_state() == ConnectionState::Ready && managers.get_aacp().is_some() {
+            notify_disconnected(&device_name(&self.mac).await);
+        }
+        self.tear_down(managers).await;
+        self.notify(ConnectionState::Disconnected);
+    }
+
+    /// Tears the connection down once the given channel is closed by the device.
+    pub fn disconnect_when_closed(&self, channel: ClosableChannel) {
+        let lifecycle = self.clone();
+        tokio::spawn(async move {
+            channel.closed().await;
+            info!("Connection to {} closed by the device", lifecycle.mac);
+            lifecycle.disconnected().await;
+        });
+    }
+
+    async fn tear_down(&self, managers: DeviceManagers) {
+        let was_ready = managers.connection_state() == ConnectionState::Ready;
+        managers.disconnect().await;
+        if let Some(handle) = &self.tray_handle {
+            handle
+                .update(|tray: &mut MyTray| tray.remove_device(&self.mac))
+                .await;
+        }
+        if was_ready {
+            let _ = self
+                .ui_tx
+                .send(BluetoothUIMessage::DeviceDisconnected(self.mac.clone()));
+        }
+    }
+
+    fn notify(&self, state: ConnectionState) {
+        info!("{}: {}", self.mac, state);
+        device_state_changed();
+        let _ = self.ui_tx.send(BluetoothUIMessage::ConnectionStateChanged(
+            self.mac.clone(),
+            state,
+        ));
+    }
+}
+
+/// The name stored for a device, empty if it has none yet. Reads devices.json off the runtime.
+pub(crate) async fn device_name(mac: &str) -> String {
+    let mac = mac.to_string();
+    tokio::task::spawn_blocking(move || {
+        std::fs::read_to_string(get_devices_path())
+            .ok()
+            .and_then(|s| serde_json::from_str::<HashMap<String, DeviceData>>(&s).ok())
+            .and_then(|devices| devices.get(&mac).map(|d| d.name.clone()))
+    })
+    .await
+    .ok()
+    .flatten()
+    .unwrap_or_default()
+}
+
+/// The channel a device can't work without: AACP for AirPods, ATT for Nothing devices.
+pub enum ClosableChannel {
+    Aacp(AACPManager),
+    Att(ATTManager),
+}
+
+impl ClosableChannel {
+    pub async fn closed(&self) {
+        match self {
+            ClosableChannel::Aacp(aacp) => aacp.wait_closed().await,
+            ClosableChannel::Att(att) => att.wait_closed().await,
+        }
+    }
+}
@@ -1,32 +1,39 @@
 use crate::bluetooth::aacp::AACPManager;
 use crate::bluetooth::att::ATTManager;
+use crate::bluetooth::lifecycle::ConnectionState;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// bumped whenever something known about a device changes, so observers only have to look at
+// the managers when there is something new
+static STATE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Marks the state of a device as changed, call it after the change is made.
+pub fn device_state_changed() {
+    STATE_GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Changes whenever `device_state_changed` is called.
+pub fn device_state_generation() -> u64 {
+    STATE_GENERATION.load(Ordering::Acquire)
+}
 
 pub struct DeviceManagers {
     att: Option<Arc<ATTManager>>,
     aacp: Option<Arc<AACPManager>>,
+    connection_state: ConnectionState,
+    // the connection attempt that owns these managers, see `ConnectionLifecycle`
+    connection_id: u64,
 }
 
 impl DeviceManagers {
-    pub fn with_aacp(aacp: AACPManager) -> Self {
+    /// A device that is still connecting, managers are added once they are set up.
+    pub fn connecting(connection_id: u64) -> Self {
         Self {
             att: None,
-            aacp: Some(Arc::new(aacp)),
-        }
-    }
-
-    pub fn with_att(att: ATTManager) -> Self {
-        Self {
-            att: Some(Arc::new(att)),
             aacp: None,
-        }
-    }
-
-    // keeping the att for airpods optional as it requires changes in system bluez config
-    pub fn with_both(aacp: AACPManager, att: ATTManager) -> Self {
-        Self {
-            att: Some(Arc::new(att)),
-            aacp: Some(Arc::new(aacp)),
+            connection_state: ConnectionState::Connecting,
+            connection_id,
         }
     }
 
@@ -34,8 +41,10 @@ impl DeviceManagers {
         self.aacp = Some(Arc::new(manager));
     }
 
-    pub fn set_att(&mut self, manager: ATTManager) {
-        self.att = Some(Arc::new(manager));
+    // the att for airpods is optional as the airpods only allow it with the right bluez config,
+    // it is attached once it opened after the aacp connection
+    pub fn replace_att(&mut self, manager: Option<ATTManager>) {
+        self.att = manager.map(Arc::new);
     }
 
     pub fn get_aacp(&self) -> Option<Arc<AACPManager>> {
@@ -45,4 +54,26 @@ impl DeviceManagers {
     pub fn get_att(&self) -> Option<Arc<ATTManager>> {
         self.att.clone()
     }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection_state
+    }
+
+    pub fn set_connection_state(&mut self, state: ConnectionState) {
+        self.connection_state = state;
+    }
+
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// Stops the tasks of all managers and closes their channels.
+    pub async fn disconnect(&self) {
+        if let Some(aacp) = &self.aacp {
+            aacp.disconnect().await;
+        }
+        if let Some(att) = &self.att {
+            att.disconnect().await;
+        }
+    }
 }
@@ -1,5 +1,10 @@
 pub mod aacp;
+pub mod adapter;
 pub mod att;
+pub mod battery_provider;
 pub(crate) mod discovery;
 pub mod le;
+pub mod lifecycle;
 pub mod managers;
+pub mod reconnect;
+pub mod transport;
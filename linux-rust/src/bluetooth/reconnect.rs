@@ -0,0 +1,123 @@
+use crate::bluetooth::aacp::AACPManager;
+use crate::bluetooth::adapter::open_adapter;
+use crate::bluetooth::att::ATTManager;
+use crate::bluetooth::lifecycle::{ClosableChannel, ConnectionLifecycle, ConnectionState};
+use bluer::Address;
+use log::{error, info};
+use std::future::Future;
+use tokio::time::{Duration, sleep};
+
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+// attempts in a row, a connection that makes it to Ready starts over
+const MAX_ATTEMPTS: u32 = 6;
+
+/// Exponential backoff with a retry budget: waits 1s, 2s, 4s, ... up to `MAX_DELAY` between
+/// attempts and gives up after `MAX_ATTEMPTS`.
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self { attempt: 0 }
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// The delay before the next attempt, or None once the budget is used up.
+    fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= MAX_ATTEMPTS {
+            return None;
+        }
+        let delay = INITIAL_DELAY
+            .saturating_mul(1 << self.attempt)
+            .min(MAX_DELAY);
+        self.attempt += 1;
+        Some(delay)
+    }
+}
+
+/// The managers of one successful connection attempt, and the channel whose closing means the
+/// connection is gone.
+pub struct Connected {
+    pub aacp: Option<AACPManager>,
+    pub att: Option<ATTManager>,
+    pub channel: ClosableChannel,
+}
+
+/// Connects the device with `connect` and keeps it connected: whenever the attempt fails or the
+/// channel drops while BlueZ still has the device connected, `connect` runs again after a
+/// backoff. `connect` has to do the whole setup (handshake etc.) as every attempt starts from
+/// fresh managers. Stops when the device disconnects, another connection takes the device over
+/// or the retry budget is used up.
+pub async fn supervise<F, Fut>(lifecycle: ConnectionLifecycle, mut connect: F)
+where
+    F: FnMut(ConnectionLifecycle) -> Fut,
+    Fut: Future<Output = bluer::Result<Connected>>,
+{
+    if !lifecycle.begin().await {
+        return;
+    }
+    let address = lifecycle.address();
+    let mut backoff = Backoff::new();
+    loop {
+        match connect(lifecycle.clone()).await {
+            Ok(connected) => {
+                if !lifecycle.ready(connected.aacp, connected.att).await {
+                    return;
+                }
+                backoff.reset();
+                connected.channel.closed().await;
+                if !lifecycle.connection_lost().await {
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("Failed to connect to {}: {}", address, e);
+                if !lifecycle.is_current().await {
+                    return;
+                }
+            }
+        }
+
+        if !is_acl_connected(address).await.unwrap_or(false) {
+            info!("{} is no longer connected, not reconnecting", address);
+            lifecycle.disconnected().await;
+            return;
+        }
+        let Some(delay) = backoff.next_delay() else {
+            error!(
+                "Giving up on {} after {} reconnect attempts",
+                address, MAX_ATTEMPTS
+            );
+            lifecycle.disconnected().await;
+            return;
+        };
+        info!(
+            "Reconnecting to {} in {:?} (attempt {}/{})",
+            address,
+            delay,
+            backoff.attempt(),
+            MAX_ATTEMPTS
+        );
+        sleep(delay).await;
+        if !lifecycle.is_current().await {
+            return;
+        }
+        lifecycle.set_state(ConnectionState::Connecting).await;
+    }
+}
+
+/// Whether BlueZ still has the ACL link up, the L2CAP channels can only be reopened then.
+async fn is_acl_connected(address: Address) -> bluer::Result<bool> {
+    let session = bluer::Session::new().await?;
+    let adapter = open_adapter(&session).await?;
+    adapter.device(address)?.is_connected().await
+}
@@ -0,0 +1,142 @@
+use crate::bluetooth::adapter::active_adapter;
+use bluer::l2cap::{SeqPacket, Socket, SocketAddr};
+use bluer::{Address, AddressType, Error, Result};
+use log::{error, info};
+use std::future::Future;
+use std::io;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{Duration, Instant, sleep};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A packet-oriented, bidirectional link the managers talk over.
+///
+/// `recv` returns `Ok(0)` once the remote side has closed the link.
+pub trait PacketTransport: Send + Sync + 'static {
+    fn send(&self, data: &[u8]) -> impl Future<Output = io::Result<()>> + Send;
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send;
+}
+
+pub struct L2capTransport {
+    socket: SeqPacket,
+}
+
+impl L2capTransport {
+    pub async fn connect(addr: Address, psm: u16) -> Result<Self> {
+        let target_sa = SocketAddr::new(addr, AddressType::BrEdr, psm);
+
+        let socket = Socket::new_seq_packet().inspect_err(|e| {
+            error!("Failed to create L2CAP socket: {}", e);
+        })?;
+        // go through the selected adapter, the kernel would otherwise pick one itself
+        if let Some(active) = active_adapter() {
+            socket
+                .bind(SocketAddr::new(active.address, AddressType::BrEdr, 0))
+                .inspect_err(|e| {
+                    error!("Failed to bind L2CAP socket to {}: {}", active.name, e);
+                })?;
+        }
+        let seq_packet =
+            match tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(target_sa)).await {
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => {
+                    error!("L2CAP connect failed: {}", e);
+                    return Err(e.into());
+                }
+                Err(_) => {
+                    error!("L2CAP connect timed out");
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Connection timeout",
+                    )));
+                }
+            };
+
+        // Wait for connection to be fully established
+        let start = Instant::now();
+        loop {
+            match seq_packet.peer_addr() {
+                Ok(peer) if peer.cid != 0 => break,
+                Ok(_) => { /* still waiting */ }
+                Err(e) => {
+                    if e.raw_os_error() == Some(107) {
+                        // ENOTCONN
+                        error!("Peer has disconnected during connection setup.");
+                        return Err(e.into());
+                    }
+                    error!("Error getting peer address: {}", e);
+                }
+            }
+            if start.elapsed() >= CONNECT_TIMEOUT {
+                error!("Timed out waiting for L2CAP connection to be fully established.");
+                return Err(Error::from(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Connection timeout",
+                )));
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+
+        info!(
+            "L2CAP connection established with {} on PSM {:#06X}",
+            addr, psm
+        );
+        Ok(L2capTransport { socket: seq_packet })
+    }
+}
+
+impl PacketTransport for L2capTransport {
+    async fn send(&self, data: &[u8]) -> io::Result<()> {
+        self.socket.send(data).await.map(|_| ())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.recv(buf).await
+    }
+}
+
+/// One end of an in-memory duplex link, for running the managers without a Bluetooth adapter.
+pub struct MemoryTransport {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+}
+
+impl MemoryTransport {
+    /// Creates two connected ends; packets sent on one are received on the other.
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let (a_tx, a_rx) = mpsc::unbounded_channel();
+        let (b_tx, b_rx) = mpsc::unbounded_channel();
+        (
+            MemoryTransport {
+                tx: a_tx,
+                rx: Mutex::new(b_rx),
+            },
+            MemoryTransport {
+                tx: b_tx,
+                rx: Mutex::new(a_rx),
+            },
+        )
+    }
+}
+
+impl PacketTransport for MemoryTransport {
+    async fn send(&self, data: &[u8]) -> io::Result<()> {
+        self.tx
+            .send(data.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Remote end closed"))
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut rx = self.rx.lock().await;
+        match rx.recv().await {
+            Some(data) => {
+                // behave like a SOCK_SEQPACKET socket, truncating packets larger than the buffer
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+            None => Ok(0),
+        }
+    }
+}
@@ -0,0 +1,515 @@
+use crate::bluetooth::aacp::BatteryStatus;
+use crate::dbus_service::{
+    AIRPODS_INTERFACE, BUS_NAME, DAEMON_INTERFACE, DEVICE_INTERFACE, ROOT_PATH, device_path,
+};
+use crate::devices::enums::AirPodsNoiseControlMode;
+use crate::notifications::LOW_BATTERY;
+use crate::ui::tray::TrayDevice;
+use clap::{Subcommand, ValueEnum};
+use dbus::arg::{PropMap, RefArg};
+use dbus::blocking::Connection;
+use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManager;
+use dbus::message::MatchRule;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::Duration;
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+// the watcher also looks again this often, so it notices LibrePods starting or quitting
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Commands for an already running LibrePods, sent over its D-Bus service. None of them start
+/// anything themselves.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Show the devices LibrePods handles and their state
+    Status {
+        #[arg(long, help = "Print JSON instead of text")]
+        json: bool,
+    },
+    /// Switch the listening mode
+    Mode {
+        #[arg(value_enum)]
+        mode: ListeningModeArg,
+    },
+    /// Turn Conversation Awareness on or off
+    Ca {
+        #[arg(value_enum)]
+        state: Switch,
+    },
+    /// Rename the AirPods
+    Rename { name: String },
+    /// Show the battery levels
+    Battery,
+    /// Print a line for a status bar whenever the state changes
+    Watch {
+        #[arg(long, value_enum, default_value = "waybar")]
+        format: WatchFormat,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WatchFormat {
+    /// JSON for a waybar custom module with `return-type` set to `json`
+    Waybar,
+    /// JSON for an i3blocks block with `interval=persist` and `format=json`
+    I3blocks,
+    /// Just the text, for polybar's `tail = true` and similar
+    Plain,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ListeningModeArg {
+    Anc,
+    Transparency,
+    Adaptive,
+    Off,
+}
+
+impl From<ListeningModeArg> for AirPodsNoiseControlMode {
+    fn from(mode: ListeningModeArg) -> Self {
+        match mode {
+            ListeningModeArg::Anc => AirPodsNoiseControlMode::NoiseCancellation,
+            ListeningModeArg::Transparency => AirPodsNoiseControlMode::Transparency,
+            ListeningModeArg::Adaptive => AirPodsNoiseControlMode::Adaptive,
+            ListeningModeArg::Off => AirPodsNoiseControlMode::Off,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatteryLevel {
+    pub level: u8,
+    pub status: String,
+}
+
+/// A device as published by the D-Bus service.
+#[derive(Debug, Serialize)]
+pub struct DeviceStatus {
+    pub address: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub connection_state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub airpods: Option<AirPodsStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AirPodsStatus {
+    pub battery: BTreeMap<String, BatteryLevel>,
+    pub ear_detection: Vec<String>,
+    pub listening_mode: String,
+    pub conversation_awareness: bool,
+    pub personalized_volume: bool,
+}
+
+impl DeviceStatus {
+    fn from_interfaces(interfaces: &HashMap<String, PropMap>) -> Option<Self> {
+        let device = interfaces.get(DEVICE_INTERFACE)?;
+        let airpods = interfaces
+            .get(AIRPODS_INTERFACE)
+            .map(|props| AirPodsStatus {
+                battery: prop_battery(props, "Battery"),
+                ear_detection: prop_strings(props, "EarDetection"),
+                listening_mode: prop_string(props, "ListeningMode"),
+                conversation_awareness: prop_bool(props, "ConversationAwareness"),
+                personalized_volume: prop_bool(props, "PersonalizedVolume"),
+            });
+        Some(Self {
+            address: prop_string(device, "Address"),
+            name: prop_string(device, "Name"),
+            device_type: prop_string(device, "Type"),
+            connection_state: prop_string(device, "ConnectionState"),
+            airpods,
+        })
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.connection_state == "Ready"
+    }
+
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            &self.address
+        } else {
+            &self.name
+        }
+    }
+
+    /// Battery levels on one line, like `L 80% · R 75% (charging) · Case 50%`.
+    pub fn battery_line(&self) -> Option<String> {
+        let airpods = self.airpods.as_ref()?;
+        let parts: Vec<String> = ["Headphone", "Left", "Right", "Case"]
+            .into_iter()
+            .filter_map(|component| {
+                let battery = airpods.battery.get(component)?;
+                if battery.status == "Disconnected" {
+                    return None;
+                }
+                let label = match component {
+                    "Headphone" => "",
+                    "Left" => "L ",
+                    "Right" => "R ",
+                    _ => "Case ",
+                };
+                let charging = if battery.status == "Charging" {
+                    " (charging)"
+                } else {
+                    ""
+                };
+                Some(format!("{}{}%{}", label, battery.level, charging))
+            })
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" · "))
+    }
+
+    /// The device the way the tray knows it, so the status bar shows what the tray would.
+    fn tray_device(&self) -> TrayDevice {
+        let mut device = TrayDevice {
+            name: self.display_name().to_string(),
+            connected: self.is_ready(),
+            ..Default::default()
+        };
+        let Some(airpods) = &self.airpods else {
+            return device;
+        };
+        let battery = |component: &str| {
+            airpods.battery.get(component).map(|b| {
+                let status = match b.status.as_str() {
+                    "Charging" => BatteryStatus::Charging,
+                    "Disconnected" => BatteryStatus::Disconnected,
+                    _ => BatteryStatus::NotCharging,
+                };
+                (b.level, status)
+            })
+        };
+        (device.battery_headphone, device.battery_headphone_status) = battery("Headphone").unzip();
+        (device.battery_l, device.battery_l_status) = battery("Left").unzip();
+        (device.battery_r, device.battery_r_status) = battery("Right").unzip();
+        (device.battery_c, device.battery_c_status) = battery("Case").unzip();
+        device.listening_mode =
+            AirPodsNoiseControlMode::from_id(&airpods.listening_mode).map(|mode| mode.to_byte());
+        device.conversation_detect_enabled = Some(airpods.conversation_awareness);
+        device
+    }
+}
+
+/// Runs a command against the running instance, errors are meant for the user.
+pub fn run(command: Command, device: Option<String>) -> Result<(), String> {
+    let conn = Connection::new_session()
+        .map_err(|e| format!("Can't connect to the session bus: {}", e))?;
+    match command {
+        Command::Status { json } => {
+            let devices = select_all(list_devices(&conn)?, device.as_deref())?;
+            if json {
+                let json = serde_json::to_string_pretty(&devices)
+                    .map_err(|e| format!("Failed to serialize the status: {}", e))?;
+                println!("{}", json);
+                return Ok(());
+            }
+            for device in devices {
+                println!(
+                    "{} ({}): {}",
+                    device.display_name(),
+                    device.address,
+                    device.connection_state
+                );
+                if let Some(battery) = device.battery_line() {
+                    println!("  Battery: {}", battery);
+                }
+                if let Some(airpods) = &device.airpods {
+                    if !airpods.listening_mode.is_empty() {
+                        println!("  Listening mode: {}", airpods.listening_mode);
+                    }
+                    println!(
+                        "  Conversation awareness: {}",
+                        if airpods.conversation_awareness {
+                            "on"
+                        } else {
+                            "off"
+                        }
+                    );
+                    if !airpods.ear_detection.is_empty() {
+                        println!("  Ear detection: {}", airpods.ear_detection.join(", "));
+                    }
+                }
+            }
+            Ok(())
+        }
+        Command::Watch { format } => watch(&conn, format, device.as_deref()),
+        Command::Battery => {
+            for device in select_all(list_devices(&conn)?, device.as_deref())? {
+                match device.battery_line() {
+                    Some(battery) => println!("{}: {}", device.display_name(), battery),
+                    None => println!("{}: unknown", device.display_name()),
+                }
+            }
+            Ok(())
+        }
+        Command::Mode { mode } => {
+            let mode = AirPodsNoiseControlMode::from(mode);
+            call(&conn, device.as_deref(), "SetListeningMode", (mode.id(),))
+        }
+        Command::Ca { state } => call(
+            &conn,
+            device.as_deref(),
+            "SetConversationAwareness",
+            (matches!(state, Switch::On),),
+        ),
+        Command::Rename { name } => call(&conn, device.as_deref(), "Rename", (name,)),
+    }
+}
+
+/// Prints a status line whenever something LibrePods publishes changes. Keeps going while
+/// LibrePods isn't running, the line then says so, and stops once nobody reads the output.
+fn watch(conn: &Connection, format: WatchFormat, address: Option<&str>) -> Result<(), String> {
+    let rule = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+        .with_sender(BUS_NAME);
+    conn.add_match(rule, |_: (), _, _| true)
+        .map_err(|e| format!("Failed to watch LibrePods: {}", e))?;
+    let rule = MatchRule::new_signal("org.freedesktop.DBus.ObjectManager", "InterfacesAdded")
+        .with_sender(BUS_NAME);
+    conn.add_match(rule, |_: (), _, _| true)
+        .map_err(|e| format!("Failed to watch LibrePods: {}", e))?;
+    let rule = MatchRule::new_signal("org.freedesktop.DBus.ObjectManager", "InterfacesRemoved")
+        .with_sender(BUS_NAME);
+    conn.add_match(rule, |_: (), _, _| true)
+        .map_err(|e| format!("Failed to watch LibrePods: {}", e))?;
+
+    let mut last_line = None;
+    loop {
+        let devices: Vec<DeviceStatus> = list_devices(conn)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|d| address.is_none_or(|a| d.address.eq_ignore_ascii_case(a)))
+            .collect();
+        let line = status_line(format, &devices);
+        if last_line.as_ref() != Some(&line) {
+            let mut stdout = std::io::stdout().lock();
+            if writeln!(stdout, "{}", line)
+                .and_then(|_| stdout.flush())
+                .is_err()
+            {
+                return Ok(());
+            }
+            last_line = Some(line);
+        }
+        conn.process(WATCH_INTERVAL)
+            .map_err(|e| format!("Lost the session bus: {}", e))?;
+    }
+}
+
+/// One line for the status bar. The text is about the first connected AirPods, or the first
+/// connected device, the tooltip lists every device like the tray does.
+fn status_line(format: WatchFormat, devices: &[DeviceStatus]) -> String {
+    let tray_devices: Vec<(&DeviceStatus, TrayDevice)> =
+        devices.iter().map(|d| (d, d.tray_device())).collect();
+    let primary = tray_devices
+        .iter()
+        .filter(|(_, d)| d.connected)
+        .rev()
+        .max_by_key(|(status, d)| (status.airpods.is_some(), d.has_battery()))
+        .map(|(_, d)| d);
+
+    let percentage = primary.and_then(TrayDevice::min_battery);
+    let charging = primary.is_some_and(TrayDevice::any_bud_charging);
+    let text = match (primary, percentage) {
+        (None, _) => String::new(),
+        (Some(device), None) => device.name.clone(),
+        (Some(_), Some(level)) => format!("{}%{}", level, if charging { "⚡" } else { "" }),
+    };
+    let tooltip = if tray_devices.is_empty() {
+        "No devices connected".to_string()
+    } else {
+        tray_devices
+            .iter()
+            .map(|(status, d)| match d.listening_mode_name() {
+                Some(mode) if d.connected => format!("{}\n{}", d.summary(&status.address), mode),
+                _ => d.summary(&status.address),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let mut classes = Vec::new();
+    if primary.is_none() {
+        classes.push("disconnected");
+    }
+    if charging {
+        classes.push("charging");
+    }
+    if percentage.is_some_and(|level| level <= LOW_BATTERY) && !charging {
+        classes.push("low");
+    }
+
+    match format {
+        WatchFormat::Waybar => json!({
+            "text": text,
+            "tooltip": tooltip,
+            "class": classes,
+            "percentage": percentage.unwrap_or(0),
+        })
+        .to_string(),
+        WatchFormat::I3blocks => json!({
+            "full_text": text,
+            "short_text": percentage.map(|level| format!("{}%", level)).unwrap_or_default(),
+            "urgent": classes.contains(&"low"),
+        })
+        .to_string(),
+        WatchFormat::Plain => text,
+    }
+}
+
+/// Asks an already running instance to show its window. Returns false if there is none, and
+/// an error if it is running but can't show the window.
+pub fn show_running_window() -> Result<bool, String> {
+    let Ok(conn) = Connection::new_session() else {
+        return Ok(false);
+    };
+    match conn
+        .with_proxy(BUS_NAME, ROOT_PATH, CALL_TIMEOUT)
+        .method_call::<(), _, _, _>(DAEMON_INTERFACE, "ShowWindow", ())
+    {
+        Ok(()) => Ok(true),
+        Err(e) if e.name() == Some("org.freedesktop.DBus.Error.ServiceUnknown") => Ok(false),
+        Err(e) => Err(format!(
+            "LibrePods is already running, but can't show its window: {}",
+            e.message().unwrap_or("no reason given")
+        )),
+    }
+}
+
+/// Every device the running instance publishes, ordered by address.
+pub fn list_devices(conn: &Connection) -> Result<Vec<DeviceStatus>, String> {
+    let objects = conn
+        .with_proxy(BUS_NAME, ROOT_PATH, CALL_TIMEOUT)
+        .get_managed_objects()
+        .map_err(|e| {
+            if e.name() == Some("org.freedesktop.DBus.Error.ServiceUnknown") {
+                "LibrePods isn't running".to_string()
+            } else {
+                format!("Failed to get the devices from LibrePods: {}", e)
+            }
+        })?;
+    let mut devices: Vec<DeviceStatus> = objects
+        .values()
+        .filter_map(DeviceStatus::from_interfaces)
+        .collect();
+    devices.sort_by(|a, b| a.address.cmp(&b.address));
+    Ok(devices)
+}
+
+/// The devices a status command is about: the one asked for, or all of them.
+fn select_all(
+    devices: Vec<DeviceStatus>,
+    address: Option<&str>,
+) -> Result<Vec<DeviceStatus>, String> {
+    let Some(address) = address else {
+        return Ok(devices);
+    };
+    let selected: Vec<DeviceStatus> = devices
+        .into_iter()
+        .filter(|d| d.address.eq_ignore_ascii_case(address))
+        .collect();
+    if selected.is_empty() {
+        return Err(format!("{} isn't connected", address));
+    }
+    Ok(selected)
+}
+
+/// Calls a method of the AirPods interface on the device asked for, or on the connected
+/// AirPods if there is only one.
+fn call<A: dbus::arg::AppendAll>(
+    conn: &Connection,
+    address: Option<&str>,
+    method: &str,
+    args: A,
+) -> Result<(), String> {
+    let airpods: Vec<DeviceStatus> = list_devices(conn)?
+        .into_iter()
+        .filter(|d| d.airpods.is_some() && d.is_ready())
+        .collect();
+    let device = match address {
+        Some(address) => airpods
+            .iter()
+            .find(|d| d.address.eq_ignore_ascii_case(address))
+            .ok_or_else(|| format!("No AirPods with the address {} are connected", address))?,
+        None => match airpods.as_slice() {
+            [] => return Err("No AirPods are connected".to_string()),
+            [device] => device,
+            _ => {
+                return Err(
+                    "More than one pair of AirPods is connected, pick one with --device"
+                        .to_string(),
+                );
+            }
+        },
+    };
+    conn.with_proxy(BUS_NAME, device_path(&device.address), CALL_TIMEOUT)
+        .method_call::<(), _, _, _>(AIRPODS_INTERFACE, method, args)
+        .map_err(|e| e.message().unwrap_or("unknown error").to_string())
+}
+
+fn prop_string(props: &PropMap, name: &str) -> String {
+    props
+        .get(name)
+        .and_then(|v| v.0.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn prop_bool(props: &PropMap, name: &str) -> bool {
+    props
+        .get(name)
+        .and_then(|v| v.0.as_u64())
+        .is_some_and(|v| v != 0)
+}
+
+fn prop_strings(props: &PropMap, name: &str) -> Vec<String> {
+    props
+        .get(name)
+        .and_then(|v| v.0.as_iter())
+        .map(|values| {
+            values
+                .filter_map(|s| s.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// a{s(ys)}: component -> (level, status), read generically as the types are lost over D-Bus
+fn prop_battery(props: &PropMap, name: &str) -> BTreeMap<String, BatteryLevel> {
+    let mut battery = BTreeMap::new();
+    let Some(mut entries) = props.get(name).and_then(|v| v.0.as_iter()) else {
+        return battery;
+    };
+    while let (Some(component), Some(value)) = (entries.next(), entries.next()) {
+        let Some(mut fields) = value.as_iter() else {
+            continue;
+        };
+        let (Some(level), Some(status)) = (
+            fields.next().and_then(|l| l.as_u64()),
+            fields.next().and_then(|s| s.as_str()),
+        ) else {
+            continue;
+        };
+        if let Some(component) = component.as_str() {
+            battery.insert(
+                component.to_string(),
+                BatteryLevel {
+                    level: level as u8,
+                    status: status.to_string(),
+                },
+            );
+        }
+    }
+    battery
+}
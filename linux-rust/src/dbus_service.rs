@@ -0,0 +1,551 @@
+use crate::bluetooth::aacp::{AACPManager, ControlCommandIdentifiers};
+use crate::bluetooth::managers::{DeviceManagers, device_state_generation};
+use crate::devices::enums::{AirPodsNoiseControlMode, DeviceData, DeviceInformation, DeviceType};
+use crate::ui::messages::BluetoothUIMessage;
+use crate::utils::get_devices_path;
+use dbus::Path;
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::blocking::stdintf::org_freedesktop_dbus::{
+    ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved, PropertiesPropertiesChanged,
+    RequestNameReply,
+};
+use dbus::message::{MessageType, SignalArgs};
+use dbus_crossroads::{Crossroads, IfaceToken, MethodErr};
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const BUS_NAME: &str = "me.librepods.Daemon";
+pub const ROOT_PATH: &str = "/me/librepods";
+pub const DAEMON_INTERFACE: &str = "me.librepods.Daemon";
+pub const DEVICE_INTERFACE: &str = "me.librepods.Device";
+pub const AIRPODS_INTERFACE: &str = "me.librepods.AirPods";
+
+// after a device changed, it is compared against what was published at most this often,
+// changes go out as PropertiesChanged
+const SYNC_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Object path of a device, like `/me/librepods/devices/dev_AA_BB_CC_DD_EE_FF`.
+pub fn device_path(mac: &str) -> String {
+    format!("{}/devices/dev_{}", ROOT_PATH, mac.replace(':', "_"))
+}
+
+/// What is published about one device. Everything but the first four fields belongs to the
+/// AirPods interface and stays empty for other devices.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DeviceSnapshot {
+    address: String,
+    name: String,
+    device_type: String,
+    connection_state: String,
+    // component -> (level, status)
+    battery: HashMap<String, (u8, String)>,
+    ear_detection: Vec<String>,
+    listening_mode: String,
+    conversation_awareness: bool,
+    personalized_volume: bool,
+    information: HashMap<String, String>,
+}
+
+impl DeviceSnapshot {
+    fn is_airpods(&self) -> bool {
+        self.device_type == DeviceType::AirPods.to_string()
+    }
+
+    fn interfaces(&self) -> Vec<String> {
+        let mut interfaces = vec![DEVICE_INTERFACE.to_string()];
+        if self.is_airpods() {
+            interfaces.push(AIRPODS_INTERFACE.to_string());
+        }
+        interfaces
+    }
+
+    /// All properties by interface, as announced when the object is added.
+    fn properties(&self) -> HashMap<String, PropMap> {
+        fn property<T: RefArg + Clone + 'static>(
+            name: &str,
+            value: &T,
+        ) -> (String, Variant<Box<dyn RefArg>>) {
+            (
+                name.to_string(),
+                Variant(Box::new(value.clone()) as Box<dyn RefArg>),
+            )
+        }
+
+        let mut interfaces = HashMap::from([(
+            DEVICE_INTERFACE.to_string(),
+            PropMap::from([
+                property("Address", &self.address),
+                property("Name", &self.name),
+                property("Type", &self.device_type),
+                property("ConnectionState", &self.connection_state),
+            ]),
+        )]);
+        if self.is_airpods() {
+            interfaces.insert(
+                AIRPODS_INTERFACE.to_string(),
+                PropMap::from([
+                    property("Battery", &self.battery),
+                    property("EarDetection", &self.ear_detection),
+                    property("ListeningMode", &self.listening_mode),
+                    property("ConversationAwareness", &self.conversation_awareness),
+                    property("PersonalizedVolume", &self.personalized_volume),
+                    property("Information", &self.information),
+                ]),
+            );
+        }
+        interfaces
+    }
+
+    /// The properties of both interfaces that differ from `old`.
+    fn changes(&self, old: &DeviceSnapshot) -> (PropMap, PropMap) {
+        fn changed<T: RefArg + PartialEq + Clone + 'static>(
+            map: &mut PropMap,
+            name: &str,
+            old: &T,
+            new: &T,
+        ) {
+            if old != new {
+                map.insert(
+                    name.to_string(),
+                    Variant(Box::new(new.clone()) as Box<dyn RefArg>),
+                );
+            }
+        }
+
+        let mut device = PropMap::new();
+        changed(&mut device, "Name", &old.name, &self.name);
+        changed(
+            &mut device,
+            "ConnectionState",
+            &old.connection_state,
+            &self.connection_state,
+        );
+
+        let mut airpods = PropMap::new();
+        changed(&mut airpods, "Battery", &old.battery, &self.battery);
+        changed(
+            &mut airpods,
+            "EarDetection",
+            &old.ear_detection,
+            &self.ear_detection,
+        );
+        changed(
+            &mut airpods,
+            "ListeningMode",
+            &old.listening_mode,
+            &self.listening_mode,
+        );
+        changed(
+            &mut airpods,
+            "ConversationAwareness",
+            &old.conversation_awareness,
+            &self.conversation_awareness,
+        );
+        changed(
+            &mut airpods,
+            "PersonalizedVolume",
+            &old.personalized_volume,
+            &self.personalized_volume,
+        );
+        changed(
+            &mut airpods,
+            "Information",
+            &old.information,
+            &self.information,
+        );
+        (device, airpods)
+    }
+}
+
+/// Publishes `me.librepods.Daemon` on the session bus, with an object per device in the
+/// `DeviceManagers` map. Runs on its own thread, as the dbus crate is blocking.
+pub fn start_dbus_service(
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+) {
+    let runtime = Handle::current();
+    std::thread::spawn(move || {
+        if let Err(e) = run(device_managers, ui_tx, runtime) {
+            error!("D-Bus service stopped: {}", e);
+        }
+    });
+}
+
+fn run(
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+    runtime: Handle,
+) -> Result<(), dbus::Error> {
+    let conn = Connection::new_session()?;
+    let reply = conn.request_name(BUS_NAME, false, false, true)?;
+    if !matches!(reply, RequestNameReply::PrimaryOwner) {
+        return Err(dbus::Error::new_failed(&format!(
+            "{} is already taken, is LibrePods running twice?",
+            BUS_NAME
+        )));
+    }
+    info!("Published {} on the session bus", BUS_NAME);
+
+    let mut cr = Crossroads::new();
+    let device_iface = register_device_interface(&mut cr);
+    let airpods_iface =
+        register_airpods_interface(&mut cr, device_managers.clone(), runtime.clone());
+    let daemon_iface = register_daemon_interface(&mut cr, ui_tx);
+    let object_manager = cr.object_manager::<()>();
+    cr.insert(ROOT_PATH, &[daemon_iface, object_manager], ());
+
+    let mut published: HashMap<String, DeviceSnapshot> = HashMap::new();
+    let mut devices_file = DevicesFile::default();
+    let mut published_generation = None;
+    loop {
+        conn.channel()
+            .read_write(Some(SYNC_INTERVAL))
+            .map_err(|_| dbus::Error::new_failed("Lost the connection to the session bus"))?;
+        while let Some(msg) = conn.channel().pop_message() {
+            if msg.msg_type() == MessageType::MethodCall {
+                let _ = cr.handle_message(msg, &conn);
+            }
+        }
+
+        let generation = device_state_generation();
+        let devices_changed = devices_file.refresh();
+        if published_generation == Some(generation) && !devices_changed {
+            continue;
+        }
+        published_generation = Some(generation);
+        let snapshots = runtime.block_on(snapshot_devices(&device_managers, &devices_file.devices));
+
+        published.retain(|mac, old| {
+            if snapshots.contains_key(mac) {
+                return true;
+            }
+            debug!("Removing D-Bus object for {}", mac);
+            let path = Path::from(device_path(mac));
+            cr.remove::<DeviceSnapshot>(&path);
+            let signal = ObjectManagerInterfacesRemoved {
+                object: path,
+                interfaces: old.interfaces(),
+            };
+            let _ = conn
+                .channel()
+                .send(signal.to_emit_message(&Path::from(ROOT_PATH)));
+            false
+        });
+
+        for (mac, snapshot) in snapshots {
+            let path = Path::from(device_path(&mac));
+            let Some(old) = published.get(&mac) else {
+                debug!("Adding D-Bus object for {}", mac);
+                if snapshot.is_airpods() {
+                    cr.insert(
+                        path.clone(),
+                        &[device_iface, airpods_iface],
+                        snapshot.clone(),
+                    );
+                } else {
+                    cr.insert(path.clone(), &[device_iface], snapshot.clone());
+                }
+                let signal = ObjectManagerInterfacesAdded {
+                    object: path,
+                    interfaces: snapshot.properties(),
+                };
+                let _ = conn
+                    .channel()
+                    .send(signal.to_emit_message(&Path::from(ROOT_PATH)));
+                published.insert(mac, snapshot);
+                continue;
+            };
+            if *old == snapshot {
+                continue;
+            }
+            let (device_changes, airpods_changes) = snapshot.changes(old);
+            let mut signals = vec![(DEVICE_INTERFACE, device_changes)];
+            if snapshot.is_airpods() {
+                signals.push((AIRPODS_INTERFACE, airpods_changes));
+            }
+            for (interface, changed_properties) in signals {
+                if changed_properties.is_empty() {
+                    continue;
+                }
+                let signal = PropertiesPropertiesChanged {
+                    interface_name: interface.to_string(),
+                    changed_properties,
+                    invalidated_properties: Vec::new(),
+                };
+                let _ = conn.channel().send(signal.to_emit_message(&path));
+            }
+            if let Some(data) = cr.data_mut::<DeviceSnapshot>(&path) {
+                *data = snapshot.clone();
+            }
+            published.insert(mac, snapshot);
+        }
+    }
+}
+
+fn register_daemon_interface(
+    cr: &mut Crossroads,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+) -> IfaceToken<()> {
+    cr.register(DAEMON_INTERFACE, |b| {
+        // opens the window, or starts the UI first when running headless
+        b.method("ShowWindow", (), (), move |_, _: &mut (), ()| {
+            // the window of a headless instance can only be opened once
+            ui_tx.send(BluetoothUIMessage::OpenWindow).map_err(|_| {
+                MethodErr::failed(&"The window was closed and can't be opened again, restart LibrePods to get it back")
+            })
+        });
+    })
+}
+
+fn register_device_interface(cr: &mut Crossroads) -> IfaceToken<DeviceSnapshot> {
+    cr.register(DEVICE_INTERFACE, |b| {
+        b.property("Address")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.address.clone()));
+        b.property("Name")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.name.clone()));
+        b.property("Type")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.device_type.clone()));
+        b.property("ConnectionState")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.connection_state.clone()));
+    })
+}
+
+fn register_airpods_interface(
+    cr: &mut Crossroads,
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    runtime: Handle,
+) -> IfaceToken<DeviceSnapshot> {
+    let backend = Backend {
+        device_managers,
+        runtime,
+    };
+    cr.register(AIRPODS_INTERFACE, |b| {
+        b.property("Battery")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.battery.clone()));
+        b.property("EarDetection")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.ear_detection.clone()));
+        b.property("ListeningMode")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.listening_mode.clone()));
+        b.property("ConversationAwareness")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.conversation_awareness));
+        b.property("PersonalizedVolume")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.personalized_volume));
+        b.property("Information")
+            .get(|_, device: &mut DeviceSnapshot| Ok(device.information.clone()));
+
+        let backend_mode = backend.clone();
+        b.method(
+            "SetListeningMode",
+            ("mode",),
+            (),
+            move |_, device: &mut DeviceSnapshot, (mode,): (String,)| {
+                let mode = AirPodsNoiseControlMode::from_id(&mode).ok_or_else(|| {
+                    MethodErr::invalid_arg(&format!(
+                        "{} (expected off, noise-cancellation, transparency or adaptive)",
+                        mode
+                    ))
+                })?;
+                backend_mode.send(
+                    &device.address,
+                    "set the listening mode",
+                    |aacp| async move {
+                        aacp.send_control_command(
+                            ControlCommandIdentifiers::ListeningMode,
+                            &[mode.to_byte()],
+                        )
+                        .await
+                    },
+                )
+            },
+        );
+
+        let backend_rename = backend.clone();
+        b.method(
+            "Rename",
+            ("name",),
+            (),
+            move |_, device: &mut DeviceSnapshot, (name,): (String,)| {
+                if name.trim().is_empty() {
+                    return Err(MethodErr::invalid_arg(&"name can't be empty"));
+                }
+                backend_rename.send(&device.address, "rename", |aacp| async move {
+                    aacp.send_rename_packet(name.trim()).await
+                })
+            },
+        );
+
+        for (method, identifier) in [
+            (
+                "SetConversationAwareness",
+                ControlCommandIdentifiers::ConversationDetectConfig,
+            ),
+            (
+                "SetPersonalizedVolume",
+                ControlCommandIdentifiers::AdaptiveVolumeConfig,
+            ),
+        ] {
+            let backend = backend.clone();
+            b.method(
+                method,
+                ("enabled",),
+                (),
+                move |_, device: &mut DeviceSnapshot, (enabled,): (bool,)| {
+                    backend.send(&device.address, "change the setting", |aacp| async move {
+                        aacp.send_control_command(
+                            identifier,
+                            if enabled { &[0x01] } else { &[0x02] },
+                        )
+                        .await
+                    })
+                },
+            );
+        }
+    })
+}
+
+/// What the method handlers need to reach the devices from the D-Bus thread.
+#[derive(Clone)]
+struct Backend {
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    runtime: Handle,
+}
+
+impl Backend {
+    fn send<F, Fut>(&self, mac: &str, what: &str, send: F) -> Result<(), MethodErr>
+    where
+        F: FnOnce(Arc<AACPManager>) -> Fut,
+        Fut: Future<Output = bluer::Result<()>>,
+    {
+        let aacp = self
+            .device_managers
+            .blocking_read()
+            .get(mac)
+            .and_then(|m| m.get_aacp())
+            .ok_or_else(|| MethodErr::failed(&format!("{} is not connected", mac)))?;
+        info!("D-Bus request to {} for {}", what, mac);
+        self.runtime
+            .block_on(send(aacp))
+            .map_err(|e| MethodErr::failed(&format!("Failed to {}: {}", what, e)))
+    }
+}
+
+/// devices.json, only read again once it was written to.
+#[derive(Default)]
+struct DevicesFile {
+    modified: Option<SystemTime>,
+    devices: HashMap<String, DeviceData>,
+}
+
+impl DevicesFile {
+    /// Reloads the devices if the file changed, returns whether it did.
+    fn refresh(&mut self) -> bool {
+        let modified = std::fs::metadata(get_devices_path())
+            .and_then(|m| m.modified())
+            .ok();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        self.devices = std::fs::read_to_string(get_devices_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        true
+    }
+}
+
+async fn snapshot_devices(
+    device_managers: &RwLock<HashMap<String, DeviceManagers>>,
+    devices: &HashMap<String, DeviceData>,
+) -> HashMap<String, DeviceSnapshot> {
+    let entries: Vec<_> = device_managers
+        .read()
+        .await
+        .iter()
+        .map(|(mac, m)| (mac.clone(), m.connection_state(), m.get_aacp()))
+        .collect();
+
+    let mut snapshots = HashMap::new();
+    for (mac, connection_state, aacp) in entries {
+        let data = devices.get(&mac);
+        // devices only end up in devices.json once they sent their information, AirPods are
+        // the ones connected before that
+        let device_type = data.map_or(DeviceType::AirPods, |d| d.type_.clone());
+        let mut snapshot = DeviceSnapshot {
+            address: mac.clone(),
+            name: data.map(|d| d.name.clone()).unwrap_or_default(),
+            device_type: device_type.to_string(),
+            connection_state: connection_state.to_string(),
+            ..Default::default()
+        };
+        if let Some(DeviceInformation::AirPods(info)) = data.and_then(|d| d.information.as_ref()) {
+            // the LE keys stay out, they are secrets
+            snapshot.information = HashMap::from([
+                ("Name".to_string(), info.name.clone()),
+                ("ModelNumber".to_string(), info.model_number.clone()),
+                ("Manufacturer".to_string(), info.manufacturer.clone()),
+                ("SerialNumber".to_string(), info.serial_number.clone()),
+                ("Version1".to_string(), info.version1.clone()),
+                ("Version2".to_string(), info.version2.clone()),
+                ("Version3".to_string(), info.version3.clone()),
+                (
+                    "HardwareRevision".to_string(),
+                    info.hardware_revision.clone(),
+                ),
+                (
+                    "UpdaterIdentifier".to_string(),
+                    info.updater_identifier.clone(),
+                ),
+                (
+                    "LeftSerialNumber".to_string(),
+                    info.left_serial_number.clone(),
+                ),
+                (
+                    "RightSerialNumber".to_string(),
+                    info.right_serial_number.clone(),
+                ),
+            ]);
+        }
+        if let Some(aacp) = aacp {
+            let state = aacp.state.lock().await;
+            snapshot.battery = state
+                .battery_info
+                .iter()
+                .map(|b| {
+                    (
+                        format!("{:?}", b.component),
+                        (b.level, format!("{:?}", b.status)),
+                    )
+                })
+                .collect();
+            snapshot.ear_detection = state
+                .ear_detection_status
+                .iter()
+                .map(|s| format!("{:?}", s))
+                .collect();
+            let value_of = |identifier| {
+                state
+                    .control_command_status_list
+                    .iter()
+                    .find(|s| s.identifier == identifier)
+                    .and_then(|s| s.value.first().copied())
+            };
+            snapshot.listening_mode = value_of(ControlCommandIdentifiers::ListeningMode)
+                .map(|mode| AirPodsNoiseControlMode::from_byte(&mode).id().to_string())
+                .unwrap_or_default();
+            snapshot.conversation_awareness =
+                value_of(ControlCommandIdentifiers::ConversationDetectConfig) == Some(0x01);
+            snapshot.personalized_volume =
+                value_of(ControlCommandIdentifiers::AdaptiveVolumeConfig) == Some(0x01);
+        }
+        snapshots.insert(mac, snapshot);
+    }
+    snapshots
+}
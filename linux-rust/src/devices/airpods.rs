@@ -1,20 +1,39 @@
 use crate::bluetooth::aacp::ControlCommandIdentifiers;
-use crate::bluetooth::aacp::{AACPEvent, AACPManager, AirPodsLEKeys, ProximityKeyType, StemPressType};
+use crate::bluetooth::aacp::{AACPEvent, AACPManager, AirPodsLEKeys, ProximityKeyType};
+use crate::bluetooth::adapter::open_adapter;
+use crate::bluetooth::att::{ATTHandles, ATTManager};
+use crate::bluetooth::lifecycle::{ConnectionLifecycle, ConnectionState, device_name};
+use crate::bluetooth::transport::PacketTransport;
+use crate::devices::desired_settings::DesiredSettings;
+use crate::devices::stem_press::{StemPressSettings, perform_stem_press_action};
+use crate::head_tracking::gestures::{GestureDetector, HeadGestureAction, HeadGestureSettings};
+use crate::head_tracking::opentrack::{OpenTrackOutput, OpenTrackSettings};
+use crate::head_tracking::orientation::{HeadOrientation, recenter_requests};
 use crate::media_controller::MediaController;
+use crate::notifications::{BatteryNotifier, notify_ownership_lost};
 use crate::ui::messages::BluetoothUIMessage;
 use crate::ui::tray::MyTray;
+use crate::utils::{AttChannelStatus, check_att_channel_status, run_command};
 use bluer::Address;
 use ksni::Handle;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, Instant, sleep};
+
+// a gap this long between head tracking samples means a new tracking session
+const HEAD_TRACKING_SESSION_GAP: Duration = Duration::from_secs(1);
+// settings on the ATT channel, subscribed to and read once it opened
+const ATT_SETTINGS_HANDLES: [ATTHandles; 3] = [
+    ATTHandles::AirPodsTransparency,
+    ATTHandles::AirPodsLoudSoundReduction,
+    ATTHandles::AirPodsHearingAid,
+];
 
 pub struct AirPodsDevice {
     pub mac_address: Address,
     pub aacp_manager: AACPManager,
-    // pub att_manager: ATTManager,
     pub media_controller: Arc<Mutex<MediaController>>,
     // pub command_tx: Option<tokio::sync::mpsc::UnboundedSender<(ControlCommandIdentifiers, Vec<u8>)>>,
 }
@@ -22,40 +41,98 @@ pub struct AirPodsDevice {
 impl AirPodsDevice {
     pub async fn new(
         mac_address: Address,
+        lifecycle: &ConnectionLifecycle,
         tray_handle: Option<Handle<MyTray>>,
         ui_tx: tokio::sync::mpsc::UnboundedSender<BluetoothUIMessage>,
-    ) -> Self {
+    ) -> bluer::Result<Self> {
         info!("Creating new AirPodsDevice for {}", mac_address);
+        let session = bluer::Session::new().await?;
+        let local_mac = open_adapter(&session).await?.address().await?.to_string();
+
         let mut aacp_manager = AACPManager::new();
-        aacp_manager.connect(mac_address).await;
+        aacp_manager.connect(mac_address).await?;
+        lifecycle.set_state(ConnectionState::Handshaking).await;
 
-        // let mut att_manager = ATTManager::new();
-        // att_manager.connect(mac_address).await.expect("Failed to connect ATT");
+        if let Err(e) = Self::handshake(&aacp_manager).await {
+            aacp_manager.disconnect().await;
+            return Err(e);
+        }
 
-        if let Some(handle) = &tray_handle {
-            handle
-                .update(|tray: &mut MyTray| tray.connected = true)
-                .await;
+        let device = Self::init(
+            mac_address,
+            aacp_manager,
+            local_mac,
+            tray_handle,
+            ui_tx.clone(),
+        )
+        .await;
+
+        // BlueZ claims the ATT channel by default and the connect only fails after a timeout,
+        // so it is opened next to the connection instead of holding it up
+        tokio::spawn(connect_att(
+            mac_address,
+            device.aacp_manager.clone(),
+            lifecycle.clone(),
+            ui_tx,
+        ));
+
+        Ok(device)
+    }
+
+    /// Creates the device on top of an existing transport instead of an L2CAP socket, so the
+    /// whole stack can run without a Bluetooth adapter. `local_mac` stands in for the adapter
+    /// address.
+    pub async fn with_transport<T: PacketTransport>(
+        mac_address: Address,
+        transport: Arc<T>,
+        local_mac: String,
+        lifecycle: &ConnectionLifecycle,
+        tray_handle: Option<Handle<MyTray>>,
+        ui_tx: tokio::sync::mpsc::UnboundedSender<BluetoothUIMessage>,
+    ) -> bluer::Result<Self> {
+        info!(
+            "Creating new AirPodsDevice for {} on a custom transport",
+            mac_address
+        );
+        let mut aacp_manager = AACPManager::new();
+        aacp_manager
+            .connect_with_transport(mac_address, transport)
+            .await;
+        lifecycle.set_state(ConnectionState::Handshaking).await;
+
+        if let Err(e) = Self::handshake(&aacp_manager).await {
+            aacp_manager.disconnect().await;
+            return Err(e);
         }
 
+        Ok(Self::init(mac_address, aacp_manager, local_mac, tray_handle, ui_tx).await)
+    }
+
+    /// Sets up a freshly opened AACP channel. Runs again on every reconnect, so the order here
+    /// is the order the AirPods get it in. The packets only fail to send once the channel is
+    /// gone, which fails the connection attempt.
+    async fn handshake(aacp_manager: &AACPManager) -> bluer::Result<()> {
         info!("Sending handshake");
-        if let Err(e) = aacp_manager.send_handshake().await {
-            error!("Failed to send handshake to AirPods device: {}", e);
-        }
+        aacp_manager
+            .send_handshake()
+            .await
+            .inspect_err(|e| error!("Failed to send handshake to AirPods device: {}", e))?;
 
         sleep(Duration::from_millis(100)).await;
 
         info!("Setting feature flags");
-        if let Err(e) = aacp_manager.send_set_feature_flags_packet().await {
-            error!("Failed to set feature flags: {}", e);
-        }
+        aacp_manager
+            .send_set_feature_flags_packet()
+            .await
+            .inspect_err(|e| error!("Failed to set feature flags: {}", e))?;
 
         sleep(Duration::from_millis(100)).await;
 
         info!("Requesting notifications");
-        if let Err(e) = aacp_manager.send_notification_request().await {
-            error!("Failed to request notifications: {}", e);
-        }
+        aacp_manager
+            .send_notification_request()
+            .await
+            .inspect_err(|e| error!("Failed to request notifications: {}", e))?;
 
         info!("sending some packet");
         if let Err(e) = aacp_manager.send_some_packet().await {
@@ -63,33 +140,33 @@ impl AirPodsDevice {
         }
 
         info!("Requesting Proximity Keys: IRK and ENC_KEY");
-        if let Err(e) = aacp_manager
+        aacp_manager
             .send_proximity_keys_request(vec![ProximityKeyType::Irk, ProximityKeyType::EncKey])
             .await
-        {
-            error!("Failed to request proximity keys: {}", e);
-        }
+            .inspect_err(|e| error!("Failed to request proximity keys: {}", e))?;
+        Ok(())
+    }
 
-        info!("Enabling raw gestures (single press)");
-        if let Err(e) = aacp_manager
-            .send_control_command(ControlCommandIdentifiers::StemConfig, &[0x01, 0, 0, 0])
-            .await
-        {
+    async fn init(
+        mac_address: Address,
+        aacp_manager: AACPManager,
+        local_mac: String,
+        tray_handle: Option<Handle<MyTray>>,
+        ui_tx: tokio::sync::mpsc::UnboundedSender<BluetoothUIMessage>,
+    ) -> Self {
+        let stem_press_rx = StemPressSettings::watch(&mac_address.to_string());
+        let stem_config = stem_press_rx.borrow().stem_config();
+        info!("Enabling raw gestures (stem config {:#04x})", stem_config);
+        if let Err(e) = aacp_manager.set_stem_config(stem_config).await {
             error!("Failed to enable raw gestures: {}", e);
         }
 
-        let session = bluer::Session::new()
-            .await
-            .expect("Failed to get bluer session");
-        let adapter = session
-            .default_adapter()
-            .await
-            .expect("Failed to get default adapter");
-        let local_mac = adapter
-            .address()
-            .await
-            .expect("Failed to get adapter address")
-            .to_string();
+        // the AirPods report their settings in reply to the notification request, they are
+        // compared against what they should be once they came in
+        let aacp_manager_sync = aacp_manager.clone();
+        tokio::spawn(async move {
+            DesiredSettings::reapply(&mac_address.to_string(), &aacp_manager_sync).await;
+        });
 
         let media_controller = Arc::new(Mutex::new(MediaController::new(
             mac_address.to_string(),
@@ -102,7 +179,9 @@ impl AirPodsDevice {
         aacp_manager.set_event_channel(tx).await;
         if let Some(handle) = &tray_handle {
             handle
-                .update(|tray: &mut MyTray| tray.command_tx = Some(command_tx.clone()))
+                .update(|tray: &mut MyTray| {
+                    tray.device_mut(&mac_address.to_string()).command_tx = Some(command_tx.clone())
+                })
                 .await;
         }
 
@@ -135,7 +214,8 @@ impl AirPodsDevice {
                 if let Some(handle) = &tray_handle_clone {
                     handle
                         .update(|tray: &mut MyTray| {
-                            tray.listening_mode = Some(value[0]);
+                            tray.device_mut(&mac_address.to_string()).listening_mode =
+                                Some(value[0]);
                         })
                         .await;
                 }
@@ -152,7 +232,33 @@ impl AirPodsDevice {
                 if let Some(handle) = &tray_handle_clone {
                     handle
                         .update(|tray: &mut MyTray| {
-                            tray.allow_off_option = Some(value[0]);
+                            tray.device_mut(&mac_address.to_string()).allow_off_option =
+                                Some(value[0]);
+                        })
+                        .await;
+                }
+            }
+        });
+
+        let (auto_anc_strength_tx, mut auto_anc_strength_rx) =
+            tokio::sync::mpsc::unbounded_channel();
+        aacp_manager
+            .subscribe_to_control_command(
+                ControlCommandIdentifiers::AutoAncStrength,
+                auto_anc_strength_tx,
+            )
+            .await;
+        let tray_handle_clone = tray_handle.clone();
+        tokio::spawn(async move {
+            while let Some(value) = auto_anc_strength_rx.recv().await {
+                let Some(&strength) = value.first() else {
+                    continue;
+                };
+                if let Some(handle) = &tray_handle_clone {
+                    handle
+                        .update(|tray: &mut MyTray| {
+                            tray.device_mut(&mac_address.to_string()).auto_anc_strength =
+                                Some(strength);
                         })
                         .await;
                 }
@@ -173,7 +279,8 @@ impl AirPodsDevice {
                 if let Some(handle) = &tray_handle_clone {
                     handle
                         .update(|tray: &mut MyTray| {
-                            tray.conversation_detect_enabled = Some(value[0] == 0x01);
+                            tray.device_mut(&mac_address.to_string())
+                                .conversation_detect_enabled = Some(value[0] == 0x01);
                         })
                         .await;
                 }
@@ -205,6 +312,18 @@ impl AirPodsDevice {
         let ui_tx_clone = ui_tx.clone();
         let command_tx_clone = command_tx.clone();
         tokio::spawn(async move {
+            let mut head_orientation = HeadOrientation::new();
+            let mut last_head_tracking_sample: Option<Instant> = None;
+            let mut gesture_detector = GestureDetector::new();
+            // the settings change in the UI while samples are streaming in
+            let mut gesture_settings_rx = HeadGestureSettings::watch();
+            let mut gesture_settings = gesture_settings_rx.borrow_and_update().clone();
+            let mut opentrack_settings_rx = OpenTrackSettings::watch();
+            // marked as changed, so the output is opened with the first sample
+            opentrack_settings_rx.mark_changed();
+            let mut recenter_rx = recenter_requests(&mac_address.to_string());
+            let mut opentrack_output: Option<OpenTrackOutput> = None;
+            let mut battery_notifier = BatteryNotifier::new();
             while let Some(event) = rx.recv().await {
                 let event_clone = event.clone();
                 match event {
@@ -227,6 +346,7 @@ impl AirPodsDevice {
                         if let Some(handle) = &tray_handle {
                             handle
                                 .update(|tray: &mut MyTray| {
+                                    let tray = tray.device_mut(&mac_address.to_string());
                                     for b in &battery_info {
                                         match b.component as u8 {
                                             0x01 => {
@@ -252,6 +372,10 @@ impl AirPodsDevice {
                                 .await;
                         }
                         debug!("Updated tray with new battery info");
+                        battery_notifier.battery_changed(
+                            &device_name(&mac_address.to_string()).await,
+                            &battery_info,
+                        );
 
                         let _ = ui_tx_clone.send(BluetoothUIMessage::AACPUIEvent(
                             mac_address.to_string(),
@@ -322,12 +446,20 @@ impl AirPodsDevice {
                         let controller = mc_clone.lock().await;
                         controller.pause_all_media().await;
                         controller.deactivate_a2dp_profile().await;
+                        notify_ownership_lost(&device_name(&mac_address.to_string()).await);
                     }
                     AACPEvent::StemPress(press_type, bud_type) => {
                         info!("Stem press received: {:?} on {:?}", press_type, bud_type);
-                        if press_type == StemPressType::SinglePress {
-                            let controller = mc_clone.lock().await;
-                            controller.toggle_play_pause().await;
+                        // the mapping can be changed in the UI at any point
+                        let settings = stem_press_rx.borrow().clone();
+                        if let Some(binding) = settings.binding(press_type, bud_type) {
+                            info!("Stem press action: {}", binding.action);
+                            perform_stem_press_action(
+                                binding,
+                                &aacp_manager_clone_events,
+                                &mc_clone,
+                            )
+                            .await;
                         }
                         // Forward to UI
                         let _ = ui_tx_clone.send(BluetoothUIMessage::AACPUIEvent(
@@ -335,6 +467,66 @@ impl AirPodsDevice {
                             event_clone,
                         ));
                     }
+                    AACPEvent::HeadTracking {
+                        orientation,
+                        accel_h,
+                        accel_v,
+                    } => {
+                        // not forwarded to the UI, samples arrive far too often for that
+                        let now = Instant::now();
+                        if last_head_tracking_sample
+                            .is_none_or(|t| now.duration_since(t) > HEAD_TRACKING_SESSION_GAP)
+                        {
+                            debug!("New head tracking session, calibrating neutral pose");
+                            head_orientation.reset_calibration();
+                            gesture_detector.reset();
+                            if let Some(output) = &mut opentrack_output {
+                                output.reset();
+                            }
+                        }
+                        last_head_tracking_sample = Some(now);
+                        if recenter_rx.has_changed().unwrap_or(false) {
+                            recenter_rx.mark_unchanged();
+                            info!("Recentering head tracking");
+                            head_orientation.reset_calibration();
+                            if let Some(output) = &mut opentrack_output {
+                                output.reset();
+                            }
+                        }
+                        if gesture_settings_rx.has_changed().unwrap_or(false) {
+                            gesture_settings = gesture_settings_rx.borrow_and_update().clone();
+                        }
+                        if opentrack_settings_rx.has_changed().unwrap_or(false) {
+                            let opentrack_settings =
+                                opentrack_settings_rx.borrow_and_update().clone();
+                            opentrack_output = None;
+                            if opentrack_settings.enabled {
+                                match OpenTrackOutput::new(&opentrack_settings).await {
+                                    Ok(output) => opentrack_output = Some(output),
+                                    Err(e) => error!("Failed to open OpenTrack socket: {}", e),
+                                }
+                            }
+                        }
+                        if gesture_settings.enabled
+                            && let Some(gesture) = gesture_detector.process(accel_h, accel_v)
+                        {
+                            let action = gesture_settings.action_for(gesture);
+                            info!("Head gesture detected: {:?}, action: {}", gesture, action);
+                            perform_head_gesture_action(action, &gesture_settings, &mc_clone).await;
+                        }
+                        if head_orientation.add_calibration_sample(orientation) {
+                            let pose = head_orientation.calculate_orientation(orientation);
+                            debug!(
+                                "Head orientation: pitch={:.1}, yaw={:.1}, accel_h={}, accel_v={}",
+                                pose.pitch, pose.yaw, accel_h, accel_v
+                            );
+                            if let Some(output) = &mut opentrack_output
+                                && let Err(e) = output.send(pose).await
+                            {
+                                debug!("Failed to send pose to OpenTrack: {}", e);
+                            }
+                        }
+                    }
                     _ => {
                         debug!("Received unhandled AACP event: {:?}", event);
                         let _ = ui_tx_clone.send(BluetoothUIMessage::AACPUIEvent(
@@ -350,14 +542,116 @@ impl AirPodsDevice {
         AirPodsDevice {
             mac_address,
             aacp_manager,
-            // att_manager,
             media_controller,
             // command_tx: Some(command_tx.clone()),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Opens the ATT channel when the AirPods allow it and hands it to the device managers.
+/// Otherwise the ATT based features stay off and the UI is told which BlueZ setting is in the
+/// way.
+async fn connect_att(
+    mac_address: Address,
+    aacp_manager: AACPManager,
+    lifecycle: ConnectionLifecycle,
+    ui_tx: tokio::sync::mpsc::UnboundedSender<BluetoothUIMessage>,
+) {
+    let mut att_manager = ATTManager::new();
+    let status = match att_manager.connect(mac_address).await {
+        Ok(()) => {
+            info!("ATT channel available for {}", mac_address);
+            // the AACP channel may have dropped while waiting for this one
+            if !aacp_manager.is_connected().await {
+                att_manager.disconnect().await;
+                return;
+            }
+            subscribe_att_notifications(mac_address, &att_manager, &ui_tx).await;
+            if !lifecycle.attach_att(att_manager.clone()).await {
+                return;
+            }
+            read_att_values(mac_address, &att_manager, &ui_tx).await;
+            AttChannelStatus::Available
+        }
+        Err(e) => {
+            let status = check_att_channel_status(&e);
+            warn!("ATT channel unavailable for {}: {}", mac_address, status);
+            status
+        }
+    };
+    let _ = ui_tx.send(BluetoothUIMessage::ATTStatus(
+        mac_address.to_string(),
+        status,
+    ));
+}
+
+async fn subscribe_att_notifications(
+    mac_address: Address,
+    att_manager: &ATTManager,
+    ui_tx: &tokio::sync::mpsc::UnboundedSender<BluetoothUIMessage>,
+) {
+    for handle in ATT_SETTINGS_HANDLES {
+        info!("Subscribing to {:?} notifications", handle);
+        if let Err(e) = att_manager.enable_notifications(handle).await {
+            error!("Failed to enable {:?} notifications: {}", handle, e);
+        }
+        let (att_tx, mut att_rx) = tokio::sync::mpsc::unbounded_channel();
+        att_manager.register_listener(handle, att_tx).await;
+        let ui_tx_att = ui_tx.clone();
+        tokio::spawn(async move {
+            while let Some(value) = att_rx.recv().await {
+                let _ = ui_tx_att.send(BluetoothUIMessage::ATTNotification(
+                    mac_address.to_string(),
+                    handle as u16,
+                    value,
+                ));
+            }
+        });
+    }
+}
+
+/// Reads the ATT backed settings once connected, so they are known before any of them changes.
+/// The values reach the UI the same way notifications do.
+async fn read_att_values(
+    mac_address: Address,
+    att_manager: &ATTManager,
+    ui_tx: &tokio::sync::mpsc::UnboundedSender<BluetoothUIMessage>,
+) {
+    for handle in ATT_SETTINGS_HANDLES {
+        match att_manager.read(handle).await {
+            Ok(value) => {
+                debug!("{:?} of {}: {}", handle, mac_address, hex::encode(&value));
+                let _ = ui_tx.send(BluetoothUIMessage::ATTNotification(
+                    mac_address.to_string(),
+                    handle as u16,
+                    value,
+                ));
+            }
+            Err(e) => error!("Failed to read {:?} of {}: {}", handle, mac_address, e),
+        }
+    }
+}
+
+async fn perform_head_gesture_action(
+    action: HeadGestureAction,
+    settings: &HeadGestureSettings,
+    media_controller: &Arc<Mutex<MediaController>>,
+) {
+    match action {
+        HeadGestureAction::None => {}
+        HeadGestureAction::PlayPause => media_controller.lock().await.toggle_play_pause().await,
+        HeadGestureAction::NextTrack => media_controller.lock().await.next_track().await,
+        HeadGestureAction::PreviousTrack => media_controller.lock().await.previous_track().await,
+        HeadGestureAction::AcceptCall
+        | HeadGestureAction::DeclineCall
+        | HeadGestureAction::DismissNotification => match settings.command_for(action) {
+            Some(command) => run_command(command),
+            None => warn!("No command configured for head gesture action: {}", action),
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AirPodsInformation {
     pub name: String,
     pub model_number: String,
@@ -372,3 +666,60 @@ pub struct AirPodsInformation {
     pub version3: String,
     pub le_keys: AirPodsLEKeys,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::aacp::EarDetectionStatus;
+    use crate::bluetooth::transport::MemoryTransport;
+    use crate::simulator::{AirPodsSimulator, SimulatorScript};
+    use crate::utils::use_test_data_dir;
+    use std::collections::HashMap;
+    use tokio::sync::RwLock;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn connects_over_a_transport() {
+        use_test_data_dir();
+        let script: SimulatorScript =
+            serde_json::from_str(r#"{ "address": "AA:BB:CC:DD:EE:01" }"#).unwrap();
+        let addr: Address = script.address.parse().unwrap();
+        let (host_end, device_end) = MemoryTransport::pair();
+        tokio::spawn(AirPodsSimulator::new(device_end, script).run());
+        let (ui_tx, _ui_rx) = tokio::sync::mpsc::unbounded_channel();
+        let lifecycle = ConnectionLifecycle::new(
+            addr,
+            Arc::new(RwLock::new(HashMap::new())),
+            ui_tx.clone(),
+            None,
+        );
+
+        let device = AirPodsDevice::with_transport(
+            addr,
+            Arc::new(host_end),
+            "00:11:22:33:44:55".to_string(),
+            &lifecycle,
+            None,
+            ui_tx,
+        )
+        .await
+        .unwrap();
+
+        // the simulator answers the notification request with its state, ownership comes last
+        timeout(Duration::from_secs(5), async {
+            while !device.aacp_manager.state.lock().await.owns {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let state = device.aacp_manager.state.lock().await;
+        assert_eq!(state.battery_info.len(), 3);
+        assert_eq!(
+            state.ear_detection_status,
+            vec![EarDetectionStatus::OutOfEar; 2]
+        );
+        drop(state);
+        device.aacp_manager.disconnect().await;
+    }
+}
@@ -0,0 +1,199 @@
+use crate::bluetooth::aacp::{AACPManager, ControlCommandIdentifiers, ControlCommandStatus};
+use crate::utils::{get_desired_settings_path, load_device_preference};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::sync::mpsc::Sender;
+use tokio::time::{Duration, Instant, timeout_at};
+
+// how long the AirPods get to report the synced settings after they connected
+const SETTINGS_REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings kept in sync, in the order they are written back. The allowed modes come before
+/// the listening mode, so a mode that was just allowed again can be switched to.
+pub const SYNCED_SETTINGS: [ControlCommandIdentifiers; 12] = [
+    ControlCommandIdentifiers::ListeningModeConfigs,
+    ControlCommandIdentifiers::AllowOffOption,
+    ControlCommandIdentifiers::ListeningMode,
+    ControlCommandIdentifiers::AutoAncStrength,
+    ControlCommandIdentifiers::ConversationDetectConfig,
+    ControlCommandIdentifiers::AdaptiveVolumeConfig,
+    ControlCommandIdentifiers::OneBudAncMode,
+    ControlCommandIdentifiers::DoubleClickInterval,
+    ControlCommandIdentifiers::ClickHoldInterval,
+    ControlCommandIdentifiers::VolumeSwipeMode,
+    ControlCommandIdentifiers::VolumeSwipeInterval,
+    ControlCommandIdentifiers::ChimeVolume,
+];
+
+/// Name of a synced setting as shown in the UI.
+pub fn synced_setting_name(identifier: ControlCommandIdentifiers) -> String {
+    match identifier {
+        ControlCommandIdentifiers::ListeningModeConfigs => "Allowed listening modes".to_string(),
+        ControlCommandIdentifiers::AllowOffOption => "Off listening mode".to_string(),
+        ControlCommandIdentifiers::ListeningMode => "Listening mode".to_string(),
+        ControlCommandIdentifiers::AutoAncStrength => "Adaptive strength".to_string(),
+        ControlCommandIdentifiers::ConversationDetectConfig => "Conversation awareness".to_string(),
+        ControlCommandIdentifiers::AdaptiveVolumeConfig => "Personalized volume".to_string(),
+        ControlCommandIdentifiers::OneBudAncMode => "Noise control with one AirPod".to_string(),
+        ControlCommandIdentifiers::DoubleClickInterval => "Press speed".to_string(),
+        ControlCommandIdentifiers::ClickHoldInterval => "Press and hold duration".to_string(),
+        ControlCommandIdentifiers::VolumeSwipeMode => "Volume swipe".to_string(),
+        ControlCommandIdentifiers::VolumeSwipeInterval => "Volume swipe length".to_string(),
+        ControlCommandIdentifiers::ChimeVolume => "Chime volume".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// What the settings of one device should be, stored in `desired_settings.json` next to
+/// `devices.json`. The AirPods take over the settings of every Apple device they connect to,
+/// so the values set from here are written again whenever they connect.
+///
+/// A value is recorded whenever it is sent to the device, whether it is written back is up to
+/// the per-setting switch. The listening mode is only followed when asked to, it is meant to be
+/// changed on the AirPods themselves.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DesiredSettings {
+    pub values: HashMap<ControlCommandIdentifiers, Vec<u8>>,
+    pub keep_in_sync: HashMap<ControlCommandIdentifiers, bool>,
+}
+
+impl DesiredSettings {
+    pub fn load(mac: &str) -> Self {
+        let mut settings = load_store().remove(mac).unwrap_or_default();
+        // the Adaptive strength used to be remembered on its own in the preferences
+        if !settings
+            .values
+            .contains_key(&ControlCommandIdentifiers::AutoAncStrength)
+            && let Some(strength) = load_device_preference::<u8>(mac, "autoAncStrength")
+        {
+            settings
+                .values
+                .insert(ControlCommandIdentifiers::AutoAncStrength, vec![strength]);
+        }
+        settings
+    }
+
+    pub fn save(&self, mac: &str) {
+        let mut store = load_store();
+        store.insert(mac.to_string(), self.clone());
+        let path = get_desired_settings_path();
+        debug!("Writing desired settings for {} to {:?}", mac, path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        match serde_json::to_string(&store) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&path, json) {
+                    error!("Failed to write desired settings: {}", e);
+                }
+            }
+            Err(e) => error!("Failed to serialize desired settings: {}", e),
+        }
+    }
+
+    /// Remembers a value sent to the device. Values of settings that aren't synced are ignored.
+    /// Doesn't block: the file is updated on a thread of its own, in the order the values were
+    /// sent.
+    pub fn record(mac: &str, identifier: ControlCommandIdentifiers, value: &[u8]) {
+        static RECORDER: OnceLock<Sender<(String, ControlCommandIdentifiers, Vec<u8>)>> =
+            OnceLock::new();
+        if !SYNCED_SETTINGS.contains(&identifier) {
+            return;
+        }
+        let recorder = RECORDER.get_or_init(|| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                for (mac, identifier, value) in rx {
+                    Self::store(&mac, identifier, &value);
+                }
+            });
+            tx
+        });
+        let _ = recorder.send((mac.to_string(), identifier, value.to_vec()));
+    }
+
+    fn store(mac: &str, identifier: ControlCommandIdentifiers, value: &[u8]) {
+        let mut settings = Self::load(mac);
+        if settings.values.get(&identifier).map(Vec::as_slice) == Some(value) {
+            return;
+        }
+        settings.values.insert(identifier, value.to_vec());
+        settings.save(mac);
+    }
+
+    pub fn keeps_in_sync(&self, identifier: ControlCommandIdentifiers) -> bool {
+        self.keep_in_sync
+            .get(&identifier)
+            .copied()
+            .unwrap_or(identifier != ControlCommandIdentifiers::ListeningMode)
+    }
+
+    /// The stored values, in write order, the device reports differently. Settings the device
+    /// didn't report at all are left out, it most likely doesn't have them.
+    pub fn differences(
+        &self,
+        reported: &[ControlCommandStatus],
+    ) -> Vec<(ControlCommandIdentifiers, Vec<u8>)> {
+        SYNCED_SETTINGS
+            .into_iter()
+            .filter(|identifier| self.keeps_in_sync(*identifier))
+            .filter_map(|identifier| {
+                let desired = self.values.get(&identifier)?;
+                let current = reported.iter().find(|s| s.identifier == identifier)?;
+                (current.value != *desired).then(|| (identifier, desired.clone()))
+            })
+            .collect()
+    }
+
+    /// Writes every synced setting the device reports differently back to it. Run right after
+    /// connecting, it first waits for the device to report the settings there are values for.
+    pub async fn reapply(mac: &str, aacp_manager: &AACPManager) {
+        let settings = {
+            let mac = mac.to_string();
+            tokio::task::spawn_blocking(move || Self::load(&mac))
+                .await
+                .unwrap_or_default()
+        };
+        let deadline = Instant::now() + SETTINGS_REPORT_TIMEOUT;
+        for identifier in SYNCED_SETTINGS
+            .into_iter()
+            .filter(|identifier| settings.values.contains_key(identifier))
+        {
+            // hands out the current value right away if the device already reported it
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            aacp_manager
+                .subscribe_to_control_command(identifier, tx)
+                .await;
+            if timeout_at(deadline, rx.recv()).await.is_err() {
+                warn!("{} didn't report {}, not restoring it", mac, identifier);
+            }
+        }
+        let reported = aacp_manager
+            .state
+            .lock()
+            .await
+            .control_command_status_list
+            .clone();
+        for (identifier, value) in settings.differences(&reported) {
+            info!(
+                "Restoring {} for {} to {}",
+                identifier,
+                mac,
+                hex::encode(&value)
+            );
+            if let Err(e) = aacp_manager.send_control_command(identifier, &value).await {
+                error!("Failed to restore {}: {}", identifier, e);
+            }
+        }
+    }
+}
+
+fn load_store() -> HashMap<String, DesiredSettings> {
+    std::fs::read_to_string(get_desired_settings_path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
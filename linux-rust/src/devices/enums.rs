@@ -1,6 +1,12 @@
-use crate::bluetooth::aacp::BatteryInfo;
+use crate::bluetooth::aacp::{
+    BatteryInfo, HEADPHONE_ACCOMMODATION_BANDS, HeadphoneAccommodation, ListeningModeCycle,
+};
 use crate::devices::airpods::AirPodsInformation;
+use crate::devices::desired_settings::DesiredSettings;
+use crate::devices::hearing_aid::HearingAidSettings;
 use crate::devices::nothing::NothingInformation;
+use crate::devices::stem_press::{StemPressAction, StemPressSettings};
+use crate::devices::transparency::TransparencySettings;
 use iced::widget::combo_box;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
@@ -57,7 +63,23 @@ pub struct AirPodsState {
     pub conversation_awareness_enabled: bool,
     pub personalized_volume_enabled: bool,
     pub allow_off_mode: bool,
+    pub auto_anc_strength: Option<u8>,
+    pub listening_mode_cycle: ListeningModeCycle,
+    pub stem_press: StemPressSettings,
+    // one per stem press binding, in the same order
+    pub stem_press_action_states: Vec<combo_box::State<StemPressAction>>,
+    pub desired_settings: DesiredSettings,
     pub battery: Vec<BatteryInfo>,
+    pub head_tracking_enabled: bool,
+    pub transparency: Option<TransparencySettings>,
+    pub transparency_per_bud: bool,
+    pub loud_sound_reduction: Option<bool>,
+    pub headphone_accommodation: Option<HeadphoneAccommodation>,
+    pub accommodation_preset_state: combo_box::State<HeadphoneAccommodationPreset>,
+    // Hearing Aid is only on when both of these are
+    pub hearing_aid_enabled: bool,
+    pub hearing_assist_enabled: bool,
+    pub hearing_aid: Option<HearingAidSettings>,
 }
 
 #[derive(Clone, Debug)]
@@ -97,6 +119,110 @@ impl AirPodsNoiseControlMode {
             AirPodsNoiseControlMode::Adaptive => 0x04,
         }
     }
+
+    /// Name used outside the UI, on D-Bus and the command line.
+    pub fn id(&self) -> &'static str {
+        match self {
+            AirPodsNoiseControlMode::Off => "off",
+            AirPodsNoiseControlMode::NoiseCancellation => "noise-cancellation",
+            AirPodsNoiseControlMode::Transparency => "transparency",
+            AirPodsNoiseControlMode::Adaptive => "adaptive",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "off" => Some(AirPodsNoiseControlMode::Off),
+            "noise-cancellation" => Some(AirPodsNoiseControlMode::NoiseCancellation),
+            "transparency" => Some(AirPodsNoiseControlMode::Transparency),
+            "adaptive" => Some(AirPodsNoiseControlMode::Adaptive),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeadphoneAccommodationPreset {
+    BalancedTone,
+    VocalRange,
+    Brightness,
+    Custom,
+}
+
+impl Display for HeadphoneAccommodationPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadphoneAccommodationPreset::BalancedTone => write!(f, "Balanced Tone"),
+            HeadphoneAccommodationPreset::VocalRange => write!(f, "Vocal Range"),
+            HeadphoneAccommodationPreset::Brightness => write!(f, "Brightness"),
+            HeadphoneAccommodationPreset::Custom => write!(f, "Custom"),
+        }
+    }
+}
+
+impl HeadphoneAccommodationPreset {
+    pub const ALL: [HeadphoneAccommodationPreset; 4] = [
+        HeadphoneAccommodationPreset::BalancedTone,
+        HeadphoneAccommodationPreset::VocalRange,
+        HeadphoneAccommodationPreset::Brightness,
+        HeadphoneAccommodationPreset::Custom,
+    ];
+
+    /// Band values (0 to 100, low to high frequencies), `None` for custom.
+    pub fn eq(&self) -> Option<[f32; HEADPHONE_ACCOMMODATION_BANDS]> {
+        match self {
+            HeadphoneAccommodationPreset::BalancedTone => Some([60.0; 8]),
+            HeadphoneAccommodationPreset::VocalRange => {
+                Some([50.0, 50.0, 60.0, 70.0, 70.0, 60.0, 50.0, 50.0])
+            }
+            HeadphoneAccommodationPreset::Brightness => {
+                Some([50.0, 50.0, 50.0, 55.0, 60.0, 70.0, 75.0, 80.0])
+            }
+            HeadphoneAccommodationPreset::Custom => None,
+        }
+    }
+
+    pub fn from_eq(eq: &[f32; HEADPHONE_ACCOMMODATION_BANDS]) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.eq().as_ref() == Some(eq))
+            .unwrap_or(HeadphoneAccommodationPreset::Custom)
+    }
+}
+
+/// Adaptive noise control strength (AutoAncStrength, 0 to 100). The official UI only offers
+/// these three steps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdaptiveStrengthPreset {
+    Less,
+    Default,
+    More,
+}
+
+impl Display for AdaptiveStrengthPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdaptiveStrengthPreset::Less => write!(f, "Less"),
+            AdaptiveStrengthPreset::Default => write!(f, "Default"),
+            AdaptiveStrengthPreset::More => write!(f, "More"),
+        }
+    }
+}
+
+impl AdaptiveStrengthPreset {
+    pub const ALL: [AdaptiveStrengthPreset; 3] = [
+        AdaptiveStrengthPreset::Less,
+        AdaptiveStrengthPreset::Default,
+        AdaptiveStrengthPreset::More,
+    ];
+
+    pub fn value(&self) -> u8 {
+        match self {
+            AdaptiveStrengthPreset::Less => 25,
+            AdaptiveStrengthPreset::Default => 50,
+            AdaptiveStrengthPreset::More => 75,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
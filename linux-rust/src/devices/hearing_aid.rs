@@ -0,0 +1,357 @@
+use crate::bluetooth::aacp::{AACPManager, ControlCommandIdentifiers};
+use crate::bluetooth::att::{ATTHandles, ATTManager};
+use crate::devices::transparency::{read_transparency_settings, write_transparency_settings};
+use bluer::Result;
+use log::{info, warn};
+
+pub const HEARING_AID_BANDS: usize = 8;
+pub const BAND_FREQUENCIES: [f32; HEARING_AID_BANDS] =
+    [250.0, 500.0, 1000.0, 2000.0, 3000.0, 4000.0, 6000.0, 8000.0];
+/// Hearing loss per band, in dB HL.
+pub const LOSS_RANGE: std::ops::RangeInclusive<f32> = 0.0..=120.0;
+pub const AMPLIFICATION_RANGE: std::ops::RangeInclusive<f32> = -1.0..=1.0;
+pub const BALANCE_RANGE: std::ops::RangeInclusive<f32> = -1.0..=1.0;
+pub const TONE_RANGE: std::ops::RangeInclusive<f32> = -1.0..=1.0;
+pub const AMBIENT_NOISE_REDUCTION_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+pub const OWN_VOICE_AMPLIFICATION_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+
+// 4 header bytes, 12 floats per ear, own voice amplification
+const SETTINGS_LEN: usize = 104;
+const LEFT_OFFSET: usize = 4;
+const RIGHT_OFFSET: usize = 52;
+const OWN_VOICE_OFFSET: usize = 100;
+
+/// Value of the AirPodsHearingAid ATT handle.
+///
+/// Each ear has the hearing loss per band followed by amplification, tone, conversation boost
+/// and ambient noise reduction, all little endian f32. The AirPods derive the gain curve from
+/// the loss themselves. Amplification is stored per ear, here it is split into an overall
+/// amplification and a balance between the ears like the Android app does: the balance is
+/// added on top for the ear it leans to, so `balance = right - left` and the amplification is
+/// that of the quieter ear.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HearingAidSettings {
+    pub left_loss: [f32; HEARING_AID_BANDS],
+    pub right_loss: [f32; HEARING_AID_BANDS],
+    pub amplification: f32,
+    pub balance: f32,
+    pub tone: f32,
+    pub conversation_boost: bool,
+    pub ambient_noise_reduction: f32,
+    pub own_voice_amplification: f32,
+}
+
+fn read_f32(data: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn write_f32(data: &mut [u8], offset: usize, value: f32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+impl HearingAidSettings {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < SETTINGS_LEN {
+            return None;
+        }
+        let loss = |start: usize| {
+            let mut loss = [0.0; HEARING_AID_BANDS];
+            for (band, value) in loss.iter_mut().enumerate() {
+                *value = read_f32(data, start + band * 4);
+            }
+            loss
+        };
+        let extras = |start: usize| start + HEARING_AID_BANDS * 4;
+        let left_amplification = read_f32(data, extras(LEFT_OFFSET));
+        let right_amplification = read_f32(data, extras(RIGHT_OFFSET));
+
+        let amplification = left_amplification.min(right_amplification);
+        let balance = right_amplification - left_amplification;
+
+        // tone, conversation boost and noise reduction are always set for both ears together
+        Some(Self {
+            left_loss: loss(LEFT_OFFSET),
+            right_loss: loss(RIGHT_OFFSET),
+            amplification: amplification.clamp(-1.0, 1.0),
+            balance: balance.clamp(-1.0, 1.0),
+            tone: read_f32(data, extras(LEFT_OFFSET) + 4),
+            conversation_boost: read_f32(data, extras(LEFT_OFFSET) + 8) > 0.5,
+            ambient_noise_reduction: read_f32(data, extras(LEFT_OFFSET) + 12),
+            own_voice_amplification: read_f32(data, OWN_VOICE_OFFSET),
+        })
+    }
+
+    /// Writes the settings over a value read from the AirPods, keeping the bytes we don't
+    /// understand.
+    pub fn apply(&self, data: &mut [u8]) {
+        // the AirPods ignore the write without this
+        data[2] = 0x64;
+        let ears = [
+            (
+                LEFT_OFFSET,
+                &self.left_loss,
+                self.amplification + (-self.balance).max(0.0),
+            ),
+            (
+                RIGHT_OFFSET,
+                &self.right_loss,
+                self.amplification + self.balance.max(0.0),
+            ),
+        ];
+        for (start, loss, amplification) in ears {
+            for (band, value) in loss.iter().enumerate() {
+                write_f32(data, start + band * 4, *value);
+            }
+            let extras = start + HEARING_AID_BANDS * 4;
+            write_f32(data, extras, amplification);
+            write_f32(data, extras + 4, self.tone);
+            write_f32(
+                data,
+                extras + 8,
+                if self.conversation_boost { 1.0 } else { 0.0 },
+            );
+            write_f32(data, extras + 12, self.ambient_noise_reduction);
+        }
+        write_f32(data, OWN_VOICE_OFFSET, self.own_voice_amplification);
+    }
+}
+
+pub async fn read_hearing_aid_settings(att_manager: &ATTManager) -> Result<HearingAidSettings> {
+    let value = att_manager.read(ATTHandles::AirPodsHearingAid).await?;
+    HearingAidSettings::decode(&value).ok_or_else(|| invalid_length(value.len()))
+}
+
+pub async fn write_hearing_aid_settings(
+    att_manager: &ATTManager,
+    settings: &HearingAidSettings,
+) -> Result<()> {
+    let mut value = att_manager.read(ATTHandles::AirPodsHearingAid).await?;
+    if value.len() < SETTINGS_LEN {
+        return Err(invalid_length(value.len()));
+    }
+    settings.apply(&mut value);
+    att_manager
+        .write(ATTHandles::AirPodsHearingAid, &value)
+        .await
+}
+
+/// Turns Hearing Aid on or off. The AirPods don't allow Hearing Aid together with a customized
+/// Transparency mode, so that is switched off first when the ATT channel is available.
+pub async fn set_hearing_aid_enabled(
+    aacp_manager: &AACPManager,
+    att_manager: Option<&ATTManager>,
+    enabled: bool,
+) -> Result<()> {
+    if enabled && let Some(att_manager) = att_manager {
+        match read_transparency_settings(att_manager).await {
+            Ok(mut transparency) if transparency.enabled => {
+                info!("Disabling customized Transparency mode for Hearing Aid");
+                transparency.enabled = false;
+                write_transparency_settings(att_manager, aacp_manager, &transparency).await?;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to read transparency settings: {}", e),
+        }
+    }
+    let value = if enabled { 0x01 } else { 0x02 };
+    aacp_manager
+        .send_control_command(ControlCommandIdentifiers::HearingAid, &[0x01, value])
+        .await?;
+    aacp_manager
+        .send_control_command(ControlCommandIdentifiers::HearingAssistConfig, &[value])
+        .await
+}
+
+fn invalid_length(len: usize) -> bluer::Error {
+    bluer::Error::from(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Unexpected hearing aid settings length: {}", len),
+    ))
+}
+
+/// Hearing thresholds from an audiogram, as (frequency in Hz, loss in dB HL) points per ear.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Audiogram {
+    pub left: Vec<(f32, f32)>,
+    pub right: Vec<(f32, f32)>,
+}
+
+impl Audiogram {
+    /// Parses `frequency,left,right` rows. A header row and `#` comments are skipped, and a
+    /// cell may be left empty when an ear wasn't measured at that frequency.
+    pub fn parse_csv(input: &str) -> std::result::Result<Self, String> {
+        let mut audiogram = Audiogram::default();
+        for (index, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cells: Vec<&str> = line.split([',', ';', '\t']).map(str::trim).collect();
+            let Ok(frequency) = cells[0].parse::<f32>() else {
+                if audiogram.left.is_empty() && audiogram.right.is_empty() {
+                    continue; // header
+                }
+                return Err(format!(
+                    "Line {}: invalid frequency '{}'",
+                    index + 1,
+                    cells[0]
+                ));
+            };
+            if cells.len() != 3 {
+                return Err(format!(
+                    "Line {}: expected frequency, left and right",
+                    index + 1
+                ));
+            }
+            if !frequency.is_finite() || frequency <= 0.0 {
+                return Err(format!(
+                    "Line {}: frequency must be a positive number",
+                    index + 1
+                ));
+            }
+            for (cell, points) in [
+                (cells[1], &mut audiogram.left),
+                (cells[2], &mut audiogram.right),
+            ] {
+                if cell.is_empty() {
+                    continue;
+                }
+                // "nan" and "inf" parse as well, but aren't a hearing loss
+                let loss = cell
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|loss| loss.is_finite())
+                    .ok_or_else(|| format!("Line {}: invalid value '{}'", index + 1, cell))?;
+                points.push((frequency, loss));
+            }
+        }
+        if audiogram.left.is_empty() || audiogram.right.is_empty() {
+            return Err("The audiogram needs values for both ears".to_string());
+        }
+        for points in [&mut audiogram.left, &mut audiogram.right] {
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+        Ok(audiogram)
+    }
+
+    pub fn left_loss(&self) -> [f32; HEARING_AID_BANDS] {
+        loss_at_bands(&self.left)
+    }
+
+    pub fn right_loss(&self) -> [f32; HEARING_AID_BANDS] {
+        loss_at_bands(&self.right)
+    }
+}
+
+/// Interpolates the measured points onto the hearing aid bands, linearly over octaves.
+/// Bands outside the measured range take the value of the nearest measurement.
+fn loss_at_bands(points: &[(f32, f32)]) -> [f32; HEARING_AID_BANDS] {
+    let mut loss = [0.0; HEARING_AID_BANDS];
+    for (value, frequency) in loss.iter_mut().zip(BAND_FREQUENCIES) {
+        let above = points.iter().position(|(f, _)| *f >= frequency);
+        let interpolated = match above {
+            Some(0) => points[0].1,
+            None => points[points.len() - 1].1,
+            Some(i) => {
+                let (f0, l0) = points[i - 1];
+                let (f1, l1) = points[i];
+                let t = (frequency / f0).log2() / (f1 / f0).log2();
+                l0 + (l1 - l0) * t
+            }
+        };
+        *value = interpolated.clamp(*LOSS_RANGE.start(), *LOSS_RANGE.end());
+    }
+    loss
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_amplification(left: f32, right: f32) -> Vec<u8> {
+        let mut data = vec![0u8; SETTINGS_LEN];
+        write_f32(&mut data, LEFT_OFFSET + HEARING_AID_BANDS * 4, left);
+        write_f32(&mut data, RIGHT_OFFSET + HEARING_AID_BANDS * 4, right);
+        data
+    }
+
+    #[test]
+    fn per_ear_amplification_survives_a_write() {
+        for (left, right) in [(-0.5, 0.5), (0.5, -0.5), (0.0, 0.0), (-1.0, -0.25)] {
+            let data = with_amplification(left, right);
+            let settings = HearingAidSettings::decode(&data).unwrap();
+            let mut written = data.clone();
+            settings.apply(&mut written);
+            let read_back = HearingAidSettings::decode(&written).unwrap();
+            assert_eq!(
+                read_f32(&written, LEFT_OFFSET + HEARING_AID_BANDS * 4),
+                left
+            );
+            assert_eq!(
+                read_f32(&written, RIGHT_OFFSET + HEARING_AID_BANDS * 4),
+                right
+            );
+            assert_eq!(read_back, settings);
+        }
+    }
+
+    #[test]
+    fn balance_works_at_zero_amplification() {
+        let settings = HearingAidSettings {
+            balance: 0.5,
+            ..Default::default()
+        };
+        let mut data = vec![0u8; SETTINGS_LEN];
+        settings.apply(&mut data);
+        assert_eq!(read_f32(&data, LEFT_OFFSET + HEARING_AID_BANDS * 4), 0.0);
+        assert_eq!(read_f32(&data, RIGHT_OFFSET + HEARING_AID_BANDS * 4), 0.5);
+    }
+
+    #[test]
+    fn interpolates_over_octaves() {
+        let loss = loss_at_bands(&[(500.0, 20.0), (2000.0, 40.0), (4000.0, 70.0)]);
+        // 3000 Hz sits log2(1.5) octaves above 2000 Hz
+        let expected = [
+            20.0,
+            20.0,
+            30.0,
+            40.0,
+            40.0 + 30.0 * 1.5f32.log2(),
+            70.0,
+            70.0,
+            70.0,
+        ];
+        for (band, (loss, expected)) in loss.iter().zip(expected).enumerate() {
+            assert!(
+                (loss - expected).abs() < 1e-3,
+                "band {}: {} != {}",
+                band,
+                loss,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn clamps_to_the_loss_range() {
+        let loss = loss_at_bands(&[(1000.0, -10.0), (4000.0, 150.0)]);
+        assert_eq!(loss[0], 0.0);
+        assert_eq!(loss[HEARING_AID_BANDS - 1], 120.0);
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_numbers() {
+        let error = Audiogram::parse_csv("Hz,Left,Right\n500,20,25\n1000,nan,30").unwrap_err();
+        assert!(error.starts_with("Line 3:"), "{}", error);
+        let error = Audiogram::parse_csv("500,20,inf").unwrap_err();
+        assert!(error.starts_with("Line 1:"), "{}", error);
+        let error = Audiogram::parse_csv("500,20,25\ninf,20,25").unwrap_err();
+        assert!(error.starts_with("Line 2:"), "{}", error);
+        assert!(Audiogram::parse_csv("Hz,Left,Right\n500,20,25\n1000,,30").is_ok());
+    }
+}
@@ -0,0 +1,26 @@
+use crate::bluetooth::att::{ATTHandles, ATTManager};
+use bluer::Result;
+
+/// The AirPodsLoudSoundReduction ATT handle holds a single byte, 1 when Loud Sound Reduction
+/// is on and 0 when it is off.
+pub fn decode_loud_sound_reduction(value: &[u8]) -> Option<bool> {
+    value.first().map(|b| *b != 0)
+}
+
+pub async fn read_loud_sound_reduction(att_manager: &ATTManager) -> Result<bool> {
+    let value = att_manager
+        .read(ATTHandles::AirPodsLoudSoundReduction)
+        .await?;
+    decode_loud_sound_reduction(&value).ok_or_else(|| {
+        bluer::Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Empty Loud Sound Reduction value",
+        ))
+    })
+}
+
+pub async fn write_loud_sound_reduction(att_manager: &ATTManager, enabled: bool) -> Result<()> {
+    att_manager
+        .write(ATTHandles::AirPodsLoudSoundReduction, &[enabled as u8])
+        .await
+}
@@ -1,3 +1,8 @@
 pub mod airpods;
+pub mod desired_settings;
 pub mod enums;
+pub mod hearing_aid;
+pub mod loud_sound_reduction;
 pub(crate) mod nothing;
+pub mod stem_press;
+pub mod transparency;
@@ -1,11 +1,14 @@
 use crate::bluetooth::att::{ATTHandles, ATTManager};
+use crate::bluetooth::lifecycle::{ConnectionLifecycle, ConnectionState};
+use crate::bluetooth::transport::PacketTransport;
 use crate::devices::enums::{DeviceData, DeviceInformation, DeviceType};
 use crate::ui::messages::BluetoothUIMessage;
 use crate::utils::get_devices_path;
 use bluer::Address;
-use log::{debug, info};
+use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::time::sleep;
@@ -24,14 +27,37 @@ pub struct NothingDevice {
 impl NothingDevice {
     pub async fn new(
         mac_address: Address,
+        lifecycle: &ConnectionLifecycle,
         ui_tx: mpsc::UnboundedSender<BluetoothUIMessage>,
-    ) -> Self {
+    ) -> bluer::Result<Self> {
         let mut att_manager = ATTManager::new();
-        att_manager
-            .connect(mac_address)
-            .await
-            .expect("Failed to connect");
+        att_manager.connect(mac_address).await?;
+        lifecycle.set_state(ConnectionState::Handshaking).await;
+
+        Self::init(mac_address, att_manager, ui_tx).await
+    }
+
+    /// Creates the device on top of an existing transport instead of an L2CAP socket.
+    pub async fn with_transport<T: PacketTransport>(
+        mac_address: Address,
+        transport: Arc<T>,
+        lifecycle: &ConnectionLifecycle,
+        ui_tx: mpsc::UnboundedSender<BluetoothUIMessage>,
+    ) -> bluer::Result<Self> {
+        let mut att_manager = ATTManager::new();
+        att_manager.connect_with_transport(transport).await;
+        lifecycle.set_state(ConnectionState::Handshaking).await;
+
+        Self::init(mac_address, att_manager, ui_tx).await
+    }
 
+    /// Runs again on every reconnect. Fails, and stops the manager, if the information requests
+    /// can't be sent as the channel is gone.
+    async fn init(
+        mac_address: Address,
+        att_manager: ATTManager,
+        ui_tx: mpsc::UnboundedSender<BluetoothUIMessage>,
+    ) -> bluer::Result<Self> {
         let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
 
         att_manager
@@ -60,28 +86,11 @@ impl NothingDevice {
             }
         };
 
-        // Request version information
-        att_manager
-            .write(
-                ATTHandles::NothingEverything,
-                &[
-                    0x55, 0x20, 0x01, 0x42, 0xC0, 0x00, 0x00, 0x00, 0x00,
-                    0x00, // something, idk
-                ],
-            )
-            .await
-            .expect("Failed to write");
-
-        sleep(Duration::from_millis(100)).await;
-
-        // Request serial number
-        att_manager
-            .write(
-                ATTHandles::NothingEverything,
-                &[0x55, 0x20, 0x01, 0x06, 0xC0, 0x00, 0x00, 0x13, 0x00, 0x00],
-            )
-            .await
-            .expect("Failed to write");
+        if let Err(e) = Self::request_information(&att_manager).await {
+            error!("Failed to request information from {}: {}", mac_address, e);
+            att_manager.disconnect().await;
+            return Err(e);
+        }
 
         // let ui_tx_clone = ui_tx.clone();
         let information_l = information.clone();
@@ -171,9 +180,113 @@ impl NothingDevice {
             }
         });
 
-        NothingDevice {
+        Ok(NothingDevice {
             att_manager,
             information,
+        })
+    }
+
+    async fn request_information(att_manager: &ATTManager) -> bluer::Result<()> {
+        // Request version information
+        att_manager
+            .write(
+                ATTHandles::NothingEverything,
+                &[
+                    0x55, 0x20, 0x01, 0x42, 0xC0, 0x00, 0x00, 0x00, 0x00,
+                    0x00, // something, idk
+                ],
+            )
+            .await?;
+
+        sleep(Duration::from_millis(100)).await;
+
+        // Request serial number
+        att_manager
+            .write(
+                ATTHandles::NothingEverything,
+                &[0x55, 0x20, 0x01, 0x06, 0xC0, 0x00, 0x00, 0x13, 0x00, 0x00],
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::transport::MemoryTransport;
+    use crate::utils::use_test_data_dir;
+    use bluer::ErrorKind;
+    use tokio::sync::RwLock;
+
+    const WRITE_REQUEST: u8 = 0x12;
+    const WRITE_RESPONSE: u8 = 0x13;
+    const ERROR_RESPONSE: u8 = 0x01;
+    // Write Not Permitted
+    const ATT_ERROR_CODE: u8 = 0x03;
+
+    /// Plays the device: answers every write request, with an error when `fail` is set, until
+    /// the host closes the link. Returns the written values.
+    async fn answer_writes(transport: MemoryTransport, fail: bool) -> Vec<Vec<u8>> {
+        let mut writes = Vec::new();
+        let mut buf = [0u8; 512];
+        while let Ok(n @ 1..) = transport.recv(&mut buf).await {
+            let pdu = &buf[..n];
+            if pdu[0] != WRITE_REQUEST {
+                continue;
+            }
+            writes.push(pdu[3..].to_vec());
+            let response = if fail {
+                vec![
+                    ERROR_RESPONSE,
+                    WRITE_REQUEST,
+                    pdu[1],
+                    pdu[2],
+                    ATT_ERROR_CODE,
+                ]
+            } else {
+                vec![WRITE_RESPONSE]
+            };
+            if transport.send(&response).await.is_err() {
+                break;
+            }
         }
+        writes
+    }
+
+    async fn connect(fail: bool) -> (bluer::Result<NothingDevice>, Vec<Vec<u8>>) {
+        use_test_data_dir();
+        let addr = Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02]);
+        let (host_end, device_end) = MemoryTransport::pair();
+        let device_side = tokio::spawn(answer_writes(device_end, fail));
+        let (ui_tx, _ui_rx) = mpsc::unbounded_channel();
+        let lifecycle = ConnectionLifecycle::new(
+            addr,
+            Arc::new(RwLock::new(HashMap::new())),
+            ui_tx.clone(),
+            None,
+        );
+
+        let device =
+            NothingDevice::with_transport(addr, Arc::new(host_end), &lifecycle, ui_tx).await;
+        if let Ok(device) = &device {
+            device.att_manager.disconnect().await;
+        }
+        (device, device_side.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn requests_information_over_a_transport() {
+        let (device, writes) = connect(false).await;
+        assert!(device.is_ok());
+        assert_eq!(writes.len(), 2);
+        assert!(writes[0].starts_with(&[0x55, 0x20, 0x01, 0x42, 0xC0]));
+        assert!(writes[1].starts_with(&[0x55, 0x20, 0x01, 0x06, 0xC0]));
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_device_rejects_the_request() {
+        let (device, writes) = connect(true).await;
+        assert!(matches!(device, Err(e) if e.kind == ErrorKind::NotPermitted));
+        assert_eq!(writes.len(), 1);
     }
 }
@@ -0,0 +1,254 @@
+use crate::bluetooth::aacp::{AACPManager, StemPressBudType, StemPressType};
+use crate::media_controller::MediaController;
+use crate::utils::{load_device_preference, run_command, save_device_preference};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex, watch};
+
+const PREFERENCE_KEY: &str = "stemPressActions";
+
+// the settings in use per device, loaded the first time a device asks for them
+static CURRENT: OnceLock<std::sync::Mutex<HashMap<String, watch::Sender<StemPressSettings>>>> =
+    OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StemPressAction {
+    /// Leave the press to the AirPods, so it does whatever it does with a phone. The press type
+    /// is reported for both buds at once, so when the other bud has an action this one gets
+    /// the AirPods behaviour from us instead, see `for_press`.
+    #[default]
+    AirPodsDefault,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    CycleListeningMode,
+    ToggleMicMute,
+    RunCommand,
+    EmitKey,
+}
+
+impl StemPressAction {
+    pub const ALL: [StemPressAction; 8] = [
+        StemPressAction::AirPodsDefault,
+        StemPressAction::PlayPause,
+        StemPressAction::NextTrack,
+        StemPressAction::PreviousTrack,
+        StemPressAction::CycleListeningMode,
+        StemPressAction::ToggleMicMute,
+        StemPressAction::RunCommand,
+        StemPressAction::EmitKey,
+    ];
+
+    /// What the AirPods themselves do for a press type.
+    pub fn for_press(press: StemPressType) -> Self {
+        match press {
+            StemPressType::SinglePress => StemPressAction::PlayPause,
+            StemPressType::DoublePress => StemPressAction::NextTrack,
+            StemPressType::TriplePress => StemPressAction::PreviousTrack,
+            StemPressType::LongPress => StemPressAction::CycleListeningMode,
+        }
+    }
+}
+
+impl std::fmt::Display for StemPressAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StemPressAction::AirPodsDefault => write!(f, "AirPods default"),
+            StemPressAction::PlayPause => write!(f, "Play/Pause"),
+            StemPressAction::NextTrack => write!(f, "Next track"),
+            StemPressAction::PreviousTrack => write!(f, "Previous track"),
+            StemPressAction::CycleListeningMode => write!(f, "Cycle listening mode"),
+            StemPressAction::ToggleMicMute => write!(f, "Toggle microphone mute"),
+            StemPressAction::RunCommand => write!(f, "Run command"),
+            StemPressAction::EmitKey => write!(f, "Press key"),
+        }
+    }
+}
+
+/// What one press on one bud does. `command` is used by RunCommand and `key` by EmitKey, both
+/// are kept when switching actions so nothing typed is lost.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StemPressBinding {
+    pub press: StemPressType,
+    pub bud: StemPressBudType,
+    #[serde(default)]
+    pub action: StemPressAction,
+    #[serde(default)]
+    pub command: String,
+    #[serde(default)]
+    pub key: String,
+}
+
+/// Stem press actions of one device, stored under `stemPressActions` in its preferences.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StemPressSettings {
+    pub bindings: Vec<StemPressBinding>,
+}
+
+impl Default for StemPressSettings {
+    fn default() -> Self {
+        let mut bindings = Vec::new();
+        for bud in StemPressBudType::ALL {
+            for press in StemPressType::ALL {
+                bindings.push(StemPressBinding {
+                    press,
+                    bud,
+                    // single presses always toggled playback, keep that
+                    action: if press == StemPressType::SinglePress {
+                        StemPressAction::PlayPause
+                    } else {
+                        StemPressAction::AirPodsDefault
+                    },
+                    command: String::new(),
+                    key: String::new(),
+                });
+            }
+        }
+        Self { bindings }
+    }
+}
+
+impl StemPressSettings {
+    pub fn load(mac: &str) -> Self {
+        let mut settings: Self = load_device_preference(mac, PREFERENCE_KEY).unwrap_or_default();
+        // fill in presses missing from an older or hand edited file
+        for default in Self::default().bindings {
+            if settings.binding(default.press, default.bud).is_none() {
+                settings.bindings.push(default);
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self, mac: &str) {
+        save_device_preference(
+            mac,
+            PREFERENCE_KEY,
+            serde_json::to_value(self).unwrap_or_default(),
+        );
+    }
+
+    /// The settings in use for a device, its preferences are only read the first time.
+    pub fn current(mac: &str) -> Self {
+        Self::with_sender(mac, |sender| sender.borrow().clone())
+    }
+
+    /// Follows the settings of a device as the UI changes them.
+    pub fn watch(mac: &str) -> watch::Receiver<Self> {
+        Self::with_sender(mac, |sender| sender.subscribe())
+    }
+
+    /// Puts the settings of a device in use right away, without saving them.
+    pub fn publish(&self, mac: &str) {
+        Self::with_sender(mac, |sender| sender.send_replace(self.clone()));
+    }
+
+    fn with_sender<R>(mac: &str, f: impl FnOnce(&watch::Sender<Self>) -> R) -> R {
+        let mut senders = CURRENT
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let sender = senders
+            .entry(mac.to_string())
+            .or_insert_with(|| watch::Sender::new(Self::load(mac)));
+        f(sender)
+    }
+
+    pub fn binding(
+        &self,
+        press: StemPressType,
+        bud: StemPressBudType,
+    ) -> Option<&StemPressBinding> {
+        self.bindings
+            .iter()
+            .find(|b| b.press == press && b.bud == bud)
+    }
+
+    pub fn binding_mut(
+        &mut self,
+        press: StemPressType,
+        bud: StemPressBudType,
+    ) -> Option<&mut StemPressBinding> {
+        self.bindings
+            .iter_mut()
+            .find(|b| b.press == press && b.bud == bud)
+    }
+
+    /// The StemConfig value: presses with an action on either bud are reported to us, the rest
+    /// stay with the AirPods.
+    pub fn stem_config(&self) -> u8 {
+        self.bindings
+            .iter()
+            .filter(|b| b.action != StemPressAction::AirPodsDefault)
+            .fold(0, |mask, b| mask | b.press.stem_config_bit())
+    }
+}
+
+/// Key names are passed to a shell, only allow what key names are made of.
+fn is_valid_key_name(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '-'))
+}
+
+/// There is no portable way to inject input, so keys go through wtype on Wayland and xdotool on
+/// X11, with xdotool/ydotool style key names like `XF86AudioMute` or `ctrl+alt+t`.
+fn emit_key(key: &str) {
+    let key = key.trim();
+    if !is_valid_key_name(key) {
+        warn!("Invalid key name for stem press: '{}'", key);
+        return;
+    }
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut args = String::new();
+        let (modifiers, key) = key.rsplit_once('+').unwrap_or(("", key));
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            args.push_str(&format!(" -M {}", modifier));
+        }
+        args.push_str(&format!(" -k {}", key));
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            args.push_str(&format!(" -m {}", modifier));
+        }
+        run_command(&format!("wtype{}", args));
+    } else {
+        run_command(&format!("xdotool key {}", key));
+    }
+}
+
+pub async fn perform_stem_press_action(
+    binding: &StemPressBinding,
+    aacp_manager: &AACPManager,
+    media_controller: &Arc<Mutex<MediaController>>,
+) {
+    // a press only reaches us with the default when the other bud diverted the press type
+    let action = match binding.action {
+        StemPressAction::AirPodsDefault => StemPressAction::for_press(binding.press),
+        action => action,
+    };
+    match action {
+        StemPressAction::AirPodsDefault => {}
+        StemPressAction::PlayPause => media_controller.lock().await.toggle_play_pause().await,
+        StemPressAction::NextTrack => media_controller.lock().await.next_track().await,
+        StemPressAction::PreviousTrack => media_controller.lock().await.previous_track().await,
+        StemPressAction::CycleListeningMode => {
+            if let Err(e) = aacp_manager.cycle_listening_mode().await {
+                error!("Failed to cycle listening mode: {}", e);
+            }
+        }
+        StemPressAction::ToggleMicMute => {
+            media_controller.lock().await.toggle_microphone_mute().await
+        }
+        StemPressAction::RunCommand => match binding.command.trim() {
+            "" => warn!(
+                "No command configured for {} on the {} bud",
+                binding.press, binding.bud
+            ),
+            command => run_command(command),
+        },
+        StemPressAction::EmitKey => emit_key(&binding.key),
+    }
+}
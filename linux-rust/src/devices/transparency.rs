@@ -0,0 +1,118 @@
+use crate::bluetooth::aacp::AACPManager;
+use crate::bluetooth::att::{ATTHandles, ATTManager};
+use bluer::Result;
+
+pub const TRANSPARENCY_EQ_BANDS: usize = 8;
+pub const EQ_RANGE: std::ops::RangeInclusive<f32> = 0.0..=100.0;
+pub const AMPLIFICATION_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+pub const TONE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+pub const AMBIENT_NOISE_REDUCTION_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+
+// enabled + 12 floats per bud
+const BASE_LEN: usize = 4 + 2 * BUD_LEN;
+const BUD_LEN: usize = (TRANSPARENCY_EQ_BANDS + 4) * 4;
+
+/// Customized Transparency mode for one bud.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudTransparency {
+    pub eq: [f32; TRANSPARENCY_EQ_BANDS],
+    pub amplification: f32,
+    pub tone: f32,
+    pub conversation_boost: bool,
+    pub ambient_noise_reduction: f32,
+}
+
+impl Default for BudTransparency {
+    fn default() -> Self {
+        Self {
+            eq: [50.0; TRANSPARENCY_EQ_BANDS],
+            amplification: 1.0,
+            tone: 1.0,
+            conversation_boost: false,
+            ambient_noise_reduction: 0.0,
+        }
+    }
+}
+
+/// Value of the AirPodsTransparency ATT handle.
+///
+/// Everything is a little endian f32: the enabled flag, then the left bud and the right bud
+/// (8 EQ bands, amplification, tone, conversation boost, ambient noise reduction). Newer
+/// firmware appends an own voice amplification value, which is kept as is.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TransparencySettings {
+    pub enabled: bool,
+    pub left: BudTransparency,
+    pub right: BudTransparency,
+    pub own_voice_amplification: Option<f32>,
+}
+
+impl TransparencySettings {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < BASE_LEN {
+            return None;
+        }
+        let floats: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        let bud = |start: usize| {
+            let mut eq = [0.0; TRANSPARENCY_EQ_BANDS];
+            eq.copy_from_slice(&floats[start..start + TRANSPARENCY_EQ_BANDS]);
+            let rest = &floats[start + TRANSPARENCY_EQ_BANDS..];
+            BudTransparency {
+                eq,
+                amplification: rest[0],
+                tone: rest[1],
+                conversation_boost: rest[2] > 0.5,
+                ambient_noise_reduction: rest[3],
+            }
+        };
+        let bud_floats = BUD_LEN / 4;
+        Some(Self {
+            enabled: floats[0] > 0.5,
+            left: bud(1),
+            right: bud(1 + bud_floats),
+            own_voice_amplification: floats.get(1 + 2 * bud_floats).copied(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut floats = Vec::with_capacity(BASE_LEN / 4 + 1);
+        floats.push(if self.enabled { 1.0 } else { 0.0 });
+        for bud in [&self.left, &self.right] {
+            floats.extend_from_slice(&bud.eq);
+            floats.push(bud.amplification);
+            floats.push(bud.tone);
+            floats.push(if bud.conversation_boost { 1.0 } else { 0.0 });
+            floats.push(bud.ambient_noise_reduction);
+        }
+        if let Some(own_voice) = self.own_voice_amplification {
+            floats.push(own_voice);
+        }
+        floats.iter().flat_map(|f| f.to_le_bytes()).collect()
+    }
+}
+
+pub async fn read_transparency_settings(att_manager: &ATTManager) -> Result<TransparencySettings> {
+    let value = att_manager.read(ATTHandles::AirPodsTransparency).await?;
+    TransparencySettings::decode(&value).ok_or_else(|| {
+        bluer::Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Unexpected transparency settings length: {}", value.len()),
+        ))
+    })
+}
+
+/// Writes the settings, then sends the Headphone Accommodation packet which the AirPods need
+/// before they apply the new transparency settings.
+pub async fn write_transparency_settings(
+    att_manager: &ATTManager,
+    aacp_manager: &AACPManager,
+    settings: &TransparencySettings,
+) -> Result<()> {
+    att_manager
+        .write(ATTHandles::AirPodsTransparency, &settings.encode())
+        .await?;
+    aacp_manager.resend_headphone_accommodation().await
+}
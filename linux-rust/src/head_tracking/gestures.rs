@@ -0,0 +1,443 @@
+use crate::utils::{SharedSetting, load_app_setting, save_app_setting};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+const SMOOTHING_WINDOW: usize = 5;
+const HISTORY_LEN: usize = 100;
+const MIN_SAMPLES: usize = 4;
+const MIN_THRESHOLD: f64 = 100.0;
+const MAX_THRESHOLD: f64 = 175.0;
+const MIN_EXTREME_AMPLITUDE: f64 = 400.0;
+const FULL_AMPLITUDE: f64 = 600.0;
+const INTERVAL_HISTORY: usize = 5;
+const REQUIRED_EXTREMES: usize = 3;
+const CONFIDENCE_THRESHOLD: f64 = 0.7;
+// ignore movement for a moment after a detection, so one nod doesn't fire twice
+const COOLDOWN: Duration = Duration::from_millis(1500);
+
+const SETTINGS_KEY: &str = "head_gestures";
+
+static CURRENT: SharedSetting<HeadGestureSettings> = SharedSetting::new(HeadGestureSettings::load);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Nod,
+    Shake,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HeadGestureAction {
+    #[default]
+    None,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    AcceptCall,
+    DeclineCall,
+    DismissNotification,
+}
+
+impl HeadGestureAction {
+    pub const ALL: [HeadGestureAction; 7] = [
+        HeadGestureAction::None,
+        HeadGestureAction::PlayPause,
+        HeadGestureAction::NextTrack,
+        HeadGestureAction::PreviousTrack,
+        HeadGestureAction::AcceptCall,
+        HeadGestureAction::DeclineCall,
+        HeadGestureAction::DismissNotification,
+    ];
+
+    /// Whether the action only runs the command configured for it.
+    pub fn needs_command(&self) -> bool {
+        matches!(
+            self,
+            HeadGestureAction::AcceptCall
+                | HeadGestureAction::DeclineCall
+                | HeadGestureAction::DismissNotification
+        )
+    }
+}
+
+impl std::fmt::Display for HeadGestureAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadGestureAction::None => write!(f, "Do nothing"),
+            HeadGestureAction::PlayPause => write!(f, "Play/Pause"),
+            HeadGestureAction::NextTrack => write!(f, "Next track"),
+            HeadGestureAction::PreviousTrack => write!(f, "Previous track"),
+            HeadGestureAction::AcceptCall => write!(f, "Accept call"),
+            HeadGestureAction::DeclineCall => write!(f, "Decline call"),
+            HeadGestureAction::DismissNotification => write!(f, "Dismiss notification"),
+        }
+    }
+}
+
+/// Head gesture configuration, stored under `head_gestures` in the app settings.
+///
+/// There is no desktop-wide API for calls or notifications, so those actions run the
+/// configured shell command instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadGestureSettings {
+    pub enabled: bool,
+    pub nod: HeadGestureAction,
+    pub shake: HeadGestureAction,
+    pub accept_call_command: String,
+    pub decline_call_command: String,
+    pub dismiss_notification_command: String,
+}
+
+impl Default for HeadGestureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nod: HeadGestureAction::PlayPause,
+            shake: HeadGestureAction::NextTrack,
+            accept_call_command: String::new(),
+            decline_call_command: String::new(),
+            dismiss_notification_command: String::new(),
+        }
+    }
+}
+
+impl HeadGestureSettings {
+    pub fn load() -> Self {
+        load_app_setting(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        save_app_setting(SETTINGS_KEY, serde_json::to_value(self).unwrap_or_default());
+    }
+
+    /// The settings in use, the file is only read the first time.
+    pub fn current() -> Self {
+        CURRENT.get()
+    }
+
+    pub fn watch() -> watch::Receiver<Self> {
+        CURRENT.watch()
+    }
+
+    /// Puts the settings in use right away, without saving them.
+    pub fn publish(&self) {
+        CURRENT.publish(self.clone());
+    }
+
+    pub fn action_for(&self, gesture: Gesture) -> HeadGestureAction {
+        match gesture {
+            Gesture::Nod => self.nod,
+            Gesture::Shake => self.shake,
+        }
+    }
+
+    /// The actions a gesture can be bound to, the command based ones once a command is set.
+    pub fn available_actions(&self) -> Vec<HeadGestureAction> {
+        HeadGestureAction::ALL
+            .into_iter()
+            .filter(|action| !action.needs_command() || self.command_for(*action).is_some())
+            .collect()
+    }
+
+    /// The shell command bound to an action, if the action is command based and one is set.
+    pub fn command_for(&self, action: HeadGestureAction) -> Option<&str> {
+        let command = match action {
+            HeadGestureAction::AcceptCall => &self.accept_call_command,
+            HeadGestureAction::DeclineCall => &self.decline_call_command,
+            HeadGestureAction::DismissNotification => &self.dismiss_notification_command,
+            _ => return None,
+        };
+        Some(command.trim()).filter(|c| !c.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Extreme {
+    index: usize,
+    value: f64,
+}
+
+/// Peak/trough tracking for one acceleration axis.
+struct AxisTracker {
+    window: VecDeque<f64>,
+    values: VecDeque<f64>,
+    extremes: Vec<Extreme>,
+    intervals: VecDeque<f64>,
+    increasing: bool,
+    last_extreme_time: Option<Instant>,
+    sample_index: usize,
+}
+
+impl AxisTracker {
+    fn new() -> Self {
+        Self {
+            window: VecDeque::with_capacity(SMOOTHING_WINDOW),
+            values: VecDeque::with_capacity(HISTORY_LEN),
+            extremes: Vec::new(),
+            intervals: VecDeque::with_capacity(INTERVAL_HISTORY),
+            increasing: false,
+            last_extreme_time: None,
+            sample_index: 0,
+        }
+    }
+
+    fn push(&mut self, raw: i16, now: Instant) {
+        if self.window.len() == SMOOTHING_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(raw as f64);
+        let smoothed = self.window.iter().sum::<f64>() / self.window.len() as f64;
+
+        if self.values.len() == HISTORY_LEN {
+            self.values.pop_front();
+        }
+        self.values.push_back(smoothed);
+        self.sample_index += 1;
+
+        // forget extremes that have scrolled out of the history
+        let oldest = self.sample_index.saturating_sub(HISTORY_LEN);
+        self.extremes.retain(|e| e.index >= oldest);
+
+        if self.values.len() < MIN_SAMPLES {
+            return;
+        }
+
+        let n = self.values.len();
+        let current = self.values[n - 1];
+        let prev = self.values[n - 2];
+        let threshold = self.dynamic_threshold();
+
+        if self.increasing && current < prev - threshold {
+            if prev.abs() > MIN_EXTREME_AMPLITUDE {
+                self.record_extreme(prev, now);
+            }
+            self.increasing = false;
+        } else if !self.increasing && current > prev + threshold {
+            if prev.abs() > MIN_EXTREME_AMPLITUDE {
+                self.record_extreme(prev, now);
+            }
+            self.increasing = true;
+        }
+    }
+
+    fn dynamic_threshold(&self) -> f64 {
+        let recent: Vec<f64> = self
+            .values
+            .iter()
+            .rev()
+            .take(MIN_SAMPLES)
+            .copied()
+            .collect();
+        let mean = recent.iter().sum::<f64>() / recent.len() as f64;
+        // sample variance, only called once there are MIN_SAMPLES values
+        let variance =
+            recent.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (recent.len() - 1) as f64;
+        (variance / 3.0).clamp(MIN_THRESHOLD, MAX_THRESHOLD)
+    }
+
+    fn record_extreme(&mut self, value: f64, now: Instant) {
+        self.extremes.push(Extreme {
+            index: self.sample_index - 1,
+            value,
+        });
+        if let Some(last) = self.last_extreme_time {
+            if self.intervals.len() == INTERVAL_HISTORY {
+                self.intervals.pop_front();
+            }
+            self.intervals
+                .push_back(now.duration_since(last).as_secs_f64());
+        }
+        self.last_extreme_time = Some(now);
+    }
+
+    fn rhythm_consistency(&self) -> f64 {
+        if self.intervals.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.intervals.iter().sum::<f64>() / self.intervals.len() as f64;
+        if mean <= 0.0 {
+            return 0.0;
+        }
+        let deviation = self
+            .intervals
+            .iter()
+            .map(|i| (i / mean - 1.0).powi(2))
+            .sum::<f64>()
+            / self.intervals.len() as f64;
+        (1.0 - (deviation / 0.5).min(1.0)).max(0.0)
+    }
+
+    fn recent_mean_abs(&self, count: usize) -> f64 {
+        let count = count.min(self.values.len());
+        if count == 0 {
+            return 0.0;
+        }
+        self.values
+            .iter()
+            .rev()
+            .take(count)
+            .map(|v| v.abs())
+            .sum::<f64>()
+            / count as f64
+    }
+
+    /// How sure we are that the latest extremes on this axis form a gesture, given the
+    /// movement on the other axis.
+    fn confidence(&self, other: &AxisTracker) -> f64 {
+        if self.extremes.len() < REQUIRED_EXTREMES {
+            return 0.0;
+        }
+        let recent = &self.extremes[self.extremes.len() - REQUIRED_EXTREMES..];
+
+        let amplitude = recent.iter().map(|e| e.value.abs()).sum::<f64>() / recent.len() as f64;
+        let amplitude_factor = (amplitude / FULL_AMPLITUDE).min(1.0);
+
+        let alternating = recent
+            .windows(2)
+            .all(|pair| pair[0].value.signum() != pair[1].value.signum());
+        let alternation_factor = if alternating { 1.0 } else { 0.5 };
+
+        let other_amplitude = other.recent_mean_abs(REQUIRED_EXTREMES * 2);
+        let isolation_factor = (amplitude / (other_amplitude + 0.1) * 1.2).min(1.0);
+
+        amplitude_factor * 0.4
+            + self.rhythm_consistency() * 0.2
+            + alternation_factor * 0.2
+            + isolation_factor * 0.2
+    }
+}
+
+/// Recognises nods and head shakes from the acceleration values in head tracking samples.
+pub struct GestureDetector {
+    horizontal: AxisTracker,
+    vertical: AxisTracker,
+    cooldown_until: Option<Instant>,
+}
+
+impl GestureDetector {
+    pub fn new() -> Self {
+        Self {
+            horizontal: AxisTracker::new(),
+            vertical: AxisTracker::new(),
+            cooldown_until: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.horizontal = AxisTracker::new();
+        self.vertical = AxisTracker::new();
+    }
+
+    /// Feeds one sample; returns a gesture once enough alternating movement was seen on one axis.
+    pub fn process(&mut self, accel_h: i16, accel_v: i16) -> Option<Gesture> {
+        self.process_at(accel_h, accel_v, Instant::now())
+    }
+
+    fn process_at(&mut self, accel_h: i16, accel_v: i16, now: Instant) -> Option<Gesture> {
+        if self.cooldown_until.is_some_and(|until| now < until) {
+            return None;
+        }
+
+        self.horizontal.push(accel_h, now);
+        self.vertical.push(accel_v, now);
+
+        let gesture = if self.vertical.confidence(&self.horizontal) >= CONFIDENCE_THRESHOLD {
+            Gesture::Nod
+        } else if self.horizontal.confidence(&self.vertical) >= CONFIDENCE_THRESHOLD {
+            Gesture::Shake
+        } else {
+            return None;
+        };
+
+        self.reset();
+        self.cooldown_until = Some(now + COOLDOWN);
+        Some(gesture)
+    }
+}
+
+impl Default for GestureDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // head tracking samples arrive about this often
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// A head moving back and forth on one axis, turning around every six samples.
+    fn swing(samples: usize) -> Vec<i16> {
+        (0..samples)
+            .map(|i| if (i / 6) % 2 == 0 { 1000 } else { -1000 })
+            .collect()
+    }
+
+    /// Feeds the samples at a steady rate, returns when each gesture was detected.
+    fn detect(accel_h: &[i16], accel_v: &[i16]) -> Vec<(Duration, Gesture)> {
+        let mut detector = GestureDetector::new();
+        let start = Instant::now();
+        let mut detected = Vec::new();
+        for (i, (&h, &v)) in accel_h.iter().zip(accel_v).enumerate() {
+            let elapsed = SAMPLE_INTERVAL * i as u32;
+            if let Some(gesture) = detector.process_at(h, v, start + elapsed) {
+                detected.push((elapsed, gesture));
+            }
+        }
+        detected
+    }
+
+    #[test]
+    fn detects_a_nod() {
+        let detected = detect(&[0; 120], &swing(120));
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].1, Gesture::Nod);
+    }
+
+    #[test]
+    fn detects_a_shake() {
+        let detected = detect(&swing(120), &[0; 120]);
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].1, Gesture::Shake);
+    }
+
+    #[test]
+    fn ignores_a_still_head() {
+        assert!(detect(&[0; 120], &[0; 120]).is_empty());
+        let jitter_h: Vec<i16> = (0..120).map(|i| (i * 37 % 90) as i16 - 45).collect();
+        let jitter_v: Vec<i16> = (0..120).map(|i| (i * 53 % 90) as i16 - 45).collect();
+        assert!(detect(&jitter_h, &jitter_v).is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_cooldown() {
+        let detected = detect(&[0; 240], &swing(240));
+        assert!(detected.len() >= 2);
+        assert!(detected.iter().all(|(_, gesture)| *gesture == Gesture::Nod));
+        for pair in detected.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= COOLDOWN);
+        }
+    }
+
+    #[test]
+    fn command_actions_need_a_command() {
+        let mut settings = HeadGestureSettings::default();
+        assert!(
+            !settings
+                .available_actions()
+                .iter()
+                .any(HeadGestureAction::needs_command)
+        );
+
+        settings.decline_call_command = "  ".to_string();
+        settings.accept_call_command = "true".to_string();
+        let actions = settings.available_actions();
+        assert!(actions.contains(&HeadGestureAction::AcceptCall));
+        assert!(!actions.contains(&HeadGestureAction::DeclineCall));
+        assert!(actions.contains(&HeadGestureAction::PlayPause));
+    }
+}
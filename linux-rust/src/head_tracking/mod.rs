@@ -0,0 +1,3 @@
+pub mod gestures;
+pub mod opentrack;
+pub mod orientation;
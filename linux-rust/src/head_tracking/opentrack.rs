@@ -0,0 +1,109 @@
+use crate::head_tracking::orientation::Orientation;
+use crate::utils::{SharedSetting, load_app_setting, save_app_setting};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+pub const DEFAULT_OPENTRACK_PORT: u16 = 4242;
+const DEFAULT_SMOOTHING: f64 = 0.5;
+// anything above this would make the output lag far behind the head
+pub const MAX_SMOOTHING: f64 = 0.95;
+
+const SETTINGS_KEY: &str = "opentrack";
+
+static CURRENT: SharedSetting<OpenTrackSettings> = SharedSetting::new(OpenTrackSettings::load);
+
+/// OpenTrack output configuration, stored under `opentrack` in the app settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenTrackSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// Exponential smoothing factor, 0 passes samples through unchanged.
+    pub smoothing: f64,
+}
+
+impl Default for OpenTrackSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_OPENTRACK_PORT,
+            smoothing: DEFAULT_SMOOTHING,
+        }
+    }
+}
+
+impl OpenTrackSettings {
+    pub fn load() -> Self {
+        load_app_setting(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        save_app_setting(SETTINGS_KEY, serde_json::to_value(self).unwrap_or_default());
+    }
+
+    /// The settings in use, the file is only read the first time.
+    pub fn current() -> Self {
+        CURRENT.get()
+    }
+
+    pub fn watch() -> watch::Receiver<Self> {
+        CURRENT.watch()
+    }
+
+    /// Puts the settings in use right away, without saving them.
+    pub fn publish(&self) {
+        CURRENT.publish(self.clone());
+    }
+}
+
+/// Streams head poses to OpenTrack's "UDP over network" input on localhost.
+///
+/// Each datagram is six little-endian f64 values: x, y, z in centimetres followed by yaw,
+/// pitch and roll in degrees. The AirPods only give us pitch and yaw, everything else is 0.
+pub struct OpenTrackOutput {
+    socket: UdpSocket,
+    target: SocketAddr,
+    smoothing: f64,
+    smoothed: Option<Orientation>,
+}
+
+impl OpenTrackOutput {
+    pub async fn new(settings: &OpenTrackSettings) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let target = SocketAddr::from((Ipv4Addr::LOCALHOST, settings.port));
+        info!("Sending head tracking data to OpenTrack on {}", target);
+        Ok(Self {
+            socket,
+            target,
+            smoothing: settings.smoothing.clamp(0.0, MAX_SMOOTHING),
+            smoothed: None,
+        })
+    }
+
+    /// Drops the smoothing history, so the next pose is sent as is.
+    pub fn reset(&mut self) {
+        self.smoothed = None;
+    }
+
+    pub async fn send(&mut self, pose: Orientation) -> io::Result<()> {
+        let pose = match self.smoothed {
+            Some(prev) => Orientation {
+                pitch: prev.pitch * self.smoothing + pose.pitch * (1.0 - self.smoothing),
+                yaw: prev.yaw * self.smoothing + pose.yaw * (1.0 - self.smoothing),
+            },
+            None => pose,
+        };
+        self.smoothed = Some(pose);
+
+        let values = [0.0, 0.0, 0.0, pose.yaw, pose.pitch, 0.0];
+        let mut packet = [0u8; 48];
+        for (chunk, value) in packet.chunks_exact_mut(8).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        self.socket.send_to(&packet, self.target).await.map(|_| ())
+    }
+}
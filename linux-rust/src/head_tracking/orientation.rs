@@ -0,0 +1,124 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::watch;
+
+const DEFAULT_O1_NEUTRAL: f64 = 19000.0;
+const CALIBRATION_SAMPLE_COUNT: usize = 10;
+const MIN_CALIBRATION_SAMPLES: usize = 3;
+// full scale of the orientation values reported by the AirPods
+const ORIENTATION_SCALE: f64 = 32000.0;
+
+// per device, counts how often the UI asked to recenter
+static RECENTER_REQUESTS: OnceLock<Mutex<HashMap<String, watch::Sender<u64>>>> = OnceLock::new();
+
+/// Asks the head tracking of a device to take the next pose as the new neutral pose.
+pub fn request_recenter(mac: &str) {
+    with_recenter_sender(mac, |sender| sender.send_modify(|count| *count += 1));
+}
+
+/// Follows the recenter requests for a device, only the ones made after subscribing count.
+pub fn recenter_requests(mac: &str) -> watch::Receiver<u64> {
+    with_recenter_sender(mac, |sender| sender.subscribe())
+}
+
+fn with_recenter_sender<R>(mac: &str, f: impl FnOnce(&watch::Sender<u64>) -> R) -> R {
+    let mut senders = RECENTER_REQUESTS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    f(senders
+        .entry(mac.to_string())
+        .or_insert_with(|| watch::Sender::new(0)))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Orientation {
+    pub pitch: f64,
+    pub yaw: f64,
+}
+
+/// Turns raw head-tracking orientation values into pitch and yaw relative to a neutral pose.
+///
+/// The neutral pose is the average of the first samples after a (re)calibration, so the head
+/// should be held still, facing forward, when tracking starts.
+#[derive(Debug, Clone)]
+pub struct HeadOrientation {
+    o1_neutral: f64,
+    o2_neutral: f64,
+    o3_neutral: f64,
+    calibration_samples: Vec<[i16; 3]>,
+    calibration_complete: bool,
+}
+
+impl HeadOrientation {
+    pub fn new() -> Self {
+        HeadOrientation {
+            o1_neutral: DEFAULT_O1_NEUTRAL,
+            o2_neutral: 0.0,
+            o3_neutral: 0.0,
+            calibration_samples: Vec::with_capacity(CALIBRATION_SAMPLE_COUNT),
+            calibration_complete: false,
+        }
+    }
+
+    pub fn reset_calibration(&mut self) {
+        self.calibration_samples.clear();
+        self.calibration_complete = false;
+    }
+
+    /// Feeds a sample into the calibration, returns whether calibration is complete.
+    pub fn add_calibration_sample(&mut self, orientation: [i16; 3]) -> bool {
+        if self.calibration_samples.len() < CALIBRATION_SAMPLE_COUNT {
+            self.calibration_samples.push(orientation);
+            return false;
+        }
+        if !self.calibration_complete {
+            self.calculate_calibration();
+        }
+        true
+    }
+
+    fn calculate_calibration(&mut self) {
+        if self.calibration_samples.len() < MIN_CALIBRATION_SAMPLES {
+            warn!("Not enough head tracking calibration samples");
+            return;
+        }
+        let count = self.calibration_samples.len() as f64;
+        let mean = |i: usize| {
+            self.calibration_samples
+                .iter()
+                .map(|s| s[i] as f64)
+                .sum::<f64>()
+                / count
+        };
+        self.o1_neutral = mean(0);
+        self.o2_neutral = mean(1);
+        self.o3_neutral = mean(2);
+        info!(
+            "Head tracking calibration complete: o1_neutral={:.2}, o2_neutral={:.2}, o3_neutral={:.2}",
+            self.o1_neutral, self.o2_neutral, self.o3_neutral
+        );
+        self.calibration_complete = true;
+    }
+
+    /// Returns a zero orientation until calibration is complete.
+    pub fn calculate_orientation(&self, orientation: [i16; 3]) -> Orientation {
+        if !self.calibration_complete {
+            return Orientation::default();
+        }
+        // o1 is part of the neutral pose but doesn't contribute to pitch or yaw
+        let o2_norm = orientation[1] as f64 - self.o2_neutral;
+        let o3_norm = orientation[2] as f64 - self.o3_neutral;
+        Orientation {
+            pitch: (o2_norm + o3_norm) / 2.0 / ORIENTATION_SCALE * 180.0,
+            yaw: (o2_norm - o3_norm) / 2.0 / ORIENTATION_SCALE * 180.0,
+        }
+    }
+}
+
+impl Default for HeadOrientation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,12 +1,21 @@
 mod bluetooth;
+mod cli;
+mod dbus_service;
 mod devices;
+mod head_tracking;
 mod media_controller;
+mod notifications;
+mod simulator;
 mod ui;
 mod utils;
 
+use crate::bluetooth::adapter::{self, ActiveAdapter, active_adapter};
+use crate::bluetooth::battery_provider;
 use crate::bluetooth::discovery::{find_connected_airpods, find_other_managed_devices};
 use crate::bluetooth::le::start_le_monitor;
+use crate::bluetooth::lifecycle::{ClosableChannel, ConnectionLifecycle, ConnectionState};
 use crate::bluetooth::managers::DeviceManagers;
+use crate::bluetooth::reconnect::{self, Connected};
 use crate::devices::enums::DeviceData;
 use crate::ui::messages::BluetoothUIMessage;
 use crate::ui::tray::MyTray;
@@ -22,9 +31,10 @@ use ksni::TrayMethods;
 use log::info;
 use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
 #[derive(Parser)]
 struct Args {
@@ -37,6 +47,11 @@ struct Args {
     no_tray: bool,
     #[arg(long, help = "Start the application minimized to tray")]
     start_minimized: bool,
+    #[arg(
+        long,
+        help = "Run without a window, for headless machines and user services. Launching LibrePods again opens the window"
+    )]
+    headless: bool,
     #[arg(
         long,
         help = "Enable Bluetooth LE debug logging. Only use when absolutely necessary; this produces a lot of logs."
@@ -44,6 +59,27 @@ struct Args {
     le_debug: bool,
     #[arg(long, short = 'v', help = "Show application version and exit")]
     version: bool,
+    #[arg(
+        long,
+        value_name = "SCRIPT",
+        help = "Simulate an AirPods device from a JSON timeline instead of using Bluetooth"
+    )]
+    simulate: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "ADAPTER",
+        help = "Bluetooth adapter to use, like hci1. Overrides the adapter set in the settings"
+    )]
+    adapter: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "ADDRESS",
+        help = "Device a command is for. By default the connected AirPods"
+    )]
+    device: Option<String>,
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 fn main() -> iced::Result {
@@ -57,6 +93,28 @@ fn main() -> iced::Result {
         return Ok(());
     }
 
+    if let Some(command) = args.command {
+        if let Err(e) = cli::run(command, args.device) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if !args.headless {
+        match cli::show_running_window() {
+            Ok(true) => {
+                println!("LibrePods is already running, showing its window");
+                return Ok(());
+            }
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let log_level = if args.debug { "debug" } else { "info" };
     let wayland_display = env::var("WAYLAND_DISPLAY").is_ok();
     if env::var("RUST_LOG").is_err() {
@@ -76,18 +134,79 @@ fn main() -> iced::Result {
     }
     env_logger::init();
 
-    let (ui_tx, ui_rx) = unbounded_channel::<BluetoothUIMessage>();
+    let (ui_tx, mut ui_rx) = unbounded_channel::<BluetoothUIMessage>();
 
     let device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>> =
         Arc::new(RwLock::new(HashMap::new()));
     let device_managers_clone = device_managers.clone();
-    std::thread::spawn(|| {
+    let ui_tx_clone = ui_tx.clone();
+    let headless = args.headless;
+    std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async_main(ui_tx, device_managers_clone))
-            .unwrap();
+        let result = rt.block_on(async_main(ui_tx_clone, device_managers_clone));
+        if headless && let Err(e) = &result {
+            // nothing else keeps a headless instance useful
+            log::error!("LibrePods stopped: {}", e);
+            std::process::exit(1);
+        }
+        result.unwrap();
     });
 
-    ui::window::start_ui(ui_rx, args.start_minimized, device_managers)
+    if args.headless {
+        info!("Running headless, launch LibrePods again to open the window");
+        loop {
+            if !wait_for_window_request(&mut ui_rx) {
+                return Ok(());
+            }
+            if has_display() {
+                break;
+            }
+            log::warn!("Asked to open the window, but there is no display to open it on");
+        }
+        // the UI only hears about devices as they connect, tell it about the ones already there
+        for (mac, managers) in device_managers.blocking_read().iter() {
+            if managers.connection_state() == ConnectionState::Ready {
+                let _ = ui_tx.send(BluetoothUIMessage::DeviceConnected(mac.clone()));
+            }
+        }
+    }
+    drop(ui_tx);
+
+    let result = ui::window::start_ui(
+        ui_rx,
+        args.start_minimized && !args.headless,
+        device_managers,
+    );
+    if !args.headless {
+        return result;
+    }
+    // the daemon outlives its window. It can't be opened again though, winit only allows one
+    // event loop per process
+    match result {
+        Ok(()) => log::warn!("The window was closed for good, LibrePods keeps running headless"),
+        Err(e) => log::error!(
+            "Failed to run the window, LibrePods keeps running headless: {}",
+            e
+        ),
+    }
+    loop {
+        std::thread::park();
+    }
+}
+
+/// Drops UI messages until a window is asked for, so they don't pile up while headless.
+/// Returns false if the daemon stopped instead.
+fn wait_for_window_request(ui_rx: &mut UnboundedReceiver<BluetoothUIMessage>) -> bool {
+    while let Some(message) = ui_rx.blocking_recv() {
+        if matches!(message, BluetoothUIMessage::OpenWindow) {
+            return true;
+        }
+    }
+    false
+}
+
+fn has_display() -> bool {
+    env::var_os("WAYLAND_DISPLAY").is_some() || env::var_os("DISPLAY").is_some()
 }
 
 async fn async_main(
@@ -95,6 +214,7 @@ async fn async_main(
     device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
 ) -> bluer::Result<()> {
     let args = Args::parse();
+    adapter::init_preferred_adapter(args.adapter.clone());
 
     let mut managed_devices_mac: Vec<String> = Vec::new(); // includes ony non-AirPods. AirPods handled separately.
 
@@ -117,113 +237,35 @@ async fn async_main(
     let tray_handle = if args.no_tray {
         None
     } else {
-        let tray = MyTray {
-            conversation_detect_enabled: None,
-            battery_headphone: None,
-            battery_headphone_status: None,
-            battery_l: None,
-            battery_l_status: None,
-            battery_r: None,
-            battery_r_status: None,
-            battery_c: None,
-            battery_c_status: None,
-            connected: false,
-            listening_mode: None,
-            allow_off_option: None,
-            command_tx: None,
-            ui_tx: Some(ui_tx.clone()),
-        };
-        let handle = tray.spawn().await.unwrap();
-        Some(handle)
-    };
-
-    let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
-
-    let le_tray_clone = tray_handle.clone();
-    tokio::spawn(async move {
-        info!("Starting LE monitor...");
-        if let Err(e) = start_le_monitor(le_tray_clone).await {
-            log::error!("LE monitor error: {}", e);
+        let tray = MyTray::new(Some(ui_tx.clone()));
+        match tray.spawn().await {
+            Ok(handle) => Some(handle),
+            // headless machines often have no tray to show it in
+            Err(e) => {
+                log::warn!("Failed to start the tray: {}", e);
+                None
+            }
         }
-    });
+    };
 
-    info!("Listening for new connections.");
+    dbus_service::start_dbus_service(device_managers.clone(), ui_tx.clone());
+    battery_provider::start_battery_provider(device_managers.clone());
 
-    info!("Checking for connected devices...");
-    match find_connected_airpods(&adapter).await {
-        Ok(device) => {
-            let name = device
-                .name()
-                .await?
-                .unwrap_or_else(|| "Unknown".to_string());
-            info!("Found connected AirPods: {}, initializing.", name);
-            let airpods_device =
-                AirPodsDevice::new(device.address(), tray_handle.clone(), ui_tx.clone()).await;
-
-            let mut managers = device_managers.write().await;
-            // let dev_managers = DeviceManagers::with_both(airpods_device.aacp_manager.clone(), airpods_device.att_manager.clone());
-            let dev_managers = DeviceManagers::with_aacp(airpods_device.aacp_manager.clone());
-            managers
-                .entry(device.address().to_string())
-                .or_insert(dev_managers)
-                .set_aacp(airpods_device.aacp_manager);
-            drop(managers);
-            ui_tx
-                .send(BluetoothUIMessage::DeviceConnected(
-                    device.address().to_string(),
-                ))
-                .unwrap();
-        }
-        Err(_) => {
-            info!("No connected AirPods found.");
-        }
+    if let Some(script_path) = &args.simulate {
+        simulator::run_simulation(script_path, tray_handle, ui_tx, device_managers).await;
+        // keep the tray and the simulated device's tasks alive until the app exits
+        std::future::pending::<()>().await;
     }
 
-    match find_other_managed_devices(&adapter, managed_devices_mac.clone()).await {
-        Ok(devices) => {
-            for device in devices {
-                let addr_str = device.address().to_string();
-                info!(
-                    "Found connected managed device: {}, initializing.",
-                    addr_str
-                );
-                let type_ = devices_list.get(&addr_str).unwrap().type_.clone();
-                let ui_tx_clone = ui_tx.clone();
-                let device_managers = device_managers.clone();
-                tokio::spawn(async move {
-                    let mut managers = device_managers.write().await;
-                    if type_ == devices::enums::DeviceType::Nothing {
-                        let dev = devices::nothing::NothingDevice::new(
-                            device.address(),
-                            ui_tx_clone.clone(),
-                        )
-                        .await;
-                        let dev_managers = DeviceManagers::with_att(dev.att_manager.clone());
-                        managers
-                            .entry(addr_str.clone())
-                            .or_insert(dev_managers)
-                            .set_att(dev.att_manager);
-                        ui_tx_clone
-                            .send(BluetoothUIMessage::DeviceConnected(addr_str))
-                            .unwrap();
-                    }
-                    drop(managers)
-                });
-            }
-        }
-        Err(e) => {
-            log::debug!("type of error: {:?}", e.kind);
-            if e.kind
-                != bluer::ErrorKind::Internal(InternalErrorKind::Io(std::io::ErrorKind::NotFound))
-            {
-                log::error!("Error finding other managed devices: {}", e);
-            } else {
-                info!("No other managed devices found.");
-            }
-        }
-    }
+    let session = bluer::Session::new().await?;
+    tokio::spawn(supervise_adapter(
+        session,
+        tray_handle.clone(),
+        ui_tx.clone(),
+        device_managers.clone(),
+        devices_list.clone(),
+        managed_devices_mac.clone(),
+    ));
 
     let conn = Connection::new_system()?;
     let rule = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged");
@@ -231,7 +273,11 @@ async fn async_main(
         let Some(path) = msg.path() else {
             return true;
         };
-        if !path.contains("/org/bluez/hci") || !path.contains("/dev_") {
+        // only devices on the adapter in use
+        let Some(active) = active_adapter() else {
+            return true;
+        };
+        if !path.starts_with(&format!("/org/bluez/{}/dev_", active.name)) {
             return true;
         }
         // debug!("PropertiesChanged signal for path: {}", path);
@@ -249,10 +295,24 @@ async fn async_main(
         let Some(is_connected) = connected_var.0.as_ref().as_u64() else {
             return true;
         };
+        let proxy = conn.with_proxy("org.bluez", path, std::time::Duration::from_millis(5000));
         if is_connected == 0 {
+            let Ok(addr) = proxy
+                .get::<String>("org.bluez.Device1", "Address")
+                .map_err(|_| ())
+                .and_then(|a| a.parse::<Address>().map_err(|_| ()))
+            else {
+                return true;
+            };
+            let lifecycle = ConnectionLifecycle::new(
+                addr,
+                device_managers.clone(),
+                ui_tx.clone(),
+                tray_handle.clone(),
+            );
+            tokio::spawn(async move { lifecycle.disconnected().await });
             return true;
         }
-        let proxy = conn.with_proxy("org.bluez", path, std::time::Duration::from_millis(5000));
         let Ok(uuids) = proxy.get::<Vec<String>>("org.bluez.Device1", "UUIDs") else {
             return true;
         };
@@ -269,21 +329,12 @@ async fn async_main(
             info!("Managed device connected: {}, initializing", addr_str);
             let type_ = devices_list.get(&addr_str).unwrap().type_.clone();
             if type_ == devices::enums::DeviceType::Nothing {
-                let ui_tx_clone = ui_tx.clone();
-                let device_managers = device_managers.clone();
-                tokio::spawn(async move {
-                    let mut managers = device_managers.write().await;
-                    let dev = devices::nothing::NothingDevice::new(addr, ui_tx_clone.clone()).await;
-                    let dev_managers = DeviceManagers::with_att(dev.att_manager.clone());
-                    managers
-                        .entry(addr_str.clone())
-                        .or_insert(dev_managers)
-                        .set_att(dev.att_manager);
-                    drop(managers);
-                    ui_tx_clone
-                        .send(BluetoothUIMessage::DeviceConnected(addr_str.clone()))
-                        .unwrap();
-                });
+                tokio::spawn(connect_nothing(
+                    addr,
+                    tray_handle.clone(),
+                    ui_tx.clone(),
+                    device_managers.clone(),
+                ));
             }
             return true;
         }
@@ -295,23 +346,12 @@ async fn async_main(
             .get::<String>("org.bluez.Device1", "Name")
             .unwrap_or_else(|_| "Unknown".to_string());
         info!("AirPods connected: {}, initializing", name);
-        let handle_clone = tray_handle.clone();
-        let ui_tx_clone = ui_tx.clone();
-        let device_managers = device_managers.clone();
-        tokio::spawn(async move {
-            let airpods_device = AirPodsDevice::new(addr, handle_clone, ui_tx_clone.clone()).await;
-            let mut managers = device_managers.write().await;
-            // let dev_managers = DeviceManagers::with_both(airpods_device.aacp_manager.clone(), airpods_device.att_manager.clone());
-            let dev_managers = DeviceManagers::with_aacp(airpods_device.aacp_manager.clone());
-            managers
-                .entry(addr_str.clone())
-                .or_insert(dev_managers)
-                .set_aacp(airpods_device.aacp_manager);
-            drop(managers);
-            ui_tx_clone
-                .send(BluetoothUIMessage::DeviceConnected(addr_str.clone()))
-                .unwrap();
-        });
+        tokio::spawn(connect_airpods(
+            addr,
+            tray_handle.clone(),
+            ui_tx.clone(),
+            device_managers.clone(),
+        ));
         true
     })?;
 
@@ -320,3 +360,174 @@ async fn async_main(
         conn.process(std::time::Duration::from_millis(1000))?;
     }
 }
+
+/// Follows the Bluetooth adapter: handles the devices connected through it while it is usable,
+/// and tears them down when it goes away (unplugged, powered off, bluetoothd restarted) until it
+/// is back.
+async fn supervise_adapter(
+    session: bluer::Session,
+    tray_handle: Option<ksni::Handle<MyTray>>,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+    devices_list: HashMap<String, DeviceData>,
+    managed_devices_mac: Vec<String>,
+) {
+    loop {
+        let adapter = match adapter::wait_for_adapter(&session).await {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                log::error!("Failed to watch Bluetooth adapters: {}", e);
+                return;
+            }
+        };
+        let address = match adapter.address().await {
+            Ok(address) => address,
+            Err(e) => {
+                log::error!("Failed to get the address of {}: {}", adapter.name(), e);
+                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                continue;
+            }
+        };
+        info!("Using Bluetooth adapter {} ({})", adapter.name(), address);
+        adapter::set_active_adapter(Some(ActiveAdapter {
+            name: adapter.name().to_string(),
+            address,
+        }));
+
+        let le_tray_clone = tray_handle.clone();
+        let le_adapter = adapter.clone();
+        let le_monitor = tokio::spawn(async move {
+            info!("Starting LE monitor...");
+            if let Err(e) = start_le_monitor(le_adapter, le_tray_clone).await {
+                log::error!("LE monitor error: {}", e);
+            }
+        });
+
+        info!("Listening for new connections.");
+
+        info!("Checking for connected devices...");
+        match find_connected_airpods(&adapter).await {
+            Ok(device) => {
+                let name = device
+                    .name()
+                    .await
+                    .ok()
+                    .flatten()
+                    .unwrap_or_else(|| "Unknown".to_string());
+                info!("Found connected AirPods: {}, initializing.", name);
+                tokio::spawn(connect_airpods(
+                    device.address(),
+                    tray_handle.clone(),
+                    ui_tx.clone(),
+                    device_managers.clone(),
+                ));
+            }
+            Err(_) => {
+                info!("No connected AirPods found.");
+            }
+        }
+
+        match find_other_managed_devices(&adapter, managed_devices_mac.clone()).await {
+            Ok(devices) => {
+                for device in devices {
+                    let addr_str = device.address().to_string();
+                    info!(
+                        "Found connected managed device: {}, initializing.",
+                        addr_str
+                    );
+                    let type_ = devices_list.get(&addr_str).unwrap().type_.clone();
+                    if type_ == devices::enums::DeviceType::Nothing {
+                        tokio::spawn(connect_nothing(
+                            device.address(),
+                            tray_handle.clone(),
+                            ui_tx.clone(),
+                            device_managers.clone(),
+                        ));
+                    }
+                }
+            }
+            Err(e) => {
+                log::debug!("type of error: {:?}", e.kind);
+                if e.kind
+                    != bluer::ErrorKind::Internal(InternalErrorKind::Io(
+                        std::io::ErrorKind::NotFound,
+                    ))
+                {
+                    log::error!("Error finding other managed devices: {}", e);
+                } else {
+                    info!("No other managed devices found.");
+                }
+            }
+        }
+
+        let reason = adapter::wait_for_adapter_loss(&adapter).await;
+        log::warn!(
+            "Bluetooth adapter {} is {}, disconnecting devices",
+            adapter.name(),
+            reason
+        );
+        adapter::set_active_adapter(None);
+        le_monitor.abort();
+        let macs: Vec<String> = device_managers.read().await.keys().cloned().collect();
+        for mac in macs {
+            if let Ok(addr) = mac.parse::<Address>() {
+                ConnectionLifecycle::new(
+                    addr,
+                    device_managers.clone(),
+                    ui_tx.clone(),
+                    tray_handle.clone(),
+                )
+                .disconnected()
+                .await;
+            }
+        }
+    }
+}
+
+/// Connects the AirPods and keeps them connected until they disconnect.
+async fn connect_airpods(
+    addr: Address,
+    tray_handle: Option<ksni::Handle<MyTray>>,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+) {
+    let lifecycle =
+        ConnectionLifecycle::new(addr, device_managers, ui_tx.clone(), tray_handle.clone());
+    reconnect::supervise(lifecycle, |lifecycle| {
+        let tray_handle = tray_handle.clone();
+        let ui_tx = ui_tx.clone();
+        async move {
+            let airpods_device =
+                AirPodsDevice::new(lifecycle.address(), &lifecycle, tray_handle, ui_tx).await?;
+            Ok(Connected {
+                channel: ClosableChannel::Aacp(airpods_device.aacp_manager.clone()),
+                aacp: Some(airpods_device.aacp_manager),
+                att: None,
+            })
+        }
+    })
+    .await;
+}
+
+/// Connects a Nothing device and keeps it connected until it disconnects.
+async fn connect_nothing(
+    addr: Address,
+    tray_handle: Option<ksni::Handle<MyTray>>,
+    ui_tx: UnboundedSender<BluetoothUIMessage>,
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+) {
+    let lifecycle = ConnectionLifecycle::new(addr, device_managers, ui_tx.clone(), tray_handle);
+    reconnect::supervise(lifecycle, |lifecycle| {
+        let ui_tx = ui_tx.clone();
+        async move {
+            let dev = devices::nothing::NothingDevice::new(lifecycle.address(), &lifecycle, ui_tx)
+                .await?;
+            Ok(Connected {
+                channel: ClosableChannel::Att(dev.att_manager.clone()),
+                aacp: None,
+                att: Some(dev.att_manager),
+            })
+        }
+    })
+    .await;
+}
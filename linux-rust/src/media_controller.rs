@@ -4,7 +4,7 @@ use dbus::arg::RefArg;
 use dbus::blocking::Connection;
 use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
 use libpulse_binding::callbacks::ListResult;
-use libpulse_binding::context::introspect::SinkInfo;
+use libpulse_binding::context::introspect::{ServerInfo, SinkInfo, SourceInfo};
 use libpulse_binding::context::{Context, FlagSet as ContextFlagSet};
 use libpulse_binding::def::Retval;
 use libpulse_binding::mainloop::standard::Mainloop;
@@ -129,6 +129,12 @@ impl MediaController {
         loop {
             tokio::time::sleep(Duration::from_millis(500)).await;
 
+            if !aacp_manager.is_connected().await {
+                info!("AACP connection closed, stopping playback listener loop");
+                self.state.lock().await.playback_listener_running = false;
+                break;
+            }
+
             let is_playing = tokio::task::spawn_blocking(|| Self::check_if_playing())
                 .await
                 .unwrap_or(false);
@@ -579,8 +585,7 @@ impl MediaController {
             let mpris_services: Vec<&String> = names
                 .iter()
                 .filter(|s| {
-                    s.starts_with("org.mpris.MediaPlayer2.")
-                        && !Self::is_kdeconnect_service(s)
+                    s.starts_with("org.mpris.MediaPlayer2.") && !Self::is_kdeconnect_service(s)
                 })
                 .collect();
 
@@ -652,6 +657,95 @@ impl MediaController {
         }
     }
 
+    pub async fn next_track(&self) {
+        debug!("Skipping to next track");
+        self.send_player_command("Next").await;
+    }
+
+    pub async fn previous_track(&self) {
+        debug!("Skipping to previous track");
+        self.send_player_command("Previous").await;
+    }
+
+    /// Mutes or unmutes the default input device.
+    pub async fn toggle_microphone_mute(&self) {
+        match tokio::task::spawn_blocking(toggle_default_source_mute_sync)
+            .await
+            .unwrap_or(None)
+        {
+            Some(muted) => info!("Microphone {}", if muted { "muted" } else { "unmuted" }),
+            None => error!("Failed to toggle microphone mute"),
+        }
+    }
+
+    /// Calls an argument-less org.mpris.MediaPlayer2.Player method on the player the user is
+    /// most likely talking to: the one playing, else the last toggled one, else the first one.
+    async fn send_player_command(&self, method: &'static str) {
+        let last_toggled = {
+            let state = self.state.lock().await;
+            state.last_toggled_service.clone()
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let conn = match Connection::new_session() {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("Failed to connect to D-Bus session: {}", e);
+                    return;
+                }
+            };
+            let proxy = conn.with_proxy(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                Duration::from_secs(5),
+            );
+            let names: Vec<String> =
+                match proxy.method_call("org.freedesktop.DBus", "ListNames", ()) {
+                    Ok((n,)) => n,
+                    Err(e) => {
+                        error!("Failed to list D-Bus names: {}", e);
+                        return;
+                    }
+                };
+
+            let mpris_services: Vec<&String> = names
+                .iter()
+                .filter(|s| {
+                    s.starts_with("org.mpris.MediaPlayer2.") && !Self::is_kdeconnect_service(s)
+                })
+                .collect();
+
+            let playing = mpris_services.iter().copied().find(|service| {
+                conn.with_proxy(*service, "/org/mpris/MediaPlayer2", Duration::from_secs(5))
+                    .get::<String>("org.mpris.MediaPlayer2.Player", "PlaybackStatus")
+                    .is_ok_and(|status| status == "Playing")
+            });
+            let target = playing
+                .or_else(|| {
+                    last_toggled
+                        .as_ref()
+                        .and_then(|last| mpris_services.iter().copied().find(|s| *s == last))
+                })
+                .or_else(|| mpris_services.first().copied());
+
+            let Some(service) = target else {
+                debug!("No media players found for {}", method);
+                return;
+            };
+            let proxy = conn.with_proxy(service, "/org/mpris/MediaPlayer2", Duration::from_secs(5));
+            match proxy.method_call::<(), _, &str, &str>(
+                "org.mpris.MediaPlayer2.Player",
+                method,
+                (),
+            ) {
+                Ok(()) => info!("Sent {} to {}", method, service),
+                Err(e) => error!("Failed to send {} to {}: {}", method, service, e),
+            }
+        })
+        .await
+        .ok();
+    }
+
     async fn resume(&self) {
         debug!("Entering resume method");
         debug!("Resuming playback");
@@ -1287,6 +1381,61 @@ fn get_sink_volume_percent_by_name_sync(sink_name: &str) -> Option<u32> {
     }
 }
 
+/// Returns whether the default source is muted afterwards.
+fn toggle_default_source_mute_sync() -> Option<bool> {
+    let mut mainloop = Mainloop::new().unwrap();
+    let mut context = Context::new(&mainloop, "LibrePods-toggle_source_mute").unwrap();
+    context
+        .connect(None, ContextFlagSet::NOAUTOSPAWN, None)
+        .unwrap();
+    loop {
+        match mainloop.iterate(false) {
+            _ if context.get_state() == libpulse_binding::context::State::Ready => break,
+            _ if context.get_state() == libpulse_binding::context::State::Failed
+                || context.get_state() == libpulse_binding::context::State::Terminated =>
+            {
+                return None;
+            }
+            _ => {}
+        }
+    }
+
+    let mut introspector = context.introspect();
+    let default_source = Rc::new(RefCell::new(None));
+    let op = introspector.get_server_info({
+        let default_source = default_source.clone();
+        move |info: &ServerInfo| {
+            *default_source.borrow_mut() = info.default_source_name.as_ref().map(|s| s.to_string());
+        }
+    });
+    while op.get_state() == OperationState::Running {
+        mainloop.iterate(false);
+    }
+    let source_name = default_source.borrow().clone()?;
+
+    let muted = Rc::new(RefCell::new(None));
+    let op = introspector.get_source_info_by_name(&source_name, {
+        let muted = muted.clone();
+        move |result: ListResult<&SourceInfo>| {
+            if let ListResult::Item(item) = result {
+                *muted.borrow_mut() = Some(item.mute);
+            }
+        }
+    });
+    while op.get_state() == OperationState::Running {
+        mainloop.iterate(false);
+    }
+    let new_mute = !(*muted.borrow())?;
+
+    let op = introspector.set_source_mute_by_name(&source_name, new_mute, None);
+    while op.get_state() == OperationState::Running {
+        mainloop.iterate(false);
+    }
+    mainloop.quit(Retval(0));
+
+    Some(new_mute)
+}
+
 fn set_card_profile_sync(card_index: u32, profile_name: &str) -> bool {
     let mut mainloop = Mainloop::new().unwrap();
     let mut context = Context::new(&mainloop, "LibrePods-set_card_profile").unwrap();
@@ -0,0 +1,240 @@
+use crate::bluetooth::aacp::{BatteryComponent, BatteryInfo, BatteryStatus};
+use crate::utils::{SharedSetting, load_app_setting, save_app_setting};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const SETTINGS_KEY: &str = "notifications";
+
+static CURRENT: SharedSetting<NotificationSettings> =
+    SharedSetting::new(NotificationSettings::load);
+
+const NOTIFICATIONS_BUS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const APP_NAME: &str = "LibrePods";
+const DESKTOP_ENTRY: &str = "me.kavishdevar.librepods";
+const ICON: &str = "audio-headphones";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A part at or below this level is low, the same threshold the status bar output uses.
+pub const LOW_BATTERY: u8 = 20;
+
+/// Which desktop notifications are shown, stored under `notifications` in the app settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub connection: bool,
+    pub low_battery: bool,
+    pub case_charged: bool,
+    pub ownership_lost: bool,
+    pub bud_disconnected: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            connection: true,
+            low_battery: true,
+            case_charged: true,
+            ownership_lost: true,
+            bud_disconnected: true,
+        }
+    }
+}
+
+impl NotificationSettings {
+    pub fn load() -> Self {
+        load_app_setting(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        save_app_setting(SETTINGS_KEY, serde_json::to_value(self).unwrap_or_default());
+    }
+
+    /// The settings in use, the file is only read the first time.
+    pub fn current() -> Self {
+        CURRENT.get()
+    }
+
+    /// Puts the settings in use right away, without saving them.
+    pub fn publish(&self) {
+        CURRENT.publish(self.clone());
+    }
+}
+
+/// Turns the battery reports of one connection into notifications: the first report is the
+/// connection itself, the later ones are compared against the one before.
+#[derive(Default)]
+pub struct BatteryNotifier {
+    last: Option<Vec<BatteryInfo>>,
+}
+
+impl BatteryNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn battery_changed(&mut self, name: &str, battery: &[BatteryInfo]) {
+        let name = display_name(name);
+        let settings = NotificationSettings::current();
+        let Some(last) = self.last.replace(battery.to_vec()) else {
+            if settings.connection {
+                notify(format!("{} connected", name), battery_summary(battery));
+            }
+            return;
+        };
+        let previous = |component| find(&last, component);
+
+        if settings.low_battery {
+            for b in battery {
+                if b.status != BatteryStatus::NotCharging || b.level > LOW_BATTERY {
+                    continue;
+                }
+                if previous(b.component).is_some_and(|p| p.level > LOW_BATTERY) {
+                    notify(
+                        format!("{} battery low", name),
+                        format!("{} at {}%", component_name(b.component), b.level),
+                    );
+                }
+            }
+        }
+
+        if settings.case_charged
+            && let Some(case) = find(battery, BatteryComponent::Case)
+            && case.level == 100
+            && previous(BatteryComponent::Case)
+                .is_some_and(|p| p.status != BatteryStatus::Disconnected && p.level < 100)
+        {
+            notify(
+                format!("{} case charged", name),
+                "The case is fully charged".to_string(),
+            );
+        }
+
+        if settings.bud_disconnected {
+            for (bud, other) in [
+                (BatteryComponent::Left, BatteryComponent::Right),
+                (BatteryComponent::Right, BatteryComponent::Left),
+            ] {
+                // a bud in the case charges, so only a bud that isn't is in use
+                let dropped = find(battery, bud)
+                    .is_some_and(|b| b.status == BatteryStatus::Disconnected)
+                    && previous(bud).is_some_and(|b| b.status != BatteryStatus::Disconnected);
+                let other_in_use =
+                    find(battery, other).is_some_and(|b| b.status == BatteryStatus::NotCharging);
+                if dropped && other_in_use {
+                    notify(
+                        format!("{} disconnected", component_name(bud)),
+                        format!(
+                            "Only the {} is connected to {}",
+                            component_name(other),
+                            name
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub fn notify_disconnected(name: &str) {
+    let name = display_name(name);
+    if NotificationSettings::current().connection {
+        notify(
+            format!("{} disconnected", name),
+            "No longer connected".to_string(),
+        );
+    }
+}
+
+pub fn notify_ownership_lost(name: &str) {
+    let name = display_name(name);
+    if NotificationSettings::current().ownership_lost {
+        notify(
+            format!("{} moved to another device", name),
+            "Another device took over the AirPods, audio here is paused".to_string(),
+        );
+    }
+}
+
+// devices only get a name once they sent their information
+fn display_name(name: &str) -> &str {
+    if name.is_empty() { "AirPods" } else { name }
+}
+
+/// Battery levels on one line, like `Left 80%, Right 75% (charging), Case 50%`.
+fn battery_summary(battery: &[BatteryInfo]) -> String {
+    let parts: Vec<String> = [
+        BatteryComponent::Headphone,
+        BatteryComponent::Left,
+        BatteryComponent::Right,
+        BatteryComponent::Case,
+    ]
+    .into_iter()
+    .filter_map(|component| find(battery, component))
+    .filter(|b| b.status != BatteryStatus::Disconnected)
+    .map(|b| {
+        let charging = if b.status == BatteryStatus::Charging {
+            " (charging)"
+        } else {
+            ""
+        };
+        format!("{} {}%{}", component_name(b.component), b.level, charging)
+    })
+    .collect();
+    if parts.is_empty() {
+        "Battery unknown".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+fn find(battery: &[BatteryInfo], component: BatteryComponent) -> Option<&BatteryInfo> {
+    battery.iter().find(|b| b.component == component)
+}
+
+fn component_name(component: BatteryComponent) -> &'static str {
+    match component {
+        BatteryComponent::Headphone => "Battery",
+        BatteryComponent::Left => "Left AirPod",
+        BatteryComponent::Right => "Right AirPod",
+        BatteryComponent::Case => "Case",
+    }
+}
+
+/// Shows a notification without waiting for the notification server.
+fn notify(summary: String, body: String) {
+    debug!("Notification: {}: {}", summary, body);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = show(&summary, &body) {
+            warn!("Failed to show a notification: {}", e);
+        }
+    });
+}
+
+fn show(summary: &str, body: &str) -> Result<(), dbus::Error> {
+    let conn = Connection::new_session()?;
+    let hints = PropMap::from([(
+        "desktop-entry".to_string(),
+        Variant(Box::new(DESKTOP_ENTRY.to_string()) as Box<dyn RefArg>),
+    )]);
+    let (_id,): (u32,) = conn
+        .with_proxy(NOTIFICATIONS_BUS_NAME, NOTIFICATIONS_PATH, CALL_TIMEOUT)
+        .method_call(
+            NOTIFICATIONS_BUS_NAME,
+            "Notify",
+            (
+                APP_NAME,
+                0u32,
+                ICON,
+                summary,
+                body,
+                Vec::<String>::new(),
+                hints,
+                -1i32,
+            ),
+        )?;
+    Ok(())
+}
@@ -0,0 +1,617 @@
+//! Plays the AirPods side of AACP over a `MemoryTransport`, driven by a JSON timeline.
+//!
+//! Started with `--simulate <script.json>`. Example script:
+//!
+//! ```json
+//! {
+//!   "address": "AA:BB:CC:DD:EE:FF",
+//!   "name": "Simulated AirPods Pro",
+//!   "initial": [
+//!     { "type": "EarDetection", "primary": "InCase", "secondary": "InCase" }
+//!   ],
+//!   "events": [
+//!     { "at_ms": 0, "type": "Battery", "left": { "level": 80, "status": "NotCharging" } },
+//!     { "at_ms": 2000, "type": "EarDetection", "primary": "InEar", "secondary": "InEar" },
+//!     { "at_ms": 4000, "type": "ListeningMode", "mode": 2 },
+//!     { "at_ms": 6000, "type": "ConversationalAwareness", "level": 1 },
+//!     { "at_ms": 8000, "type": "StemPress", "press": "SinglePress", "bud": "Left" },
+//!     { "at_ms": 9000, "type": "ControlCommand", "identifier": "AllowOffOption", "value": [1] },
+//!     { "at_ms": 12000, "type": "Disconnect" }
+//!   ]
+//! }
+//! ```
+//!
+//! `initial` actions set up the state sent once the host requests notifications, `at_ms` is
+//! counted from that point on. The device data of a simulation is kept in a temporary
+//! directory, so the simulated device never shows up among the real ones.
+
+use crate::bluetooth::aacp::codec::AacpPacket;
+use crate::bluetooth::aacp::{
+    AirPodsLEKeys, BatteryComponent, BatteryInfo, BatteryStatus, ControlCommandIdentifiers,
+    ControlCommandStatus, EarDetectionStatus, ProximityKeyType, StemPressBudType, StemPressType,
+};
+use crate::bluetooth::lifecycle::{ClosableChannel, ConnectionLifecycle};
+use crate::bluetooth::managers::DeviceManagers;
+use crate::bluetooth::transport::{MemoryTransport, PacketTransport};
+use crate::devices::airpods::{AirPodsDevice, AirPodsInformation};
+use crate::devices::enums::{DeviceData, DeviceType};
+use crate::ui::messages::BluetoothUIMessage;
+use crate::ui::tray::MyTray;
+use crate::utils::{get_devices_path, override_data_dir};
+use bluer::Address;
+use ksni::Handle;
+use log::{debug, error, info};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{RwLock, mpsc};
+use tokio::time::{Duration, Instant, sleep_until};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulatorScript {
+    #[serde(default = "default_address")]
+    pub address: String,
+    #[serde(default = "default_local_address")]
+    pub local_address: String,
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default = "default_model_number")]
+    pub model_number: String,
+    #[serde(default)]
+    pub serial_number: String,
+    #[serde(default)]
+    pub firmware_version: String,
+    /// Hex-encoded IRK returned for proximity key requests
+    #[serde(default)]
+    pub irk: Option<String>,
+    /// Hex-encoded encryption key returned for proximity key requests
+    #[serde(default)]
+    pub enc_key: Option<String>,
+    #[serde(default)]
+    pub initial: Vec<ScriptAction>,
+    #[serde(default)]
+    pub events: Vec<ScriptEvent>,
+}
+
+fn default_address() -> String {
+    "AA:BB:CC:DD:EE:FF".to_string()
+}
+
+fn default_local_address() -> String {
+    "00:11:22:33:44:55".to_string()
+}
+
+fn default_name() -> String {
+    "AirPods Pro (Simulated)".to_string()
+}
+
+fn default_model_number() -> String {
+    "A2698".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptEvent {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: ScriptAction,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ScriptBattery {
+    pub level: u8,
+    pub status: BatteryStatus,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum ScriptAction {
+    Battery {
+        #[serde(default)]
+        left: Option<ScriptBattery>,
+        #[serde(default)]
+        right: Option<ScriptBattery>,
+        #[serde(default)]
+        case: Option<ScriptBattery>,
+        #[serde(default)]
+        headphone: Option<ScriptBattery>,
+    },
+    EarDetection {
+        primary: EarDetectionStatus,
+        secondary: EarDetectionStatus,
+    },
+    ListeningMode {
+        mode: u8,
+    },
+    ConversationalAwareness {
+        level: u8,
+    },
+    StemPress {
+        press: StemPressType,
+        bud: StemPressBudType,
+    },
+    ControlCommand {
+        identifier: ControlCommandIdentifiers,
+        value: Vec<u8>,
+    },
+    Disconnect,
+}
+
+struct SimulatedState {
+    batteries: Vec<BatteryInfo>,
+    ear_detection: (EarDetectionStatus, EarDetectionStatus),
+    control_commands: Vec<ControlCommandStatus>,
+    information: AirPodsInformation,
+}
+
+impl SimulatedState {
+    fn new(script: &SimulatorScript) -> Self {
+        let battery = |component| BatteryInfo {
+            component,
+            level: 100,
+            status: BatteryStatus::NotCharging,
+        };
+        let command = |identifier, value: u8| ControlCommandStatus {
+            identifier,
+            value: vec![value],
+        };
+        SimulatedState {
+            batteries: vec![
+                battery(BatteryComponent::Left),
+                battery(BatteryComponent::Right),
+                battery(BatteryComponent::Case),
+            ],
+            ear_detection: (EarDetectionStatus::OutOfEar, EarDetectionStatus::OutOfEar),
+            control_commands: vec![
+                command(ControlCommandIdentifiers::ListeningMode, 0x02),
+                command(ControlCommandIdentifiers::AllowOffOption, 0x01),
+                command(ControlCommandIdentifiers::ConversationDetectConfig, 0x02),
+                command(ControlCommandIdentifiers::AdaptiveVolumeConfig, 0x02),
+                command(ControlCommandIdentifiers::OwnsConnection, 0x01),
+            ],
+            information: AirPodsInformation {
+                name: script.name.clone(),
+                model_number: script.model_number.clone(),
+                manufacturer: "Apple Inc.".to_string(),
+                serial_number: script.serial_number.clone(),
+                version1: script.firmware_version.clone(),
+                version2: script.firmware_version.clone(),
+                hardware_revision: "1.0.0".to_string(),
+                updater_identifier: "com.apple.accessory.updater.app.71".to_string(),
+                left_serial_number: String::new(),
+                right_serial_number: String::new(),
+                version3: script.firmware_version.clone(),
+                le_keys: AirPodsLEKeys {
+                    irk: String::new(),
+                    enc_key: String::new(),
+                },
+            },
+        }
+    }
+
+    fn set_control_command(&mut self, status: ControlCommandStatus) {
+        if let Some(existing) = self
+            .control_commands
+            .iter_mut()
+            .find(|s| s.identifier == status.identifier)
+        {
+            existing.value = status.value;
+        } else {
+            self.control_commands.push(status);
+        }
+    }
+
+    fn set_battery(&mut self, component: BatteryComponent, battery: ScriptBattery) {
+        let info = BatteryInfo {
+            component,
+            level: battery.level,
+            status: battery.status,
+        };
+        if let Some(existing) = self.batteries.iter_mut().find(|b| b.component == component) {
+            *existing = info;
+        } else {
+            self.batteries.push(info);
+        }
+    }
+}
+
+pub struct AirPodsSimulator {
+    transport: MemoryTransport,
+    script: SimulatorScript,
+    state: SimulatedState,
+}
+
+impl AirPodsSimulator {
+    pub fn new(transport: MemoryTransport, script: SimulatorScript) -> Self {
+        let mut state = SimulatedState::new(&script);
+        for action in &script.initial {
+            Self::apply(&mut state, action);
+        }
+        AirPodsSimulator {
+            transport,
+            script,
+            state,
+        }
+    }
+
+    /// Runs until the script disconnects or the host closes the transport. Dropping the
+    /// simulator closes its end of the transport.
+    pub async fn run(mut self) {
+        let mut buf = vec![0u8; 1024];
+        let mut handshake_done = false;
+        let mut timeline_start: Option<Instant> = None;
+        let mut next_event = 0;
+
+        loop {
+            let next_deadline = timeline_start.and_then(|start| {
+                self.script
+                    .events
+                    .get(next_event)
+                    .map(|e| start + Duration::from_millis(e.at_ms))
+            });
+            tokio::select! {
+                res = self.transport.recv(&mut buf) => {
+                    let n = match res {
+                        Ok(0) | Err(_) => {
+                            info!("Host closed the simulated connection.");
+                            return;
+                        }
+                        Ok(n) => n,
+                    };
+                    let packet = match AacpPacket::decode(&buf[..n]) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            debug!("Simulator ignoring undecodable packet: {}", e);
+                            continue;
+                        }
+                    };
+                    if !handshake_done {
+                        if packet == AacpPacket::Handshake {
+                            info!("Simulator received handshake");
+                            handshake_done = true;
+                        } else {
+                            debug!("Simulator ignoring packet before handshake: {:?}", packet);
+                        }
+                        continue;
+                    }
+                    if matches!(packet, AacpPacket::RequestNotifications(_))
+                        && timeline_start.is_none()
+                    {
+                        timeline_start = Some(Instant::now());
+                    }
+                    self.handle_host_packet(packet).await;
+                }
+                _ = sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                    let action = self.script.events[next_event].action.clone();
+                    next_event += 1;
+                    if let ScriptAction::Disconnect = action {
+                        info!("Simulator disconnecting as scripted");
+                        return;
+                    }
+                    Self::apply(&mut self.state, &action);
+                    if let Some(packet) = self.packet_for(&action) {
+                        self.send(&packet).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_host_packet(&mut self, packet: AacpPacket) {
+        match packet {
+            AacpPacket::RequestNotifications(_) => {
+                info!("Simulator sending initial state");
+                self.send(&AacpPacket::Information(self.state.information.clone()))
+                    .await;
+                self.send(&AacpPacket::BatteryInfo(self.state.batteries.clone()))
+                    .await;
+                let (primary, secondary) = self.state.ear_detection;
+                self.send(&AacpPacket::EarDetection(primary, secondary))
+                    .await;
+                for status in self.state.control_commands.clone() {
+                    self.send(&AacpPacket::ControlCommand(status)).await;
+                }
+            }
+            AacpPacket::ControlCommand(status) => {
+                // the real device confirms every change by sending the new value back
+                self.state.set_control_command(status.clone());
+                self.send(&AacpPacket::ControlCommand(status)).await;
+            }
+            AacpPacket::ProximityKeysRequest(key_types) => {
+                let keys = key_types
+                    .iter()
+                    .filter_map(|kt| {
+                        let key = match kt {
+                            ProximityKeyType::Irk => self.script.irk.as_ref(),
+                            ProximityKeyType::EncKey => self.script.enc_key.as_ref(),
+                        }?;
+                        hex::decode(key).ok().map(|data| (*kt as u8, data))
+                    })
+                    .collect::<Vec<_>>();
+                if !keys.is_empty() {
+                    self.send(&AacpPacket::ProximityKeysResponse(keys)).await;
+                }
+            }
+            AacpPacket::Rename(name) => {
+                info!("Simulator renamed to {}", name);
+                self.state.information.name = name;
+                self.send(&AacpPacket::Information(self.state.information.clone()))
+                    .await;
+            }
+            other => debug!("Simulator received {:?}", other),
+        }
+    }
+
+    fn apply(state: &mut SimulatedState, action: &ScriptAction) {
+        match action {
+            ScriptAction::Battery {
+                left,
+                right,
+                case,
+                headphone,
+            } => {
+                for (component, battery) in [
+                    (BatteryComponent::Left, left),
+                    (BatteryComponent::Right, right),
+                    (BatteryComponent::Case, case),
+                    (BatteryComponent::Headphone, headphone),
+                ] {
+                    if let Some(battery) = battery {
+                        state.set_battery(component, *battery);
+                    }
+                }
+            }
+            ScriptAction::EarDetection { primary, secondary } => {
+                state.ear_detection = (*primary, *secondary);
+            }
+            ScriptAction::ListeningMode { mode } => {
+                state.set_control_command(ControlCommandStatus {
+                    identifier: ControlCommandIdentifiers::ListeningMode,
+                    value: vec![*mode],
+                });
+            }
+            ScriptAction::ControlCommand { identifier, value } => {
+                state.set_control_command(ControlCommandStatus {
+                    identifier: *identifier,
+                    value: value.clone(),
+                });
+            }
+            ScriptAction::ConversationalAwareness { .. }
+            | ScriptAction::StemPress { .. }
+            | ScriptAction::Disconnect => {}
+        }
+    }
+
+    fn packet_for(&self, action: &ScriptAction) -> Option<AacpPacket> {
+        match action {
+            ScriptAction::Battery { .. } => {
+                Some(AacpPacket::BatteryInfo(self.state.batteries.clone()))
+            }
+            ScriptAction::EarDetection { primary, secondary } => {
+                Some(AacpPacket::EarDetection(*primary, *secondary))
+            }
+            ScriptAction::ListeningMode { mode } => {
+                Some(AacpPacket::ControlCommand(ControlCommandStatus {
+                    identifier: ControlCommandIdentifiers::ListeningMode,
+                    value: vec![*mode],
+                }))
+            }
+            ScriptAction::ConversationalAwareness { level } => {
+                Some(AacpPacket::ConversationalAwareness(*level))
+            }
+            ScriptAction::StemPress { press, bud } => Some(AacpPacket::StemPress(*press, *bud)),
+            ScriptAction::ControlCommand { identifier, value } => {
+                Some(AacpPacket::ControlCommand(ControlCommandStatus {
+                    identifier: *identifier,
+                    value: value.clone(),
+                }))
+            }
+            ScriptAction::Disconnect => None,
+        }
+    }
+
+    async fn send(&self, packet: &AacpPacket) {
+        if let Err(e) = self.transport.send(&packet.encode()).await {
+            error!("Simulator failed to send packet: {}", e);
+        }
+    }
+}
+
+/// Loads the script, connects an `AirPodsDevice` to a simulator instead of real hardware and
+/// registers it like a freshly connected device.
+pub async fn run_simulation(
+    script_path: &Path,
+    tray_handle: Option<Handle<MyTray>>,
+    ui_tx: mpsc::UnboundedSender<BluetoothUIMessage>,
+    device_managers: Arc<RwLock<HashMap<String, DeviceManagers>>>,
+) {
+    let script: SimulatorScript = match std::fs::read_to_string(script_path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+    {
+        Ok(script) => script,
+        Err(e) => {
+            error!(
+                "Failed to load simulator script {}: {}",
+                script_path.display(),
+                e
+            );
+            return;
+        }
+    };
+    let addr: Address = match script.address.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("Invalid simulator address {}: {}", script.address, e);
+            return;
+        }
+    };
+    let addr_str = addr.to_string();
+
+    // the UI and the AACP manager look the device up in devices.json, a temporary one keeps
+    // the simulated device out of the real list
+    let data_dir = override_data_dir(
+        std::env::temp_dir().join(format!("librepods-simulation-{}", std::process::id())),
+    );
+    let devices = HashMap::from([(
+        addr_str.clone(),
+        DeviceData {
+            name: script.name.clone(),
+            type_: DeviceType::AirPods,
+            information: None,
+        },
+    )]);
+    if let Err(e) = write_devices(&data_dir, &devices) {
+        error!(
+            "Failed to set up the simulation data in {}: {}",
+            data_dir.display(),
+            e
+        );
+        return;
+    }
+    debug!("Simulation data is kept in {}", data_dir.display());
+
+    info!(
+        "Simulating AirPods {} from {}",
+        addr_str,
+        script_path.display()
+    );
+    let (host_end, device_end) = MemoryTransport::pair();
+    let local_address = script.local_address.clone();
+    let simulator = tokio::spawn(AirPodsSimulator::new(device_end, script).run());
+
+    // the simulated link can't be reopened, so there is no reconnecting
+    let lifecycle =
+        ConnectionLifecycle::new(addr, device_managers, ui_tx.clone(), tray_handle.clone());
+    if lifecycle.begin().await {
+        match AirPodsDevice::with_transport(
+            addr,
+            Arc::new(host_end),
+            local_address,
+            &lifecycle,
+            tray_handle,
+            ui_tx,
+        )
+        .await
+        {
+            Ok(airpods_device) => {
+                lifecycle.disconnect_when_closed(ClosableChannel::Aacp(
+                    airpods_device.aacp_manager.clone(),
+                ));
+                lifecycle
+                    .ready(Some(airpods_device.aacp_manager), None)
+                    .await;
+            }
+            Err(e) => {
+                error!("Failed to connect to the simulated AirPods: {}", e);
+                lifecycle.disconnected().await;
+            }
+        }
+    }
+
+    if let Err(e) = simulator.await {
+        error!("Simulator task failed: {}", e);
+    }
+    info!("Simulation finished");
+}
+
+fn write_devices(data_dir: &Path, devices: &HashMap<String, DeviceData>) -> std::io::Result<()> {
+    std::fs::create_dir_all(data_dir)?;
+    std::fs::write(get_devices_path(), serde_json::to_string(devices)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::aacp::{AACPEvent, AACPManager};
+    use crate::utils::use_test_data_dir;
+    use tokio::time::timeout;
+
+    const SCRIPT: &str = r#"{
+        "initial": [
+            { "type": "EarDetection", "primary": "InCase", "secondary": "InCase" }
+        ],
+        "events": [
+            { "at_ms": 0, "type": "Battery", "left": { "level": 80, "status": "NotCharging" } },
+            { "at_ms": 10, "type": "EarDetection", "primary": "InEar", "secondary": "InEar" },
+            { "at_ms": 20, "type": "ListeningMode", "mode": 3 },
+            { "at_ms": 30, "type": "ConversationalAwareness", "level": 1 },
+            { "at_ms": 40, "type": "StemPress", "press": "LongPress", "bud": "Left" },
+            { "at_ms": 50, "type": "Disconnect" }
+        ]
+    }"#;
+
+    #[tokio::test]
+    async fn script_reaches_the_manager() {
+        // the manager saves the information the simulator sends
+        use_test_data_dir();
+        let script: SimulatorScript = serde_json::from_str(SCRIPT).unwrap();
+        let addr: Address = script.address.parse().unwrap();
+        let (host_end, device_end) = MemoryTransport::pair();
+        let simulator = tokio::spawn(AirPodsSimulator::new(device_end, script).run());
+
+        let mut manager = AACPManager::new();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        manager
+            .connect_with_transport(addr, Arc::new(host_end))
+            .await;
+        manager.set_event_channel(event_tx).await;
+        manager.send_handshake().await.unwrap();
+        manager.send_notification_request().await.unwrap();
+
+        // the scripted disconnect closes the link once everything was received
+        timeout(Duration::from_secs(5), simulator)
+            .await
+            .unwrap()
+            .unwrap();
+        timeout(Duration::from_secs(5), manager.wait_closed())
+            .await
+            .unwrap();
+
+        let state = manager.state.lock().await;
+        let left = state
+            .battery_info
+            .iter()
+            .find(|b| b.component == BatteryComponent::Left)
+            .unwrap();
+        assert_eq!((left.level, left.status), (80, BatteryStatus::NotCharging));
+        assert_eq!(
+            state.ear_detection_status,
+            vec![EarDetectionStatus::InEar, EarDetectionStatus::InEar]
+        );
+        assert_eq!(
+            state.old_ear_detection_status,
+            vec![EarDetectionStatus::InCase, EarDetectionStatus::InCase]
+        );
+        assert!(
+            state
+                .control_command_status_list
+                .contains(&ControlCommandStatus {
+                    identifier: ControlCommandIdentifiers::ListeningMode,
+                    value: vec![3],
+                })
+        );
+        assert_eq!(state.conversational_awareness_status, 1);
+        drop(state);
+
+        let mut events = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            events.push(event);
+        }
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AACPEvent::ControlCommand(status)
+                if status.identifier == ControlCommandIdentifiers::ListeningMode
+                    && status.value == [3]
+        )));
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, AACPEvent::ConversationalAwareness(1)))
+        );
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AACPEvent::StemPress(StemPressType::LongPress, StemPressBudType::Left)
+        )));
+    }
+}
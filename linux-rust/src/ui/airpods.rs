@@ -1,28 +1,44 @@
-use crate::bluetooth::aacp::{AACPManager, ControlCommandIdentifiers};
+use crate::bluetooth::aacp::{
+    AACPManager, AUTO_ANC_STRENGTH_MAX, ControlCommandIdentifiers, HEADPHONE_ACCOMMODATION_BANDS,
+    HeadphoneAccommodation, ListeningModeCycle,
+};
+use crate::bluetooth::att::ATTManager;
+use crate::devices::desired_settings::{SYNCED_SETTINGS, synced_setting_name};
+use crate::devices::enums::{
+    AdaptiveStrengthPreset, AirPodsNoiseControlMode, AirPodsState, DeviceData, DeviceInformation,
+    DeviceState, HeadphoneAccommodationPreset,
+};
+use crate::devices::stem_press::{StemPressAction, StemPressSettings};
+use crate::devices::transparency::{
+    AMBIENT_NOISE_REDUCTION_RANGE, AMPLIFICATION_RANGE, BudTransparency, EQ_RANGE, TONE_RANGE,
+    TRANSPARENCY_EQ_BANDS, TransparencySettings,
+};
+use crate::head_tracking::orientation::request_recenter;
+use crate::ui::window::{Message, Tab};
+use crate::utils::{AttChannelStatus, DeviceIdStatus};
 use iced::Alignment::End;
 use iced::border::Radius;
 use iced::overlay::menu;
 use iced::widget::button::Style;
 use iced::widget::rule::FillMode;
 use iced::widget::{
-    Rule, Space, button, column, combo_box, container, row, rule, text, text_input, toggler,
+    Rule, Space, button, checkbox, column, combo_box, container, row, rule, slider, text,
+    text_input, toggler, vertical_slider,
 };
-use iced::{Background, Border, Center, Color, Length, Padding, Theme};
+use iced::{Background, Border, Center, Color, Element, Length, Padding, Theme};
 use log::error;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::thread;
 use tokio::runtime::Runtime;
-// use crate::bluetooth::att::ATTManager;
-use crate::devices::enums::{AirPodsState, DeviceData, DeviceInformation, DeviceState};
-use crate::ui::window::Message;
 
 pub fn airpods_view<'a>(
     mac: &'a str,
     devices_list: &HashMap<String, DeviceData>,
     state: &'a AirPodsState,
     aacp_manager: Arc<AACPManager>,
-    // att_manager: Arc<ATTManager>
+    att_manager: Option<Arc<ATTManager>>,
+    att_status: Option<&AttChannelStatus>,
 ) -> iced::widget::Container<'a, Message> {
     let mac = mac.to_string();
     // order: name, noise control, press and hold config, call controls (not sure if why it might be needed, adding it just in case), audio (personalized volume, conversational awareness, adaptive audio slider), connection settings, microphone, head gestures (not adding this), off listening mode, device information
@@ -361,6 +377,172 @@ pub fn airpods_view<'a>(
             )
     };
 
+    let head_tracking_toggle = {
+        let aacp_manager_ht = aacp_manager.clone();
+        let mac = mac.clone();
+        let recenter_button: Element<'a, Message> = if state.head_tracking_enabled {
+            let mac = mac.clone();
+            let state = state.clone();
+            button(text("Recenter").size(14))
+                .padding(Padding {
+                    top: 6.0,
+                    bottom: 6.0,
+                    left: 12.0,
+                    right: 12.0,
+                })
+                .style(|theme: &Theme, status| {
+                    let palette = theme.palette();
+                    let mut style = Style::default();
+                    style.text_color = palette.background;
+                    style.border = Border::default().rounded(8);
+                    style.background = Some(Background::Color(match status {
+                        button::Status::Hovered => palette.primary.scale_alpha(0.8),
+                        _ => palette.primary,
+                    }));
+                    style
+                })
+                .on_press_with(move || {
+                    request_recenter(&mac);
+                    Message::StateChanged(mac.to_string(), DeviceState::AirPods(state.clone()))
+                })
+                .into()
+        } else {
+            Space::with_width(Length::Shrink).into()
+        };
+        container(row![
+            column![
+                text("Head Tracking").size(16),
+                text("Streams head movement from the AirPods. Keep your head still and facing forward for a moment after turning this on or recentering.").size(12).style(
+                    |theme: &Theme| {
+                        let mut style = text::Style::default();
+                        style.color = Some(theme.palette().text.scale_alpha(0.7));
+                        style
+                    }
+                ).width(Length::Fill)
+            ].width(Length::Fill),
+            recenter_button,
+            toggler(state.head_tracking_enabled)
+                .on_toggle(move |is_enabled| {
+                    let aacp_manager = aacp_manager_ht.clone();
+                    run_async_in_thread(
+                        async move {
+                            let result = if is_enabled {
+                                aacp_manager.start_head_tracking().await
+                            } else {
+                                aacp_manager.stop_head_tracking().await
+                            };
+                            if let Err(e) = result {
+                                error!("Failed to toggle head tracking: {}", e);
+                            }
+                        }
+                    );
+                    let mut state = state.clone();
+                    state.head_tracking_enabled = is_enabled;
+                    Message::StateChanged(mac.to_string(), DeviceState::AirPods(state))
+                })
+            .spacing(0)
+            .size(20)
+        ]
+            .align_y(Center)
+            .spacing(8)
+        )
+            .padding(Padding{
+                top: 5.0,
+                bottom: 5.0,
+                left: 18.0,
+                right: 18.0,
+            })
+            .style(
+                |theme: &Theme| {
+                    let mut style = container::Style::default();
+                    style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+                    let mut border = Border::default();
+                    border.color = theme.palette().primary.scale_alpha(0.5);
+                    style.border = border.rounded(16);
+                    style
+                }
+            )
+    };
+
+    let hearing_aid_enabled = state.hearing_aid_enabled && state.hearing_assist_enabled;
+    let hearing_aid_row = container(
+        row![
+            column![
+                text("Hearing Aid").size(16),
+                text(if hearing_aid_enabled { "On" } else { "Off" })
+                    .size(12)
+                    .style(|theme: &Theme| {
+                        let mut style = text::Style::default();
+                        style.color = Some(theme.palette().text.scale_alpha(0.7));
+                        style
+                    })
+            ]
+            .width(Length::Fill),
+            button(text("Adjust 􀆊").size(14))
+                .style(|theme: &Theme, _status| {
+                    let mut style = Style::default();
+                    style.text_color = theme.palette().primary;
+                    style.background = Some(Background::Color(Color::TRANSPARENT));
+                    style
+                })
+                .padding(0)
+                .on_press(Message::SelectTab(Tab::HearingAid(mac.clone())))
+        ]
+        .align_y(Center),
+    )
+    .padding(Padding {
+        top: 5.0,
+        bottom: 5.0,
+        left: 18.0,
+        right: 18.0,
+    })
+    .style(|theme: &Theme| {
+        let mut style = container::Style::default();
+        style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+        let mut border = Border::default();
+        border.color = theme.palette().primary.scale_alpha(0.5);
+        style.border = border.rounded(16);
+        style
+    });
+
+    let adaptive_strength_col =
+        if matches!(state.noise_control_mode, AirPodsNoiseControlMode::Adaptive) {
+            column![
+                Space::with_height(Length::from(10)),
+                adaptive_strength_slider(&mac, state.auto_anc_strength)
+            ]
+        } else {
+            column![]
+        };
+
+    let accommodation_col = if state.headphone_accommodation.is_some() {
+        column![
+            headphone_accommodation_section(mac, state),
+            Space::with_height(Length::from(20))
+        ]
+    } else {
+        column![]
+    };
+
+    // these need the ATT channel, which isn't available everywhere
+    let att_col = if att_manager.is_some() {
+        let mut col = column![];
+        if let Some(enabled) = state.loud_sound_reduction {
+            col = col.push(loud_sound_reduction_toggle(&mac, enabled));
+            col = col.push(Space::with_height(Length::from(20)));
+        }
+        if state.transparency.is_some() {
+            col = col.push(transparency_section(&mac, state));
+            col = col.push(Space::with_height(Length::from(20)));
+        }
+        col
+    } else {
+        column![
+            att_unavailable_notice(att_status),
+            Space::with_height(Length::from(20))
+        ]
+    };
+
     let mut information_col = column![];
     if let Some(device) = devices_list.get(mac_information.as_str()) {
         if let Some(DeviceInformation::AirPods(ref airpods_info)) = device.information {
@@ -510,11 +692,24 @@ pub fn airpods_view<'a>(
         rename_input,
         Space::with_height(Length::from(20)),
         listening_mode,
+        adaptive_strength_col,
         Space::with_height(Length::from(20)),
         audio_settings_col,
         Space::with_height(Length::from(20)),
         off_listening_mode_toggle,
         Space::with_height(Length::from(20)),
+        listening_mode_cycle_section(&mac, aacp_manager.clone(), state),
+        Space::with_height(Length::from(20)),
+        stem_press_section(&mac, state),
+        Space::with_height(Length::from(20)),
+        keep_in_sync_section(&mac, state),
+        Space::with_height(Length::from(20)),
+        head_tracking_toggle,
+        Space::with_height(Length::from(20)),
+        accommodation_col,
+        hearing_aid_row,
+        Space::with_height(Length::from(20)),
+        att_col,
         information_col
     ])
     .padding(20)
@@ -531,3 +726,786 @@ where
         rt.block_on(fut);
     });
 }
+
+fn adaptive_strength_slider<'a>(mac: &str, strength: Option<u8>) -> Element<'a, Message> {
+    let strength = strength.unwrap_or(AdaptiveStrengthPreset::Default.value());
+    let description_style = |theme: &Theme| {
+        let mut style = text::Style::default();
+        style.color = Some(theme.palette().text.scale_alpha(0.7));
+        style
+    };
+    let mac_change = mac.to_string();
+    container(
+        column![
+            row![
+                text("Adaptive Strength").size(16).width(Length::Fill),
+                text(format!("{}", strength)).size(14)
+            ]
+            .align_y(Center),
+            text("How much noise Adaptive mode lets through. Lower values let more of your surroundings in.")
+                .size(12)
+                .style(description_style)
+                .width(Length::Fill),
+            row![
+                text("Less").size(12).style(description_style),
+                slider(0..=AUTO_ANC_STRENGTH_MAX, strength, move |strength| {
+                    Message::AutoAncStrengthChanged(mac_change.clone(), strength, false)
+                })
+                .on_release(Message::AutoAncStrengthChanged(
+                    mac.to_string(),
+                    strength,
+                    true
+                )),
+                text("More").size(12).style(description_style)
+            ]
+            .spacing(8)
+            .align_y(Center)
+        ]
+        .spacing(4),
+    )
+    .padding(Padding {
+        top: 12.0,
+        bottom: 12.0,
+        left: 18.0,
+        right: 18.0,
+    })
+    .style(|theme: &Theme| {
+        let mut style = container::Style::default();
+        style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+        let mut border = Border::default();
+        border.color = theme.palette().primary.scale_alpha(0.5);
+        style.border = border.rounded(16);
+        style
+    })
+    .into()
+}
+
+/// Checkboxes for the listening modes a long press on the stem cycles through.
+fn listening_mode_cycle_section<'a>(
+    mac: &str,
+    aacp_manager: Arc<AACPManager>,
+    state: &AirPodsState,
+) -> Element<'a, Message> {
+    let cycle = state.listening_mode_cycle;
+    let mut modes = vec![
+        (ListeningModeCycle::NOISE_CANCELLATION, "Noise Cancellation"),
+        (ListeningModeCycle::TRANSPARENCY, "Transparency"),
+        (ListeningModeCycle::ADAPTIVE, "Adaptive"),
+    ];
+    if state.allow_off_mode {
+        modes.insert(0, (ListeningModeCycle::OFF, "Off"));
+    }
+
+    let mut col = column![
+        text("Press and Hold Cycles Between").size(16),
+        text("Pressing and holding the stem switches between the selected listening modes. At least two have to be selected.")
+            .size(12)
+            .style(|theme: &Theme| {
+                let mut style = text::Style::default();
+                style.color = Some(theme.palette().text.scale_alpha(0.7));
+                style
+            })
+            .width(Length::Fill)
+    ]
+    .spacing(8);
+
+    for (mode, label) in modes {
+        let checked = cycle.contains(mode);
+        // unchecking would leave the AirPods with a single mode, which they refuse
+        let can_toggle = !checked || cycle.mode_count() > ListeningModeCycle::MIN_MODES;
+        let mac = mac.to_string();
+        let aacp_manager = aacp_manager.clone();
+        let state = state.clone();
+        col = col.push(checkbox(label, checked).text_size(14).on_toggle_maybe(
+            can_toggle.then_some(move |is_checked| {
+                let new_cycle = cycle.with(mode, is_checked);
+                let aacp_manager = aacp_manager.clone();
+                let mac_for_log = mac.clone();
+                run_async_in_thread(async move {
+                    if let Err(e) = aacp_manager.send_listening_mode_cycle(new_cycle).await {
+                        error!(
+                            "Failed to send listening mode cycle for {}: {}",
+                            mac_for_log, e
+                        );
+                    }
+                });
+                let mut state = state.clone();
+                state.listening_mode_cycle = new_cycle;
+                Message::StateChanged(mac.clone(), DeviceState::AirPods(state))
+            }),
+        ));
+    }
+
+    container(col)
+        .padding(Padding {
+            top: 12.0,
+            bottom: 12.0,
+            left: 18.0,
+            right: 18.0,
+        })
+        .width(Length::Fill)
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+        .into()
+}
+
+/// An action per press type and bud. Presses left on "AirPods default" are not reported to us at
+/// all, so they keep working with the phone.
+fn stem_press_section<'a>(mac: &str, state: &'a AirPodsState) -> Element<'a, Message> {
+    let description_style = |theme: &Theme| {
+        let mut style = text::Style::default();
+        style.color = Some(theme.palette().text.scale_alpha(0.7));
+        style
+    };
+
+    let change = {
+        let mac = mac.to_string();
+        let settings = state.stem_press.clone();
+        move |index: usize, update: &dyn Fn(&mut StemPressSettings, usize)| {
+            let mut settings = settings.clone();
+            update(&mut settings, index);
+            Message::StemPressChanged(mac.clone(), settings)
+        }
+    };
+
+    let mut col = column![
+        text("Press Actions").size(16),
+        text("What pressing the stem does on this computer. Keys are sent with wtype on Wayland and xdotool on X11.")
+            .size(12)
+            .style(description_style)
+            .width(Length::Fill)
+    ]
+    .spacing(8);
+
+    for ((index, binding), action_state) in state
+        .stem_press
+        .bindings
+        .iter()
+        .enumerate()
+        .zip(&state.stem_press_action_states)
+    {
+        let change_action = change.clone();
+        col = col.push(
+            row![
+                text(format!("{}, {}", binding.bud, binding.press))
+                    .size(14)
+                    .width(Length::Fill),
+                combo_box(
+                    action_state,
+                    "Select action",
+                    Some(&binding.action),
+                    move |action: StemPressAction| {
+                        change_action(index, &|settings, index| {
+                            settings.bindings[index].action = action
+                        })
+                    }
+                )
+                .width(Length::from(200))
+                .input_style(|theme: &Theme, _status| text_input::Style {
+                    background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+                    border: Border {
+                        width: 1.0,
+                        color: theme.palette().text.scale_alpha(0.3),
+                        radius: Radius::from(4.0),
+                    },
+                    icon: Default::default(),
+                    placeholder: theme.palette().text,
+                    value: theme.palette().text,
+                    selection: Default::default(),
+                })
+                .padding(Padding {
+                    top: 5.0,
+                    bottom: 5.0,
+                    left: 10.0,
+                    right: 10.0,
+                })
+                .menu_style(|theme: &Theme| menu::Style {
+                    background: Background::Color(theme.palette().background),
+                    border: Border {
+                        width: 1.0,
+                        color: theme.palette().text,
+                        radius: Radius::from(4.0),
+                    },
+                    text_color: theme.palette().text,
+                    selected_text_color: theme.palette().text,
+                    selected_background: Background::Color(
+                        theme.palette().primary.scale_alpha(0.3),
+                    ),
+                })
+            ]
+            .align_y(Center),
+        );
+
+        let argument = match binding.action {
+            StemPressAction::RunCommand => Some(("Shell command", binding.command.as_str(), true)),
+            StemPressAction::EmitKey => {
+                Some(("Key, e.g. XF86AudioMute", binding.key.as_str(), false))
+            }
+            _ => None,
+        };
+        if let Some((placeholder, value, is_command)) = argument {
+            let change_argument = change.clone();
+            col = col.push(
+                row![
+                    Space::with_width(Length::Fill),
+                    text_input(placeholder, value)
+                        .on_input(move |value| {
+                            change_argument(index, &|settings, index| {
+                                if is_command {
+                                    settings.bindings[index].command = value.clone();
+                                } else {
+                                    settings.bindings[index].key = value.clone();
+                                }
+                            })
+                        })
+                        .size(14)
+                        .padding(Padding {
+                            top: 5.0,
+                            bottom: 5.0,
+                            left: 10.0,
+                            right: 10.0,
+                        })
+                        .style(|theme: &Theme, _status| text_input::Style {
+                            background: Background::Color(
+                                theme.palette().primary.scale_alpha(0.2),
+                            ),
+                            border: Border {
+                                width: 1.0,
+                                color: theme.palette().text.scale_alpha(0.3),
+                                radius: Radius::from(4.0),
+                            },
+                            icon: Default::default(),
+                            placeholder: theme.palette().text.scale_alpha(0.5),
+                            value: theme.palette().text,
+                            selection: Default::default(),
+                        })
+                        .width(Length::from(300))
+                ]
+                .align_y(Center),
+            );
+        }
+    }
+
+    container(col)
+        .padding(Padding {
+            top: 12.0,
+            bottom: 12.0,
+            left: 18.0,
+            right: 18.0,
+        })
+        .width(Length::Fill)
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+        .into()
+}
+
+fn keep_in_sync_section<'a>(mac: &str, state: &AirPodsState) -> Element<'a, Message> {
+    let description_style = |theme: &Theme| {
+        let mut style = text::Style::default();
+        style.color = Some(theme.palette().text.scale_alpha(0.7));
+        style
+    };
+
+    let mut col = column![
+        text("Keep in Sync").size(16),
+        text("Connecting to an Apple device overwrites these settings. The ones switched on are set back to what was last chosen here whenever the AirPods connect.")
+            .size(12)
+            .style(description_style)
+            .width(Length::Fill)
+    ]
+    .spacing(8);
+
+    for identifier in SYNCED_SETTINGS {
+        let mac = mac.to_string();
+        col = col.push(
+            row![
+                text(synced_setting_name(identifier))
+                    .size(14)
+                    .width(Length::Fill),
+                toggler(state.desired_settings.keeps_in_sync(identifier))
+                    .on_toggle(move |keep_in_sync| {
+                        Message::KeepInSyncChanged(mac.clone(), identifier, keep_in_sync)
+                    })
+                    .spacing(0)
+                    .size(20)
+            ]
+            .align_y(Center),
+        );
+    }
+
+    container(col)
+        .padding(Padding {
+            top: 12.0,
+            bottom: 12.0,
+            left: 18.0,
+            right: 18.0,
+        })
+        .width(Length::Fill)
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+        .into()
+}
+
+fn loud_sound_reduction_toggle<'a>(mac: &str, enabled: bool) -> Element<'a, Message> {
+    let mac = mac.to_string();
+    container(
+        row![
+            column![
+                text("Loud Sound Reduction").size(16),
+                text("Reduces exposure to loud environmental noise.")
+                    .size(12)
+                    .style(|theme: &Theme| {
+                        let mut style = text::Style::default();
+                        style.color = Some(theme.palette().text.scale_alpha(0.7));
+                        style
+                    })
+                    .width(Length::Fill)
+            ]
+            .width(Length::Fill),
+            toggler(enabled)
+                .on_toggle(move |is_enabled| {
+                    Message::LoudSoundReductionToggled(mac.clone(), is_enabled)
+                })
+                .spacing(0)
+                .size(20)
+        ]
+        .align_y(Center)
+        .spacing(8),
+    )
+    .padding(Padding {
+        top: 5.0,
+        bottom: 5.0,
+        left: 18.0,
+        right: 18.0,
+    })
+    .style(|theme: &Theme| {
+        let mut style = container::Style::default();
+        style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+        let mut border = Border::default();
+        border.color = theme.palette().primary.scale_alpha(0.5);
+        style.border = border.rounded(16);
+        style
+    })
+    .into()
+}
+
+fn att_unavailable_notice<'a>(status: Option<&AttChannelStatus>) -> Element<'a, Message> {
+    let description_style = |theme: &Theme| {
+        let mut style = text::Style::default();
+        style.color = Some(theme.palette().text.scale_alpha(0.7));
+        style
+    };
+
+    let mut col = column![
+        text("ATT unavailable").size(16),
+        text("Loud Sound Reduction, Customized Transparency mode and Hearing Aid adjustments need the ATT channel, which isn't connected for this device.")
+            .size(12)
+            .style(description_style)
+            .width(Length::Fill)
+    ]
+    .spacing(4);
+
+    match status {
+        Some(AttChannelStatus::BlockedByDeviceId(device_id_status)) => {
+            col = col.push(
+                text(format!(
+                    "The AirPods only allow it for Apple devices, but the BlueZ DeviceID is: {}. Set it, restart Bluetooth and reconnect the AirPods.",
+                    device_id_status
+                ))
+                .size(12)
+                .style(description_style)
+                .width(Length::Fill),
+            );
+            if *device_id_status != DeviceIdStatus::FileNotFound {
+                col = col.push(
+                    button(text("Configure DeviceID").size(14))
+                        .padding(Padding {
+                            top: 5.0,
+                            bottom: 5.0,
+                            left: 12.0,
+                            right: 12.0,
+                        })
+                        .on_press(Message::ConfigureDeviceId),
+                );
+            }
+        }
+        Some(AttChannelStatus::Unreachable(e)) => {
+            col = col.push(
+                text(format!("Connecting failed: {}", e))
+                    .size(12)
+                    .style(description_style)
+                    .width(Length::Fill),
+            );
+        }
+        Some(AttChannelStatus::Available) | None => {}
+    }
+
+    container(col)
+        .padding(Padding {
+            top: 12.0,
+            bottom: 12.0,
+            left: 18.0,
+            right: 18.0,
+        })
+        .width(Length::Fill)
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+        .into()
+}
+
+fn transparency_section<'a>(mac: &str, state: &AirPodsState) -> Element<'a, Message> {
+    let Some(settings) = state.transparency.clone() else {
+        return Space::with_height(Length::Shrink).into();
+    };
+
+    let description_style = |theme: &Theme| {
+        let mut style = text::Style::default();
+        style.color = Some(theme.palette().text.scale_alpha(0.7));
+        style
+    };
+
+    let enabled_toggle = row![
+        column![
+            text("Customize Transparency Mode").size(16),
+            text("Applies the EQ, amplification, tone and noise reduction below while in Transparency mode.")
+                .size(12)
+                .style(description_style)
+                .width(Length::Fill)
+        ]
+        .width(Length::Fill),
+        toggler(settings.enabled)
+            .on_toggle({
+                let mac = mac.to_string();
+                let settings = settings.clone();
+                move |is_enabled| {
+                    Message::TransparencyChanged(
+                        mac.clone(),
+                        TransparencySettings {
+                            enabled: is_enabled,
+                            ..settings.clone()
+                        },
+                        true,
+                    )
+                }
+            })
+            .spacing(0)
+            .size(20)
+    ]
+    .align_y(Center)
+    .spacing(8);
+
+    let mut col = column![enabled_toggle].spacing(12);
+
+    if settings.enabled {
+        let per_bud_toggle = row![
+            text("Adjust each bud separately")
+                .size(14)
+                .width(Length::Fill),
+            toggler(state.transparency_per_bud)
+                .on_toggle({
+                    let mac = mac.to_string();
+                    let state = state.clone();
+                    move |is_enabled| {
+                        let mut state = state.clone();
+                        state.transparency_per_bud = is_enabled;
+                        Message::StateChanged(mac.clone(), DeviceState::AirPods(state))
+                    }
+                })
+                .spacing(0)
+                .size(16)
+        ]
+        .align_y(Center);
+        col = col.push(per_bud_toggle);
+
+        if state.transparency_per_bud {
+            col = col
+                .push(transparency_bud_controls(mac, &settings, Some(Bud::Left)))
+                .push(transparency_bud_controls(mac, &settings, Some(Bud::Right)));
+        } else {
+            col = col.push(transparency_bud_controls(mac, &settings, None));
+        }
+    }
+
+    container(col)
+        .padding(Padding {
+            top: 12.0,
+            bottom: 12.0,
+            left: 18.0,
+            right: 18.0,
+        })
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+        .into()
+}
+
+fn headphone_accommodation_section<'a>(mac: &str, state: &'a AirPodsState) -> Element<'a, Message> {
+    let Some(accommodation) = state.headphone_accommodation else {
+        return Space::with_height(Length::Shrink).into();
+    };
+    let preset = HeadphoneAccommodationPreset::from_eq(&accommodation.eq);
+
+    let description_style = |theme: &Theme| {
+        let mut style = text::Style::default();
+        style.color = Some(theme.palette().text.scale_alpha(0.7));
+        style
+    };
+
+    let change = {
+        let mac = mac.to_string();
+        move |accommodation: HeadphoneAccommodation, write: bool| {
+            Message::HeadphoneAccommodationChanged(mac.clone(), accommodation, write)
+        }
+    };
+
+    let usage_toggle =
+        |label: &'static str, value: bool, set: fn(&mut HeadphoneAccommodation, bool)| {
+            let change = change.clone();
+            row![
+                text(label).size(14).width(Length::Fill),
+                toggler(value)
+                    .on_toggle(move |is_enabled| {
+                        let mut accommodation = accommodation;
+                        set(&mut accommodation, is_enabled);
+                        change(accommodation, true)
+                    })
+                    .spacing(0)
+                    .size(16)
+            ]
+            .align_y(Center)
+        };
+
+    let preset_row = row![
+        text("Preset").size(14).width(Length::Fill),
+        combo_box(
+            &state.accommodation_preset_state,
+            "Select Preset",
+            Some(&preset),
+            {
+                let change = change.clone();
+                move |selected: HeadphoneAccommodationPreset| {
+                    // choosing Custom keeps the current bands for editing
+                    let mut accommodation = accommodation;
+                    if let Some(eq) = selected.eq() {
+                        accommodation.eq = eq;
+                    }
+                    change(accommodation, true)
+                }
+            }
+        )
+        .width(Length::from(200))
+        .input_style(|theme: &Theme, _status| text_input::Style {
+            background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+            border: Border {
+                width: 1.0,
+                color: theme.palette().text.scale_alpha(0.3),
+                radius: Radius::from(4.0),
+            },
+            icon: Default::default(),
+            placeholder: theme.palette().text,
+            value: theme.palette().text,
+            selection: Default::default(),
+        })
+        .padding(Padding {
+            top: 5.0,
+            bottom: 5.0,
+            left: 10.0,
+            right: 10.0,
+        })
+        .menu_style(|theme: &Theme| menu::Style {
+            background: Background::Color(theme.palette().background),
+            border: Border {
+                width: 1.0,
+                color: theme.palette().text,
+                radius: Radius::from(4.0),
+            },
+            text_color: theme.palette().text,
+            selected_text_color: theme.palette().text,
+            selected_background: Background::Color(theme.palette().primary.scale_alpha(0.3)),
+        })
+    ]
+    .align_y(Center);
+
+    let mut eq_row = row![].spacing(8).align_y(Center);
+    for band in 0..HEADPHONE_ACCOMMODATION_BANDS {
+        let change = change.clone();
+        eq_row = eq_row.push(
+            vertical_slider(EQ_RANGE, accommodation.eq[band], move |value| {
+                let mut accommodation = accommodation;
+                accommodation.eq[band] = value;
+                change(accommodation, false)
+            })
+            .on_release(change(accommodation, true))
+            .height(100),
+        );
+    }
+
+    let col = column![
+        column![
+            text("Headphone Accommodation").size(16),
+            text("Boosts soft sounds and adjusts frequencies to suit your hearing.")
+                .size(12)
+                .style(description_style)
+                .width(Length::Fill)
+        ],
+        usage_toggle("Phone", accommodation.phone, |a, value| a.phone = value),
+        usage_toggle("Media", accommodation.media, |a, value| a.media = value),
+        preset_row,
+        row![text("EQ").size(14).width(Length::from(180)), eq_row].align_y(Center),
+    ]
+    .spacing(12);
+
+    container(col)
+        .padding(Padding {
+            top: 12.0,
+            bottom: 12.0,
+            left: 18.0,
+            right: 18.0,
+        })
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+        .into()
+}
+
+#[derive(Clone, Copy)]
+enum Bud {
+    Left,
+    Right,
+}
+
+/// Sliders for one bud, or for both buds at once (taking the left bud's values) when `bud` is
+/// `None`.
+fn transparency_bud_controls<'a>(
+    mac: &str,
+    settings: &TransparencySettings,
+    bud: Option<Bud>,
+) -> Element<'a, Message> {
+    let current = match bud {
+        Some(Bud::Right) => settings.right,
+        _ => settings.left,
+    };
+
+    // builds the message for a changed value, `write` is false while a slider is being dragged
+    let change = {
+        let mac = mac.to_string();
+        let settings = settings.clone();
+        move |update: &dyn Fn(&mut BudTransparency), write: bool| {
+            let mut new_settings = settings.clone();
+            match bud {
+                Some(Bud::Left) => update(&mut new_settings.left),
+                Some(Bud::Right) => update(&mut new_settings.right),
+                None => {
+                    update(&mut new_settings.left);
+                    new_settings.right = new_settings.left;
+                }
+            }
+            Message::TransparencyChanged(mac.clone(), new_settings, write)
+        }
+    };
+    let write_current = change(&|_| {}, true);
+
+    let mut eq_row = row![].spacing(8).align_y(Center);
+    for band in 0..TRANSPARENCY_EQ_BANDS {
+        let change = change.clone();
+        eq_row = eq_row.push(
+            vertical_slider(EQ_RANGE, current.eq[band], move |value| {
+                change(&|bud| bud.eq[band] = value, false)
+            })
+            .on_release(write_current.clone())
+            .height(100),
+        );
+    }
+
+    let labeled_slider = |label: &'static str,
+                          range: std::ops::RangeInclusive<f32>,
+                          value: f32,
+                          set: fn(&mut BudTransparency, f32)| {
+        let change = change.clone();
+        row![
+            text(label).size(14).width(Length::from(180)),
+            slider(range, value, move |value| {
+                change(&|bud| set(bud, value), false)
+            })
+            .step(0.01_f32)
+            .on_release(write_current.clone())
+        ]
+        .align_y(Center)
+    };
+
+    let change_boost = change.clone();
+    let title = match bud {
+        Some(Bud::Left) => "Left",
+        Some(Bud::Right) => "Right",
+        None => "Both buds",
+    };
+
+    column![
+        text(title).size(14).style(|theme: &Theme| {
+            let mut style = text::Style::default();
+            style.color = Some(theme.palette().primary);
+            style
+        }),
+        row![text("EQ").size(14).width(Length::from(180)), eq_row].align_y(Center),
+        labeled_slider(
+            "Amplification",
+            AMPLIFICATION_RANGE,
+            current.amplification,
+            |bud, value| bud.amplification = value
+        ),
+        labeled_slider("Tone", TONE_RANGE, current.tone, |bud, value| bud.tone =
+            value),
+        labeled_slider(
+            "Ambient Noise Reduction",
+            AMBIENT_NOISE_REDUCTION_RANGE,
+            current.ambient_noise_reduction,
+            |bud, value| bud.ambient_noise_reduction = value
+        ),
+        row![
+            text("Conversation Boost").size(14).width(Length::Fill),
+            toggler(current.conversation_boost)
+                .on_toggle(move |is_enabled| {
+                    change_boost(&|bud| bud.conversation_boost = is_enabled, true)
+                })
+                .spacing(0)
+                .size(16)
+        ]
+        .align_y(Center)
+    ]
+    .spacing(8)
+    .into()
+}
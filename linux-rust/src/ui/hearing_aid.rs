@@ -0,0 +1,310 @@
+use crate::devices::enums::AirPodsState;
+use crate::devices::hearing_aid::{
+    AMBIENT_NOISE_REDUCTION_RANGE, AMPLIFICATION_RANGE, BALANCE_RANGE, BAND_FREQUENCIES,
+    HEARING_AID_BANDS, HearingAidSettings, LOSS_RANGE, OWN_VOICE_AMPLIFICATION_RANGE, TONE_RANGE,
+};
+use crate::ui::window::{Message, Tab};
+use iced::widget::button::Style;
+use iced::widget::{
+    Space, button, column, container, row, scrollable, slider, text, text_input, toggler,
+    vertical_slider,
+};
+use iced::{Background, Border, Center, Color, Element, Length, Padding, Theme};
+
+pub fn hearing_aid_view<'a>(
+    mac: &str,
+    state: &'a AirPodsState,
+    att_available: bool,
+    audiogram_path: &str,
+    audiogram_error: Option<&str>,
+) -> iced::widget::Container<'a, Message> {
+    let enabled = state.hearing_aid_enabled && state.hearing_assist_enabled;
+
+    let header = row![
+        button(text("􀯶 Back").size(16))
+            .style(|theme: &Theme, _status| {
+                let mut style = Style::default();
+                style.text_color = theme.palette().primary;
+                style.background = Some(Background::Color(Color::TRANSPARENT));
+                style
+            })
+            .padding(0)
+            .on_press(Message::SelectTab(Tab::Device(mac.to_string()))),
+        Space::with_width(Length::Fill),
+        text("Hearing Aid").size(20).style(|theme: &Theme| {
+            let mut style = text::Style::default();
+            style.color = Some(theme.palette().primary);
+            style
+        }),
+        Space::with_width(Length::Fill),
+    ]
+    .align_y(Center);
+
+    let enable_toggle = section(
+        row![
+            column![
+                text("Hearing Aid").size(16),
+                text("Turning this on disables Headphone Accommodation and Customized Transparency mode.")
+                    .size(12)
+                    .style(description_style)
+                    .width(Length::Fill)
+            ]
+            .width(Length::Fill),
+            toggler(enabled)
+                .on_toggle({
+                    let mac = mac.to_string();
+                    move |is_enabled| Message::HearingAidToggled(mac.clone(), is_enabled)
+                })
+                .spacing(0)
+                .size(20)
+        ]
+        .align_y(Center)
+        .spacing(8),
+        5.0,
+    );
+
+    let adjustments: Element<'a, Message> = match (&state.hearing_aid, att_available) {
+        (Some(settings), true) => column![
+            section(loss_controls(mac, settings), 12.0),
+            Space::with_height(Length::from(20)),
+            section(audiogram_import(mac, audiogram_path, audiogram_error), 12.0),
+            Space::with_height(Length::from(20)),
+            section(adjustment_controls(mac, settings), 12.0),
+        ]
+        .into(),
+        (None, true) => section(text("Reading hearing aid settings...").size(14), 12.0).into(),
+        (_, false) => section(
+            column![
+                text("Adjustments unavailable").size(16),
+                text("Hearing loss, amplification and tone are set over the ATT channel, which isn't connected for this device.")
+                    .size(12)
+                    .style(description_style)
+                    .width(Length::Fill)
+            ],
+            12.0,
+        )
+        .into(),
+    };
+
+    container(scrollable(
+        column![
+            header,
+            Space::with_height(Length::from(20)),
+            enable_toggle,
+            Space::with_height(Length::from(20)),
+            adjustments
+        ]
+        .padding(20),
+    ))
+    .center_x(Length::Fill)
+    .height(Length::Fill)
+}
+
+fn description_style(theme: &Theme) -> text::Style {
+    let mut style = text::Style::default();
+    style.color = Some(theme.palette().text.scale_alpha(0.7));
+    style
+}
+
+fn section<'a>(
+    content: impl Into<Element<'a, Message>>,
+    vertical_padding: f32,
+) -> iced::widget::Container<'a, Message> {
+    container(content)
+        .padding(Padding {
+            top: vertical_padding,
+            bottom: vertical_padding,
+            left: 18.0,
+            right: 18.0,
+        })
+        .width(Length::Fill)
+        .style(|theme: &Theme| {
+            let mut style = container::Style::default();
+            style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+            let mut border = Border::default();
+            border.color = theme.palette().primary.scale_alpha(0.5);
+            style.border = border.rounded(16);
+            style
+        })
+}
+
+/// Hearing loss sliders for both ears, one per band.
+fn loss_controls<'a>(mac: &str, settings: &HearingAidSettings) -> Element<'a, Message> {
+    let change = {
+        let mac = mac.to_string();
+        let settings = settings.clone();
+        move |update: &dyn Fn(&mut HearingAidSettings), write: bool| {
+            let mut new_settings = settings.clone();
+            update(&mut new_settings);
+            Message::HearingAidChanged(mac.clone(), new_settings, write)
+        }
+    };
+    let write_current = change(&|_| {}, true);
+
+    let mut labels = row![text("").width(Length::from(60))].spacing(8);
+    for frequency in BAND_FREQUENCIES {
+        let label = if frequency >= 1000.0 {
+            format!("{}k", frequency / 1000.0)
+        } else {
+            format!("{}", frequency)
+        };
+        labels = labels.push(text(label).size(12).width(Length::from(32)).align_x(Center));
+    }
+
+    let ear_row = |label: &'static str, loss: [f32; HEARING_AID_BANDS], left: bool| {
+        let mut ear = row![text(label).size(14).width(Length::from(60))]
+            .spacing(8)
+            .align_y(Center);
+        for (band, value) in loss.into_iter().enumerate() {
+            let change = change.clone();
+            ear = ear.push(
+                container(
+                    vertical_slider(LOSS_RANGE, value, move |value| {
+                        change(
+                            &|settings| {
+                                if left {
+                                    settings.left_loss[band] = value;
+                                } else {
+                                    settings.right_loss[band] = value;
+                                }
+                            },
+                            false,
+                        )
+                    })
+                    .step(1.0_f32)
+                    .on_release(write_current.clone())
+                    .height(100),
+                )
+                .center_x(Length::from(32)),
+            );
+        }
+        ear
+    };
+
+    column![
+        text("Hearing Loss (dB HL)").size(16),
+        text("Higher values mean more hearing loss at that frequency.")
+            .size(12)
+            .style(description_style),
+        ear_row("Left", settings.left_loss, true),
+        ear_row("Right", settings.right_loss, false),
+        labels
+    ]
+    .spacing(8)
+    .into()
+}
+
+fn audiogram_import<'a>(
+    mac: &str,
+    audiogram_path: &str,
+    audiogram_error: Option<&str>,
+) -> Element<'a, Message> {
+    let mut col = column![
+        text("Import Audiogram").size(16),
+        text("A CSV file with one frequency (Hz) per row, followed by the left and right ear thresholds in dB HL.")
+            .size(12)
+            .style(description_style)
+            .width(Length::Fill),
+        row![
+            text_input("/path/to/audiogram.csv", audiogram_path)
+                .on_input(Message::AudiogramPathInput)
+                .on_submit(Message::ImportAudiogram(mac.to_string()))
+                .padding(Padding {
+                    top: 5.0,
+                    bottom: 5.0,
+                    left: 10.0,
+                    right: 10.0,
+                }),
+            button(text("Import").size(14))
+                .padding(Padding {
+                    top: 5.0,
+                    bottom: 5.0,
+                    left: 12.0,
+                    right: 12.0,
+                })
+                .on_press_maybe(
+                    (!audiogram_path.trim().is_empty())
+                        .then(|| Message::ImportAudiogram(mac.to_string()))
+                )
+        ]
+        .spacing(8)
+        .align_y(Center)
+    ]
+    .spacing(8);
+
+    if let Some(error) = audiogram_error {
+        col = col.push(text(error.to_string()).size(12).style(|theme: &Theme| {
+            let mut style = text::Style::default();
+            style.color = Some(theme.palette().danger);
+            style
+        }));
+    }
+    col.into()
+}
+
+fn adjustment_controls<'a>(mac: &str, settings: &HearingAidSettings) -> Element<'a, Message> {
+    let change = {
+        let mac = mac.to_string();
+        let settings = settings.clone();
+        move |update: &dyn Fn(&mut HearingAidSettings), write: bool| {
+            let mut new_settings = settings.clone();
+            update(&mut new_settings);
+            Message::HearingAidChanged(mac.clone(), new_settings, write)
+        }
+    };
+    let write_current = change(&|_| {}, true);
+
+    let labeled_slider = |label: &'static str,
+                          range: std::ops::RangeInclusive<f32>,
+                          value: f32,
+                          set: fn(&mut HearingAidSettings, f32)| {
+        let change = change.clone();
+        row![
+            text(label).size(14).width(Length::from(180)),
+            slider(range, value, move |value| {
+                change(&|settings| set(settings, value), false)
+            })
+            .step(0.01_f32)
+            .on_release(write_current.clone())
+        ]
+        .align_y(Center)
+    };
+
+    let change_boost = change.clone();
+    column![
+        labeled_slider(
+            "Amplification",
+            AMPLIFICATION_RANGE,
+            settings.amplification,
+            |s, value| s.amplification = value
+        ),
+        labeled_slider("Balance", BALANCE_RANGE, settings.balance, |s, value| {
+            s.balance = value
+        }),
+        labeled_slider("Tone", TONE_RANGE, settings.tone, |s, value| s.tone = value),
+        labeled_slider(
+            "Ambient Noise Reduction",
+            AMBIENT_NOISE_REDUCTION_RANGE,
+            settings.ambient_noise_reduction,
+            |s, value| s.ambient_noise_reduction = value
+        ),
+        labeled_slider(
+            "Own Voice Amplification",
+            OWN_VOICE_AMPLIFICATION_RANGE,
+            settings.own_voice_amplification,
+            |s, value| s.own_voice_amplification = value
+        ),
+        row![
+            text("Conversation Boost").size(14).width(Length::Fill),
+            toggler(settings.conversation_boost)
+                .on_toggle(move |is_enabled| {
+                    change_boost(&|s| s.conversation_boost = is_enabled, true)
+                })
+                .spacing(0)
+                .size(16)
+        ]
+        .align_y(Center)
+    ]
+    .spacing(8)
+    .into()
+}
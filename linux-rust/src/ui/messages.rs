@@ -1,11 +1,15 @@
 use crate::bluetooth::aacp::AACPEvent;
+use crate::bluetooth::lifecycle::ConnectionState;
+use crate::utils::AttChannelStatus;
 
 #[derive(Debug, Clone)]
 pub enum BluetoothUIMessage {
     OpenWindow,
-    DeviceConnected(String),               // mac
-    DeviceDisconnected(String),            // mac
-    AACPUIEvent(String, AACPEvent),        // mac, event
-    ATTNotification(String, u16, Vec<u8>), // mac, handle, data
+    DeviceConnected(String),                         // mac
+    DeviceDisconnected(String),                      // mac
+    AACPUIEvent(String, AACPEvent),                  // mac, event
+    ATTNotification(String, u16, Vec<u8>),           // mac, handle, data
+    ATTStatus(String, AttChannelStatus),             // mac, status
+    ConnectionStateChanged(String, ConnectionState), // mac, state
     NoOp,
 }
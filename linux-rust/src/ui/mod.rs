@@ -1,4 +1,5 @@
 mod airpods;
+mod hearing_aid;
 pub mod messages;
 mod nothing;
 pub mod tray;
@@ -2,14 +2,20 @@
 
 use ab_glyph::{Font, ScaleFont};
 use ksni::{Icon, ToolTip};
+use std::collections::BTreeMap;
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::bluetooth::aacp::{BatteryStatus, ControlCommandIdentifiers};
+use crate::devices::enums::{AdaptiveStrengthPreset, AirPodsNoiseControlMode};
 use crate::ui::messages::BluetoothUIMessage;
 use crate::utils::get_app_settings_path;
 
-#[derive(Debug)]
-pub struct MyTray {
+/// What the tray knows about one device. AirPods show up as soon as their BLE adverts give away
+/// the batteries, before they are connected.
+#[derive(Debug, Default)]
+pub struct TrayDevice {
+    pub name: String,
+    pub connected: bool,
     pub conversation_detect_enabled: Option<bool>,
     pub battery_headphone: Option<u8>,
     pub battery_headphone_status: Option<BatteryStatus>,
@@ -19,66 +25,49 @@ pub struct MyTray {
     pub battery_r_status: Option<BatteryStatus>,
     pub battery_c: Option<u8>,
     pub battery_c_status: Option<BatteryStatus>,
-    pub connected: bool,
     pub listening_mode: Option<u8>,
     pub allow_off_option: Option<u8>,
+    pub auto_anc_strength: Option<u8>,
     pub command_tx: Option<UnboundedSender<(ControlCommandIdentifiers, Vec<u8>)>>,
-    pub ui_tx: Option<UnboundedSender<BluetoothUIMessage>>,
 }
 
-impl ksni::Tray for MyTray {
-    fn id(&self) -> String {
-        env!("CARGO_PKG_NAME").into()
-    }
-    fn title(&self) -> String {
-        "AirPods".into()
-    }
-    fn icon_pixmap(&self) -> Vec<Icon> {
-        let text = {
-            let mut levels: Vec<u8> = Vec::new();
-            if let Some(h) = self.battery_headphone {
-                if self.battery_headphone_status != Some(BatteryStatus::Disconnected) {
-                    levels.push(h);
-                }
-            } else {
-                if let Some(l) = self.battery_l
-                    && self.battery_l_status != Some(BatteryStatus::Disconnected)
-                {
-                    levels.push(l);
-                }
-                if let Some(r) = self.battery_r
-                    && self.battery_r_status != Some(BatteryStatus::Disconnected)
-                {
-                    levels.push(r);
-                }
-                // if let Some(c) = self.battery_c {
-                //     if self.battery_c_status != Some(BatteryStatus::Disconnected) {
-                //         levels.push(c);
-                //     }
-                // }
+impl TrayDevice {
+    /// The lowest battery level of the parts in use, the case doesn't count.
+    pub(crate) fn min_battery(&self) -> Option<u8> {
+        let mut levels: Vec<u8> = Vec::new();
+        if let Some(h) = self.battery_headphone {
+            if self.battery_headphone_status != Some(BatteryStatus::Disconnected) {
+                levels.push(h);
             }
-            let min_battery = levels.iter().min().copied();
-            if let Some(b) = min_battery {
-                format!("{}", b)
-            } else {
-                "?".to_string()
+        } else {
+            if let Some(l) = self.battery_l
+                && self.battery_l_status != Some(BatteryStatus::Disconnected)
+            {
+                levels.push(l);
             }
-        };
-        let any_bud_charging = matches!(self.battery_l_status, Some(BatteryStatus::Charging))
-            || matches!(self.battery_r_status, Some(BatteryStatus::Charging));
-        let app_settings_path = get_app_settings_path();
-        let settings = std::fs::read_to_string(&app_settings_path)
-            .ok()
-            .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok());
-        let text_mode = settings
-            .clone()
-            .and_then(|v| v.get("tray_text_mode").cloned())
-            .and_then(|ttm| serde_json::from_value(ttm).ok())
-            .unwrap_or(false);
-        let icon = generate_icon(&text, text_mode, any_bud_charging);
-        vec![icon]
+            if let Some(r) = self.battery_r
+                && self.battery_r_status != Some(BatteryStatus::Disconnected)
+            {
+                levels.push(r);
+            }
+        }
+        levels.iter().min().copied()
     }
-    fn tool_tip(&self) -> ToolTip {
+
+    pub(crate) fn any_bud_charging(&self) -> bool {
+        matches!(self.battery_l_status, Some(BatteryStatus::Charging))
+            || matches!(self.battery_r_status, Some(BatteryStatus::Charging))
+            || matches!(self.battery_headphone_status, Some(BatteryStatus::Charging))
+    }
+
+    pub(crate) fn has_battery(&self) -> bool {
+        self.battery_headphone.is_some()
+            || self.battery_l.is_some()
+            || self.battery_r.is_some()
+            || self.battery_c.is_some()
+    }
+
+    fn battery_description(&self) -> String {
         let format_component =
             |label: &str, level: Option<u8>, status: Option<BatteryStatus>| -> String {
                 match status {
@@ -95,19 +84,73 @@ impl ksni::Tray for MyTray {
                 }
             };
 
+        if self.battery_headphone.is_some() {
+            return format_component(
+                "Battery",
+                self.battery_headphone,
+                self.battery_headphone_status,
+            );
+        }
         let l = format_component("L", self.battery_l, self.battery_l_status);
         let r = format_component("R", self.battery_r, self.battery_r_status);
         let c = format_component("C", self.battery_c, self.battery_c_status);
+        format!("{} {} {}", l, r, c)
+    }
 
-        ToolTip {
-            icon_name: "".to_string(),
-            icon_pixmap: vec![],
-            title: "Battery Status".to_string(),
-            description: format!("{} {} {}", l, r, c),
+    pub(crate) fn listening_mode_name(&self) -> Option<String> {
+        self.listening_mode
+            .map(|mode| AirPodsNoiseControlMode::from_byte(&mode).to_string())
+    }
+
+    /// One line about the device as shown in the tooltip, like `AirPods Pro: L: 80% R: 75% C: -`.
+    pub(crate) fn summary(&self, mac: &str) -> String {
+        if self.has_battery() {
+            format!("{}: {}", self.label(mac), self.battery_description())
+        } else {
+            self.label(mac)
         }
     }
-    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+
+    fn label(&self, mac: &str) -> String {
+        let name = if self.name.is_empty() {
+            mac
+        } else {
+            self.name.as_str()
+        };
+        if self.connected {
+            name.to_string()
+        } else {
+            format!("{} (not connected)", name)
+        }
+    }
+
+    /// The menu section of this device. Only connected AirPods can be controlled, other devices
+    /// just list their batteries.
+    fn menu_items(&self, mac: &str) -> Vec<ksni::MenuItem<MyTray>> {
         use ksni::menu::*;
+        let mut items: Vec<ksni::MenuItem<MyTray>> = vec![
+            MenuItem::Separator,
+            StandardItem {
+                label: self.label(mac),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+        ];
+        if self.has_battery() {
+            items.push(
+                StandardItem {
+                    label: self.battery_description(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        if !self.connected || self.command_tx.is_none() {
+            return items;
+        }
+
         let allow_off = self.allow_off_option == Some(0x01);
         let options = if allow_off {
             vec![
@@ -128,22 +171,12 @@ impl ksni::Tray for MyTray {
             .and_then(|mode| options.iter().position(|&(_, val)| val == mode))
             .unwrap_or(0);
         let options_clone = options.clone();
-        vec![
-            StandardItem {
-                label: "Open Window".into(),
-                icon_name: "window-new".into(),
-                activate: Box::new(|this: &mut Self| {
-                    if let Some(tx) = &this.ui_tx {
-                        let _ = tx.send(BluetoothUIMessage::OpenWindow);
-                    }
-                }),
-                ..Default::default()
-            }
-            .into(),
+        let mac_mode = mac.to_string();
+        items.push(
             RadioGroup {
                 selected,
-                select: Box::new(move |this: &mut Self, current| {
-                    if let Some(tx) = &this.command_tx {
+                select: Box::new(move |this: &mut MyTray, current| {
+                    if let Some(tx) = this.command_tx(&mac_mode) {
                         let value = options_clone
                             .get(current)
                             .map(|&(_, val)| val)
@@ -161,14 +194,64 @@ impl ksni::Tray for MyTray {
                 ..Default::default()
             }
             .into(),
-            MenuItem::Separator,
+        );
+        if self.listening_mode == Some(0x04) {
+            // the strength can be anything from 0 to 100, show the closest preset
+            let selected = self
+                .auto_anc_strength
+                .and_then(|strength| {
+                    AdaptiveStrengthPreset::ALL.iter().position(|preset| {
+                        AdaptiveStrengthPreset::ALL.iter().all(|other| {
+                            preset.value().abs_diff(strength) <= other.value().abs_diff(strength)
+                        })
+                    })
+                })
+                .unwrap_or(1);
+            let mac_strength = mac.to_string();
+            items.push(
+                SubMenu {
+                    label: "Adaptive Strength".into(),
+                    submenu: vec![
+                        RadioGroup {
+                            selected,
+                            select: Box::new(move |this: &mut MyTray, current| {
+                                if let Some(tx) = this.command_tx(&mac_strength)
+                                    && let Some(preset) = AdaptiveStrengthPreset::ALL.get(current)
+                                {
+                                    let _ = tx.send((
+                                        ControlCommandIdentifiers::AutoAncStrength,
+                                        vec![preset.value()],
+                                    ));
+                                    this.device_mut(&mac_strength).auto_anc_strength =
+                                        Some(preset.value());
+                                }
+                            }),
+                            options: AdaptiveStrengthPreset::ALL
+                                .iter()
+                                .map(|preset| RadioItem {
+                                    label: preset.to_string(),
+                                    ..Default::default()
+                                })
+                                .collect(),
+                            ..Default::default()
+                        }
+                        .into(),
+                    ],
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        let mac_conversation = mac.to_string();
+        items.push(
             CheckmarkItem {
                 label: "Conversation Detection".into(),
                 checked: self.conversation_detect_enabled.unwrap_or(false),
                 enabled: self.conversation_detect_enabled.is_some(),
-                activate: Box::new(|this: &mut Self| {
-                    if let Some(tx) = &this.command_tx
-                        && let Some(is_enabled) = this.conversation_detect_enabled
+                activate: Box::new(move |this: &mut MyTray| {
+                    let device = this.device_mut(&mac_conversation);
+                    if let Some(tx) = &device.command_tx
+                        && let Some(is_enabled) = device.conversation_detect_enabled
                     {
                         let new_state = !is_enabled;
                         let value = if !new_state { 0x02 } else { 0x01 };
@@ -176,12 +259,147 @@ impl ksni::Tray for MyTray {
                             ControlCommandIdentifiers::ConversationDetectConfig,
                             vec![value],
                         ));
-                        this.conversation_detect_enabled = Some(new_state);
+                        device.conversation_detect_enabled = Some(new_state);
                     }
                 }),
                 ..Default::default()
             }
             .into(),
+        );
+        items
+    }
+}
+
+#[derive(Debug)]
+pub struct MyTray {
+    // keyed by the classic Bluetooth address
+    pub devices: BTreeMap<String, TrayDevice>,
+    // the device the icon shows, the first one that connected and is still connected
+    pub primary: Option<String>,
+    pub ui_tx: Option<UnboundedSender<BluetoothUIMessage>>,
+}
+
+impl MyTray {
+    pub fn new(ui_tx: Option<UnboundedSender<BluetoothUIMessage>>) -> Self {
+        Self {
+            devices: BTreeMap::new(),
+            primary: None,
+            ui_tx,
+        }
+    }
+
+    pub fn device_mut(&mut self, mac: &str) -> &mut TrayDevice {
+        self.devices.entry(mac.to_string()).or_default()
+    }
+
+    fn command_tx(
+        &self,
+        mac: &str,
+    ) -> Option<&UnboundedSender<(ControlCommandIdentifiers, Vec<u8>)>> {
+        self.devices.get(mac).and_then(|d| d.command_tx.as_ref())
+    }
+
+    pub fn device_connected(&mut self, mac: &str, name: String) {
+        let device = self.device_mut(mac);
+        device.connected = true;
+        if !name.is_empty() {
+            device.name = name;
+        }
+        let primary_connected = self
+            .primary
+            .as_ref()
+            .and_then(|p| self.devices.get(p))
+            .is_some_and(|d| d.connected);
+        if !primary_connected {
+            self.primary = Some(mac.to_string());
+        }
+    }
+
+    /// Forgets everything about the device, used when it disconnects.
+    pub fn remove_device(&mut self, mac: &str) {
+        self.devices.remove(mac);
+        if self.primary.as_deref() == Some(mac) {
+            self.primary = self
+                .devices
+                .iter()
+                .find(|(_, d)| d.connected)
+                .map(|(mac, _)| mac.clone());
+        }
+    }
+
+    /// The primary device, or any device with batteries to show when nothing is connected.
+    fn primary_device(&self) -> Option<&TrayDevice> {
+        self.primary
+            .as_ref()
+            .and_then(|p| self.devices.get(p))
+            .or_else(|| self.devices.values().find(|d| d.has_battery()))
+    }
+}
+
+impl ksni::Tray for MyTray {
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").into()
+    }
+    fn title(&self) -> String {
+        "AirPods".into()
+    }
+    fn icon_pixmap(&self) -> Vec<Icon> {
+        let primary = self.primary_device();
+        let text = primary
+            .and_then(|d| d.min_battery())
+            .map(|b| format!("{}", b))
+            .unwrap_or_else(|| "?".to_string());
+        let any_bud_charging = primary.is_some_and(|d| d.any_bud_charging());
+        let app_settings_path = get_app_settings_path();
+        let settings = std::fs::read_to_string(&app_settings_path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok());
+        let text_mode = settings
+            .clone()
+            .and_then(|v| v.get("tray_text_mode").cloned())
+            .and_then(|ttm| serde_json::from_value(ttm).ok())
+            .unwrap_or(false);
+        let icon = generate_icon(&text, text_mode, any_bud_charging);
+        vec![icon]
+    }
+    fn tool_tip(&self) -> ToolTip {
+        let description = self
+            .devices
+            .iter()
+            .map(|(mac, d)| d.summary(mac))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        ToolTip {
+            icon_name: "".to_string(),
+            icon_pixmap: vec![],
+            title: "Battery Status".to_string(),
+            description,
+        }
+    }
+    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        use ksni::menu::*;
+        let mut items = vec![
+            StandardItem {
+                label: "Open Window".into(),
+                icon_name: "window-new".into(),
+                activate: Box::new(|this: &mut Self| {
+                    if let Some(tx) = &this.ui_tx {
+                        let _ = tx.send(BluetoothUIMessage::OpenWindow);
+                    }
+                }),
+                ..Default::default()
+            }
+            .into(),
+        ];
+        // the primary device first, then the others
+        let mut devices: Vec<(&String, &TrayDevice)> = self.devices.iter().collect();
+        devices.sort_by_key(|(mac, _)| self.primary.as_ref() != Some(*mac));
+        for (mac, device) in devices {
+            items.extend(device.menu_items(mac));
+        }
+        items.extend([
+            MenuItem::Separator,
             StandardItem {
                 label: "Exit".into(),
                 icon_name: "application-exit".into(),
@@ -189,7 +407,8 @@ impl ksni::Tray for MyTray {
                 ..Default::default()
             }
             .into(),
-        ]
+        ]);
+        items
     }
 }
 
@@ -1,22 +1,46 @@
 use crate::bluetooth::aacp::{
-    AACPEvent, BatteryComponent, BatteryStatus, ControlCommandIdentifiers,
+    AACPEvent, BatteryComponent, BatteryStatus, ControlCommandIdentifiers, HeadphoneAccommodation,
+    ListeningModeCycle,
 };
+use crate::bluetooth::adapter::{ADAPTER_SETTING_KEY, open_adapter};
+use crate::bluetooth::att::{ATTHandles, ATTManager};
+use crate::bluetooth::battery_provider::{BatteryPolicy, BatteryProviderSettings};
+use crate::bluetooth::lifecycle::ConnectionState;
 use crate::bluetooth::managers::DeviceManagers;
+use crate::devices::desired_settings::DesiredSettings;
 use crate::devices::enums::{
-    AirPodsNoiseControlMode, AirPodsState, DeviceData, DeviceState, DeviceType, NothingAncMode,
-    NothingState,
+    AirPodsNoiseControlMode, AirPodsState, DeviceData, DeviceState, DeviceType,
+    HeadphoneAccommodationPreset, NothingAncMode, NothingState,
 };
+use crate::devices::hearing_aid::{
+    Audiogram, HearingAidSettings, read_hearing_aid_settings, set_hearing_aid_enabled,
+    write_hearing_aid_settings,
+};
+use crate::devices::loud_sound_reduction::{
+    decode_loud_sound_reduction, read_loud_sound_reduction, write_loud_sound_reduction,
+};
+use crate::devices::stem_press::{StemPressAction, StemPressSettings};
+use crate::devices::transparency::{
+    TransparencySettings, read_transparency_settings, write_transparency_settings,
+};
+use crate::head_tracking::gestures::{HeadGestureAction, HeadGestureSettings};
+use crate::head_tracking::opentrack::{DEFAULT_OPENTRACK_PORT, MAX_SMOOTHING, OpenTrackSettings};
+use crate::notifications::{LOW_BATTERY, NotificationSettings};
 use crate::ui::airpods::airpods_view;
+use crate::ui::hearing_aid::hearing_aid_view;
 use crate::ui::messages::BluetoothUIMessage;
 use crate::ui::nothing::nothing_view;
-use crate::utils::{DeviceIdStatus, MyTheme, check_device_id_status, configure_device_id, get_app_settings_path, get_devices_path};
+use crate::utils::{
+    AttChannelStatus, DeviceIdStatus, MyTheme, check_device_id_status, configure_device_id,
+    get_app_settings_path, get_devices_path, save_app_setting,
+};
 use bluer::{Address, Session};
 use iced::border::Radius;
 use iced::overlay::menu;
 use iced::widget::button::Style;
 use iced::widget::rule::FillMode;
 use iced::widget::{
-    Space, button, column, combo_box, container, pane_grid, row, rule, scrollable, text,
+    Space, button, column, combo_box, container, pane_grid, row, rule, scrollable, slider, text,
     text_input, toggler, vertical_rule,
 };
 use iced::{
@@ -59,6 +83,21 @@ pub struct App {
     tray_text_mode: bool,
     device_id_status: DeviceIdStatus,
     device_id_configuring: bool,
+    head_gestures: HeadGestureSettings,
+    nod_action_state: combo_box::State<HeadGestureAction>,
+    shake_action_state: combo_box::State<HeadGestureAction>,
+    opentrack: OpenTrackSettings,
+    opentrack_port_input: String,
+    // typed or dragged head tracking settings are only saved once editing finished
+    head_tracking_unsaved: bool,
+    audiogram_path: String,
+    audiogram_error: Option<String>,
+    att_statuses: HashMap<String, AttChannelStatus>,
+    connection_states: HashMap<String, ConnectionState>,
+    adapter_input: String,
+    battery_provider: BatteryProviderSettings,
+    battery_policy_state: combo_box::State<BatteryPolicy>,
+    notifications: NotificationSettings,
 }
 
 pub struct BluetoothState {
@@ -90,13 +129,37 @@ pub enum Message {
     CancelAddDevice,
     StateChanged(String, DeviceState),
     TrayTextModeChanged(bool), // yes, I know I should add all settings to a struct, but I'm lazy
+    AdapterInput(String),
+    BatteryProviderChanged(BatteryProviderSettings),
+    NotificationsChanged(NotificationSettings),
     ConfigureDeviceId,
     DeviceIdConfigResult(Result<(), String>),
+    HeadGesturesChanged(HeadGestureSettings),
+    HeadGestureCommandInput(HeadGestureSettings),
+    OpenTrackChanged(OpenTrackSettings),
+    OpenTrackSmoothingChanged(OpenTrackSettings),
+    OpenTrackPortInput(String),
+    SaveHeadTrackingSettings,
+    TransparencyRead(String, Result<TransparencySettings, String>),
+    TransparencyChanged(String, TransparencySettings, bool), // mac, settings, write to device
+    HeadphoneAccommodationChanged(String, HeadphoneAccommodation, bool), // mac, accommodation, write to device
+    AutoAncStrengthChanged(String, u8, bool), // mac, strength, write to device
+    StemPressChanged(String, StemPressSettings),
+    KeepInSyncChanged(String, ControlCommandIdentifiers, bool),
+    WriteResult(String, Result<(), String>),
+    LoudSoundReductionRead(String, Result<bool, String>),
+    LoudSoundReductionToggled(String, bool),
+    HearingAidRead(String, Result<HearingAidSettings, String>),
+    HearingAidToggled(String, bool),
+    HearingAidChanged(String, HearingAidSettings, bool), // mac, settings, write to device
+    AudiogramPathInput(String),
+    ImportAudiogram(String),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Tab {
     Device(String),
+    HearingAid(String),
     Settings,
     AddDevice,
 }
@@ -145,6 +208,14 @@ impl App {
             .and_then(|v| v.get("tray_text_mode").cloned())
             .and_then(|ttm| serde_json::from_value(ttm).ok())
             .unwrap_or(false);
+        let adapter_input = settings
+            .clone()
+            .and_then(|v| v.get(ADAPTER_SETTING_KEY).cloned())
+            .and_then(|a| serde_json::from_value(a).ok())
+            .unwrap_or_default();
+
+        let opentrack = OpenTrackSettings::current();
+        let head_gestures = HeadGestureSettings::current();
 
         let bluetooth_state = BluetoothState::new();
 
@@ -197,11 +268,45 @@ impl App {
                 tray_text_mode,
                 device_id_status: check_device_id_status(),
                 device_id_configuring: false,
+                nod_action_state: combo_box::State::new(head_gestures.available_actions()),
+                shake_action_state: combo_box::State::new(head_gestures.available_actions()),
+                head_gestures,
+                opentrack_port_input: opentrack.port.to_string(),
+                head_tracking_unsaved: false,
+                audiogram_path: String::new(),
+                audiogram_error: None,
+                att_statuses: HashMap::new(),
+                connection_states: HashMap::new(),
+                adapter_input,
+                battery_provider: BatteryProviderSettings::current(),
+                battery_policy_state: combo_box::State::new(BatteryPolicy::ALL.to_vec()),
+                notifications: NotificationSettings::current(),
+                opentrack,
             },
             Task::batch(vec![open_task, wait_task]),
         )
     }
 
+    /// Puts new head gesture settings in use. The actions that need a command are only offered
+    /// once it is set.
+    fn set_head_gestures(&mut self, settings: HeadGestureSettings) {
+        settings.publish();
+        let actions = settings.available_actions();
+        if actions != self.head_gestures.available_actions() {
+            self.nod_action_state = combo_box::State::new(actions.clone());
+            self.shake_action_state = combo_box::State::new(actions);
+        }
+        self.head_gestures = settings;
+    }
+
+    fn save_head_tracking_settings(&mut self) {
+        if self.head_tracking_unsaved {
+            self.head_gestures.save();
+            self.opentrack.save();
+            self.head_tracking_unsaved = false;
+        }
+    }
+
     fn title(&self, _id: window::Id) -> String {
         "LibrePods".to_string()
     }
@@ -216,6 +321,7 @@ impl App {
                 if self.window == Some(id) {
                     self.window = None;
                 }
+                self.save_head_tracking_settings();
                 Task::none()
             }
             Message::Resized(event) => {
@@ -224,18 +330,12 @@ impl App {
             }
             Message::SelectTab(tab) => {
                 self.selected_tab = tab;
+                self.save_head_tracking_settings();
                 Task::none()
             }
             Message::ThemeSelected(theme) => {
                 self.selected_theme = theme;
-                let app_settings_path = get_app_settings_path();
-                let settings = serde_json::json!({"theme": self.selected_theme, "tray_text_mode": self.tray_text_mode});
-                debug!(
-                    "Writing settings to {}: {}",
-                    app_settings_path.to_str().unwrap(),
-                    settings
-                );
-                std::fs::write(app_settings_path, settings.to_string()).ok();
+                save_app_setting("theme", serde_json::json!(self.selected_theme));
                 Task::none()
             }
             Message::CopyToClipboard(data) => iced::clipboard::write(data),
@@ -299,8 +399,15 @@ impl App {
                         match type_ {
                             Some(DeviceType::AirPods) => {
                                 let device_managers = self.device_managers.blocking_read();
-                                let device_manager = device_managers.get(&mac).unwrap();
-                                let aacp_manager = device_manager.get_aacp().unwrap();
+                                // the connection can be gone again before this message got here
+                                let Some(device_manager) = device_managers.get(&mac) else {
+                                    debug!("{} disconnected before the UI saw it connect", mac);
+                                    return Task::batch(vec![wait_task]);
+                                };
+                                let Some(aacp_manager) = device_manager.get_aacp() else {
+                                    debug!("{} lost its connection before the UI saw it", mac);
+                                    return Task::batch(vec![wait_task]);
+                                };
                                 let aacp_manager_state = aacp_manager.state.clone();
                                 let state = aacp_manager_state.blocking_lock();
                                 debug!("AACP manager found for AirPods device {}", mac);
@@ -320,6 +427,7 @@ impl App {
                                         .map(|d| d.name.clone())
                                         .unwrap_or_else(|| "Unknown Device".to_string())
                                 };
+                                let stem_press = StemPressSettings::current(&mac);
                                 self.device_states.insert(mac.clone(), DeviceState::AirPods(AirPodsState {
                                     device_name,
                                     battery: state.battery_info.clone(),
@@ -358,7 +466,49 @@ impl App {
                                         status.identifier == ControlCommandIdentifiers::AllowOffOption &&
                                         matches!(status.value.as_slice(), [0x01])
                                     }),
+                                    auto_anc_strength: state.control_command_status_list.iter().find_map(|status| {
+                                        if status.identifier == ControlCommandIdentifiers::AutoAncStrength {
+                                            status.value.first().copied()
+                                        } else {
+                                            None
+                                        }
+                                    }),
+                                    listening_mode_cycle: state.control_command_status_list.iter().find_map(|status| {
+                                        if status.identifier == ControlCommandIdentifiers::ListeningModeConfigs {
+                                            status.value.first().map(|bits| ListeningModeCycle::from_bits(*bits))
+                                        } else {
+                                            None
+                                        }
+                                    }).unwrap_or_default(),
+                                    stem_press_action_states: stem_press.bindings.iter().map(|_| {
+                                        combo_box::State::new(StemPressAction::ALL.to_vec())
+                                    }).collect(),
+                                    stem_press,
+                                    desired_settings: DesiredSettings::load(&mac),
+                                    head_tracking_enabled: state.head_tracking,
+                                    transparency: None,
+                                    transparency_per_bud: false,
+                                    loud_sound_reduction: None,
+                                    headphone_accommodation: state.headphone_accommodation,
+                                    accommodation_preset_state: combo_box::State::new(
+                                        HeadphoneAccommodationPreset::ALL.to_vec(),
+                                    ),
+                                    hearing_aid_enabled: state.control_command_status_list.iter().any(|status| {
+                                        status.identifier == ControlCommandIdentifiers::HearingAid &&
+                                        status.value.get(1) == Some(&0x01)
+                                    }),
+                                    hearing_assist_enabled: state.control_command_status_list.iter().any(|status| {
+                                        status.identifier == ControlCommandIdentifiers::HearingAssistConfig &&
+                                        matches!(status.value.as_slice(), [0x01])
+                                    }),
+                                    hearing_aid: None,
                                 }));
+                                if let Some(att_manager) = device_manager.get_att() {
+                                    return Task::batch(vec![
+                                        wait_task,
+                                        read_airpods_att_values(&mac, att_manager),
+                                    ]);
+                                }
                             }
                             Some(DeviceType::Nothing) => {
                                 self.device_states.insert(
@@ -386,7 +536,20 @@ impl App {
                         let wait_task = Task::perform(wait_for_message(ui_rx), |msg| msg);
                         debug!("Device disconnected: {}", mac);
 
+                        self.bluetooth_state.connected_devices.retain(|d| d != &mac);
                         self.device_states.remove(&mac);
+                        self.att_statuses.remove(&mac);
+                        Task::batch(vec![wait_task])
+                    }
+                    BluetoothUIMessage::ConnectionStateChanged(mac, state) => {
+                        let ui_rx = Arc::clone(&self.ui_rx);
+                        let wait_task = Task::perform(wait_for_message(ui_rx), |msg| msg);
+                        debug!("Connection state of {}: {}", mac, state);
+                        if state == ConnectionState::Disconnected {
+                            self.connection_states.remove(&mac);
+                        } else {
+                            self.connection_states.insert(mac, state);
+                        }
                         Task::batch(vec![wait_task])
                     }
                     BluetoothUIMessage::AACPUIEvent(mac, event) => {
@@ -472,6 +635,38 @@ impl App {
                                         });
                                     }
                                 }
+                                ControlCommandIdentifiers::AutoAncStrength => {
+                                    if let Some(DeviceState::AirPods(state)) =
+                                        self.device_states.get_mut(&mac)
+                                    {
+                                        state.auto_anc_strength = status.value.first().copied();
+                                    }
+                                }
+                                ControlCommandIdentifiers::ListeningModeConfigs => {
+                                    if let Some(DeviceState::AirPods(state)) =
+                                        self.device_states.get_mut(&mac)
+                                        && let Some(bits) = status.value.first()
+                                    {
+                                        state.listening_mode_cycle =
+                                            ListeningModeCycle::from_bits(*bits);
+                                    }
+                                }
+                                ControlCommandIdentifiers::HearingAid => {
+                                    if let Some(DeviceState::AirPods(state)) =
+                                        self.device_states.get_mut(&mac)
+                                    {
+                                        state.hearing_aid_enabled =
+                                            status.value.get(1) == Some(&0x01);
+                                    }
+                                }
+                                ControlCommandIdentifiers::HearingAssistConfig => {
+                                    if let Some(DeviceState::AirPods(state)) =
+                                        self.device_states.get_mut(&mac)
+                                    {
+                                        state.hearing_assist_enabled =
+                                            matches!(status.value.as_slice(), [0x01]);
+                                    }
+                                }
                                 _ => {
                                     debug!("Unhandled Control Command Status: {:?}", status);
                                 }
@@ -484,10 +679,25 @@ impl App {
                                     debug!("Updated battery info for {}: {:?}", mac, state.battery);
                                 }
                             }
+                            AACPEvent::HeadphoneAccommodation(accommodation) => {
+                                if let Some(DeviceState::AirPods(state)) =
+                                    self.device_states.get_mut(&mac)
+                                {
+                                    state.headphone_accommodation = Some(accommodation);
+                                }
+                            }
                             _ => {}
                         }
                         Task::batch(vec![wait_task])
                     }
+                    BluetoothUIMessage::ATTStatus(mac, status) => {
+                        debug!("ATT channel status for {}: {}", mac, status);
+                        // the values are read by the device once the channel opened
+                        self.att_statuses.insert(mac, status);
+                        let ui_rx = Arc::clone(&self.ui_rx);
+                        let wait_task = Task::perform(wait_for_message(ui_rx), |msg| msg);
+                        Task::batch(vec![wait_task])
+                    }
                     BluetoothUIMessage::ATTNotification(mac, handle, value) => {
                         debug!(
                             "ATT Notification for {}: handle=0x{:04X}, value={:?}",
@@ -496,6 +706,33 @@ impl App {
 
                         // TODO: Handle Nothing's ANC Mode changes here
 
+                        if handle == ATTHandles::AirPodsTransparency as u16
+                            && let Some(DeviceState::AirPods(state)) =
+                                self.device_states.get_mut(&mac)
+                        {
+                            match TransparencySettings::decode(&value) {
+                                Some(settings) => state.transparency = Some(settings),
+                                None => error!("Invalid transparency settings for {}", mac),
+                            }
+                        }
+
+                        if handle == ATTHandles::AirPodsLoudSoundReduction as u16
+                            && let Some(DeviceState::AirPods(state)) =
+                                self.device_states.get_mut(&mac)
+                        {
+                            state.loud_sound_reduction = decode_loud_sound_reduction(&value);
+                        }
+
+                        if handle == ATTHandles::AirPodsHearingAid as u16
+                            && let Some(DeviceState::AirPods(state)) =
+                                self.device_states.get_mut(&mac)
+                        {
+                            match HearingAidSettings::decode(&value) {
+                                Some(settings) => state.hearing_aid = Some(settings),
+                                None => error!("Invalid hearing aid settings for {}", mac),
+                            }
+                        }
+
                         let ui_rx = Arc::clone(&self.ui_rx);
                         let wait_task = Task::perform(wait_for_message(ui_rx), |msg| msg);
                         Task::batch(vec![wait_task])
@@ -593,14 +830,7 @@ impl App {
             }
             Message::TrayTextModeChanged(is_enabled) => {
                 self.tray_text_mode = is_enabled;
-                let app_settings_path = get_app_settings_path();
-                let settings = serde_json::json!({"theme": self.selected_theme, "tray_text_mode": self.tray_text_mode});
-                debug!(
-                    "Writing settings to {}: {}",
-                    app_settings_path.to_str().unwrap(),
-                    settings
-                );
-                std::fs::write(app_settings_path, settings.to_string()).ok();
+                save_app_setting("tray_text_mode", serde_json::json!(self.tray_text_mode));
                 Task::none()
             }
             Message::ConfigureDeviceId => {
@@ -623,6 +853,340 @@ impl App {
                 }
                 Task::none()
             }
+            Message::HeadGesturesChanged(settings) => {
+                settings.save();
+                self.set_head_gestures(settings);
+                Task::none()
+            }
+            Message::HeadGestureCommandInput(settings) => {
+                self.set_head_gestures(settings);
+                self.head_tracking_unsaved = true;
+                Task::none()
+            }
+            Message::OpenTrackChanged(settings) => {
+                settings.publish();
+                settings.save();
+                self.opentrack = settings;
+                Task::none()
+            }
+            Message::OpenTrackSmoothingChanged(settings) => {
+                settings.publish();
+                self.opentrack = settings;
+                self.head_tracking_unsaved = true;
+                Task::none()
+            }
+            Message::SaveHeadTrackingSettings => {
+                self.save_head_tracking_settings();
+                Task::none()
+            }
+            Message::TransparencyRead(mac, result) => {
+                match result {
+                    Ok(settings) => {
+                        debug!("Transparency settings for {}: {:?}", mac, settings);
+                        if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac)
+                        {
+                            state.transparency = Some(settings);
+                        }
+                    }
+                    Err(e) => error!("Failed to read transparency settings for {}: {}", mac, e),
+                }
+                Task::none()
+            }
+            Message::TransparencyChanged(mac, settings, write) => {
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.transparency = Some(settings.clone());
+                }
+                if !write {
+                    return Task::none();
+                }
+                let managers = self.device_managers.blocking_read();
+                let Some((aacp_manager, att_manager)) = managers
+                    .get(&mac)
+                    .and_then(|m| m.get_aacp().zip(m.get_att()))
+                else {
+                    error!(
+                        "No AACP and ATT manager for {}, cannot write transparency settings",
+                        mac
+                    );
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        write_transparency_settings(&att_manager, &aacp_manager, &settings)
+                            .await
+                            .map_err(|e| format!("transparency settings: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::HeadphoneAccommodationChanged(mac, accommodation, write) => {
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.headphone_accommodation = Some(accommodation);
+                }
+                if !write {
+                    return Task::none();
+                }
+                let Some(aacp_manager) = self
+                    .device_managers
+                    .blocking_read()
+                    .get(&mac)
+                    .and_then(|m| m.get_aacp())
+                else {
+                    error!(
+                        "No AACP manager for {}, cannot send Headphone Accommodation",
+                        mac
+                    );
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        aacp_manager
+                            .send_headphone_accommodation(accommodation)
+                            .await
+                            .map_err(|e| format!("Headphone Accommodation: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::AutoAncStrengthChanged(mac, strength, write) => {
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.auto_anc_strength = Some(strength);
+                }
+                if !write {
+                    return Task::none();
+                }
+                let Some(aacp_manager) = self
+                    .device_managers
+                    .blocking_read()
+                    .get(&mac)
+                    .and_then(|m| m.get_aacp())
+                else {
+                    error!("No AACP manager for {}, cannot send Adaptive strength", mac);
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        aacp_manager
+                            .send_auto_anc_strength(strength)
+                            .await
+                            .map_err(|e| format!("Adaptive strength: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::StemPressChanged(mac, settings) => {
+                settings.publish(&mac);
+                settings.save(&mac);
+                let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) else {
+                    return Task::none();
+                };
+                let stem_config_changed = state.stem_press.stem_config() != settings.stem_config();
+                state.stem_press = settings;
+                if !stem_config_changed {
+                    return Task::none();
+                }
+                let stem_config = state.stem_press.stem_config();
+                let Some(aacp_manager) = self
+                    .device_managers
+                    .blocking_read()
+                    .get(&mac)
+                    .and_then(|m| m.get_aacp())
+                else {
+                    error!("No AACP manager for {}, cannot send stem config", mac);
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        aacp_manager
+                            .set_stem_config(stem_config)
+                            .await
+                            .map_err(|e| format!("stem config: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::KeepInSyncChanged(mac, identifier, keep_in_sync) => {
+                // reloaded, values sent since the state was built are recorded in the file
+                let mut settings = DesiredSettings::load(&mac);
+                settings.keep_in_sync.insert(identifier, keep_in_sync);
+                settings.save(&mac);
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.desired_settings = settings;
+                }
+                Task::none()
+            }
+            Message::LoudSoundReductionRead(mac, result) => {
+                match result {
+                    Ok(enabled) => {
+                        if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac)
+                        {
+                            state.loud_sound_reduction = Some(enabled);
+                        }
+                    }
+                    Err(e) => error!("Failed to read Loud Sound Reduction for {}: {}", mac, e),
+                }
+                Task::none()
+            }
+            Message::LoudSoundReductionToggled(mac, enabled) => {
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.loud_sound_reduction = Some(enabled);
+                }
+                let Some(att_manager) = self
+                    .device_managers
+                    .blocking_read()
+                    .get(&mac)
+                    .and_then(|m| m.get_att())
+                else {
+                    error!(
+                        "No ATT manager for {}, cannot write Loud Sound Reduction",
+                        mac
+                    );
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        write_loud_sound_reduction(&att_manager, enabled)
+                            .await
+                            .map_err(|e| format!("Loud Sound Reduction: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::HearingAidRead(mac, result) => {
+                match result {
+                    Ok(settings) => {
+                        debug!("Hearing aid settings for {}: {:?}", mac, settings);
+                        if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac)
+                        {
+                            state.hearing_aid = Some(settings);
+                        }
+                    }
+                    Err(e) => error!("Failed to read hearing aid settings for {}: {}", mac, e),
+                }
+                Task::none()
+            }
+            Message::HearingAidToggled(mac, enabled) => {
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.hearing_aid_enabled = enabled;
+                    state.hearing_assist_enabled = enabled;
+                    if enabled && let Some(transparency) = state.transparency.as_mut() {
+                        transparency.enabled = false;
+                    }
+                }
+                let managers = self.device_managers.blocking_read();
+                let Some(aacp_manager) = managers.get(&mac).and_then(|m| m.get_aacp()) else {
+                    error!("No AACP manager for {}, cannot toggle Hearing Aid", mac);
+                    return Task::none();
+                };
+                let att_manager = managers.get(&mac).and_then(|m| m.get_att());
+                Task::perform(
+                    async move {
+                        set_hearing_aid_enabled(&aacp_manager, att_manager.as_deref(), enabled)
+                            .await
+                            .map_err(|e| format!("Hearing Aid state: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::HearingAidChanged(mac, settings, write) => {
+                if let Some(DeviceState::AirPods(state)) = self.device_states.get_mut(&mac) {
+                    state.hearing_aid = Some(settings.clone());
+                }
+                if !write {
+                    return Task::none();
+                }
+                let Some(att_manager) = self
+                    .device_managers
+                    .blocking_read()
+                    .get(&mac)
+                    .and_then(|m| m.get_att())
+                else {
+                    error!(
+                        "No ATT manager for {}, cannot write hearing aid settings",
+                        mac
+                    );
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        write_hearing_aid_settings(&att_manager, &settings)
+                            .await
+                            .map_err(|e| format!("hearing aid settings: {}", e))
+                    },
+                    move |result| Message::WriteResult(mac.clone(), result),
+                )
+            }
+            Message::AudiogramPathInput(path) => {
+                self.audiogram_path = path;
+                self.audiogram_error = None;
+                Task::none()
+            }
+            Message::ImportAudiogram(mac) => {
+                let path = self.audiogram_path.trim();
+                let audiogram = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path, e))
+                    .and_then(|csv| Audiogram::parse_csv(&csv));
+                let audiogram = match audiogram {
+                    Ok(audiogram) => audiogram,
+                    Err(e) => {
+                        error!("Failed to import audiogram: {}", e);
+                        self.audiogram_error = Some(e);
+                        return Task::none();
+                    }
+                };
+                self.audiogram_error = None;
+                let Some(DeviceState::AirPods(state)) = self.device_states.get(&mac) else {
+                    return Task::none();
+                };
+                let Some(settings) = state.hearing_aid.clone() else {
+                    return Task::none();
+                };
+                debug!("Imported audiogram for {}: {:?}", mac, audiogram);
+                Task::done(Message::HearingAidChanged(
+                    mac,
+                    HearingAidSettings {
+                        left_loss: audiogram.left_loss(),
+                        right_loss: audiogram.right_loss(),
+                        ..settings
+                    },
+                    true,
+                ))
+            }
+            Message::WriteResult(mac, result) => {
+                if let Err(e) = result {
+                    error!("Failed to write {} for {}", e, mac);
+                }
+                Task::none()
+            }
+            Message::BatteryProviderChanged(settings) => {
+                settings.publish();
+                settings.save();
+                self.battery_provider = settings;
+                Task::none()
+            }
+            Message::NotificationsChanged(settings) => {
+                settings.publish();
+                settings.save();
+                self.notifications = settings;
+                Task::none()
+            }
+            Message::AdapterInput(input) => {
+                save_app_setting(ADAPTER_SETTING_KEY, serde_json::json!(input.trim()));
+                self.adapter_input = input;
+                Task::none()
+            }
+            Message::OpenTrackPortInput(input) => {
+                // only save ports that parse, but keep whatever is being typed in the field
+                if let Ok(port) = input.trim().parse::<u16>()
+                    && port != 0
+                {
+                    self.opentrack.port = port;
+                    self.opentrack.publish();
+                    self.head_tracking_unsaved = true;
+                }
+                self.opentrack_port_input = input;
+                Task::none()
+            }
         }
     }
 
@@ -641,7 +1205,11 @@ impl App {
                 Pane::Sidebar => {
                     let create_tab_button = |tab: Tab, label: &str, mac_addr: &str, connected: bool| -> Element<'_, Message> {
                         let label = label.to_string() + if connected { " 􀉣" } else { "" };
-                        let is_selected = self.selected_tab == tab;
+                        // the hearing aid page belongs to the device
+                        let is_selected = match (&self.selected_tab, &tab) {
+                            (Tab::HearingAid(selected), Tab::Device(mac)) => selected == mac,
+                            (selected, tab) => selected == tab,
+                        };
                         let col = column![
                             text(label).size(16),
                             text({
@@ -686,7 +1254,11 @@ impl App {
                                         _ => "Connected".to_string(),
                                     }
                                 } else {
-                                    mac_addr.to_string()
+                                    match self.connection_states.get(mac_addr) {
+                                        Some(ConnectionState::Connecting) => "Connecting…".to_string(),
+                                        Some(ConnectionState::Handshaking) => "Handshaking…".to_string(),
+                                        _ => mac_addr.to_string(),
+                                    }
                                 }
                             }).size(12)
                         ];
@@ -841,7 +1413,9 @@ impl App {
                                                                     id,
                                                                     &devices_list,
                                                                     state,
-                                                                    aacp_manager.clone()
+                                                                    aacp_manager.clone(),
+                                                                    managers.get_att(),
+                                                                    self.att_statuses.get(id)
                                                                 ))
                                                     })
                                                 }
@@ -892,7 +1466,178 @@ impl App {
                                 }
                             }
                         }
+                        Tab::HearingAid(id) => {
+                            match (self.device_states.get(id), device_managers.get(id)) {
+                                (Some(DeviceState::AirPods(state)), Some(managers)) => hearing_aid_view(
+                                    id,
+                                    state,
+                                    managers.get_att().is_some(),
+                                    &self.audiogram_path,
+                                    self.audiogram_error.as_deref(),
+                                ),
+                                _ => container(
+                                    text("This device is not connected").size(16)
+                                )
+                                    .center_x(Length::Fill)
+                                    .center_y(Length::Fill),
+                            }
+                        }
                         Tab::Settings => {
+                            let adapter_section = container(
+                                column![
+                                    row![
+                                        text("Bluetooth adapter").size(16),
+                                        Space::with_width(Length::Fill),
+                                        text_input("Default", &self.adapter_input)
+                                            .on_input(Message::AdapterInput)
+                                            .padding(Padding{
+                                                top: 5.0,
+                                                bottom: 5.0,
+                                                left: 10.0,
+                                                right: 10.0,
+                                            })
+                                            .style(
+                                                |theme: &Theme, _status| {
+                                                    text_input::Style {
+                                                        background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+                                                        border: Border {
+                                                            width: 1.0,
+                                                            color: theme.palette().text.scale_alpha(0.3),
+                                                            radius: Radius::from(4.0)
+                                                        },
+                                                        icon: Default::default(),
+                                                        placeholder: theme.palette().text.scale_alpha(0.5),
+                                                        value: theme.palette().text,
+                                                        selection: Default::default(),
+                                                    }
+                                                }
+                                            )
+                                            .width(Length::from(200))
+                                    ]
+                                    .align_y(Center),
+                                    text("The adapter to use, like hci1. Leave empty for the default adapter. Applies after a restart, --adapter overrides it.").size(12).style(
+                                        |theme: &Theme| {
+                                            let mut style = text::Style::default();
+                                            style.color = Some(theme.palette().text.scale_alpha(0.7));
+                                            style
+                                        }
+                                    ).width(Length::Fill)
+                                ]
+                                .spacing(8)
+                            )
+                                .padding(Padding{
+                                    top: 12.0,
+                                    bottom: 12.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                })
+                                .style(
+                                    |theme: &Theme| {
+                                        let mut style = container::Style::default();
+                                        style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+                                        let mut border = Border::default();
+                                        border.color = theme.palette().primary.scale_alpha(0.5);
+                                        style.border = border.rounded(16);
+                                        style
+                                    }
+                                );
+
+                            let battery_provider = &self.battery_provider;
+                            let battery_provider_section = container(
+                                column![
+                                    row![
+                                        column![
+                                            text("Report battery to the system").size(16),
+                                            text("Show the AirPods battery in the desktop's Bluetooth and power settings. BlueZ only takes one level, the case and each bud are left out.").size(12).style(
+                                                |theme: &Theme| {
+                                                    let mut style = text::Style::default();
+                                                    style.color = Some(theme.palette().text.scale_alpha(0.7));
+                                                    style
+                                                }
+                                            ).width(Length::Fill)
+                                        ].width(Length::Fill),
+                                        toggler(battery_provider.enabled)
+                                            .on_toggle({
+                                                let battery_provider = battery_provider.clone();
+                                                move |is_enabled| {
+                                                    Message::BatteryProviderChanged(BatteryProviderSettings {
+                                                        enabled: is_enabled,
+                                                        ..battery_provider.clone()
+                                                    })
+                                                }
+                                            })
+                                            .spacing(0)
+                                            .size(20)
+                                    ]
+                                    .align_y(Center)
+                                    .spacing(12),
+                                    row![
+                                        text("Level reported").size(16),
+                                        Space::with_width(Length::Fill),
+                                        combo_box(
+                                            &self.battery_policy_state,
+                                            "Select level",
+                                            Some(&battery_provider.policy),
+                                            {
+                                                let battery_provider = battery_provider.clone();
+                                                move |policy| Message::BatteryProviderChanged(BatteryProviderSettings {
+                                                    policy,
+                                                    ..battery_provider.clone()
+                                                })
+                                            }
+                                        )
+                                            .input_style(|theme: &Theme, _status| text_input::Style {
+                                                background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+                                                border: Border {
+                                                    width: 1.0,
+                                                    color: theme.palette().text.scale_alpha(0.3),
+                                                    radius: Radius::from(4.0),
+                                                },
+                                                icon: Default::default(),
+                                                placeholder: theme.palette().text,
+                                                value: theme.palette().text,
+                                                selection: Default::default(),
+                                            })
+                                            .menu_style(|theme: &Theme| menu::Style {
+                                                background: Background::Color(theme.palette().background),
+                                                border: Border {
+                                                    width: 1.0,
+                                                    color: theme.palette().text,
+                                                    radius: Radius::from(4.0),
+                                                },
+                                                text_color: theme.palette().text,
+                                                selected_text_color: theme.palette().text,
+                                                selected_background: Background::Color(theme.palette().primary.scale_alpha(0.3)),
+                                            })
+                                            .padding(Padding {
+                                                top: 5.0,
+                                                bottom: 5.0,
+                                                left: 10.0,
+                                                right: 10.0,
+                                            })
+                                            .width(Length::from(200))
+                                    ]
+                                    .align_y(Center)
+                                ]
+                                .spacing(8)
+                            )
+                                .padding(Padding{
+                                    top: 12.0,
+                                    bottom: 12.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                })
+                                .style(
+                                    |theme: &Theme| {
+                                        let mut style = container::Style::default();
+                                        style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+                                        let mut border = Border::default();
+                                        border.color = theme.palette().primary.scale_alpha(0.5);
+                                        style.border = border.rounded(16);
+                                        style
+                                    }
+                                );
+
                             let tray_text_mode_toggle = container(
                                 row![
                                     column![
@@ -1148,14 +1893,347 @@ impl App {
                             ]
                             .spacing(12);
 
+                            let notifications = &self.notifications;
+                            let notifications_section = column![
+                                container(
+                                    text("Notifications").size(20).style(
+                                        |theme: &Theme| {
+                                            let mut style = text::Style::default();
+                                            style.color = Some(theme.palette().primary);
+                                            style
+                                        }
+                                    )
+                                )
+                                .padding(Padding {
+                                    top: 0.0,
+                                    bottom: 0.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                }),
+                                container(
+                                    column![
+                                        notification_toggle_row(
+                                            "Connected and disconnected",
+                                            "With the battery levels when the AirPods connect.".to_string(),
+                                            notifications.connection,
+                                            {
+                                                let notifications = notifications.clone();
+                                                move |enabled| Message::NotificationsChanged(NotificationSettings {
+                                                    connection: enabled,
+                                                    ..notifications.clone()
+                                                })
+                                            }
+                                        ),
+                                        notification_toggle_row(
+                                            "Low battery",
+                                            format!("When a bud or the case drops to {}%.", LOW_BATTERY),
+                                            notifications.low_battery,
+                                            {
+                                                let notifications = notifications.clone();
+                                                move |enabled| Message::NotificationsChanged(NotificationSettings {
+                                                    low_battery: enabled,
+                                                    ..notifications.clone()
+                                                })
+                                            }
+                                        ),
+                                        notification_toggle_row(
+                                            "Case charged",
+                                            "When the case is fully charged.".to_string(),
+                                            notifications.case_charged,
+                                            {
+                                                let notifications = notifications.clone();
+                                                move |enabled| Message::NotificationsChanged(NotificationSettings {
+                                                    case_charged: enabled,
+                                                    ..notifications.clone()
+                                                })
+                                            }
+                                        ),
+                                        notification_toggle_row(
+                                            "Moved to another device",
+                                            "When another device takes the AirPods over.".to_string(),
+                                            notifications.ownership_lost,
+                                            {
+                                                let notifications = notifications.clone();
+                                                move |enabled| Message::NotificationsChanged(NotificationSettings {
+                                                    ownership_lost: enabled,
+                                                    ..notifications.clone()
+                                                })
+                                            }
+                                        ),
+                                        notification_toggle_row(
+                                            "Bud disconnected",
+                                            "When one bud drops out while the other is in use.".to_string(),
+                                            notifications.bud_disconnected,
+                                            {
+                                                let notifications = notifications.clone();
+                                                move |enabled| Message::NotificationsChanged(NotificationSettings {
+                                                    bud_disconnected: enabled,
+                                                    ..notifications.clone()
+                                                })
+                                            }
+                                        )
+                                    ]
+                                    .spacing(12)
+                                )
+                                .padding(Padding {
+                                    top: 12.0,
+                                    bottom: 12.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                })
+                                .style(|theme: &Theme| {
+                                    let mut style = container::Style::default();
+                                    style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+                                    let mut border = Border::default();
+                                    border.color = theme.palette().primary.scale_alpha(0.5);
+                                    style.border = border.rounded(16);
+                                    style
+                                })
+                            ]
+                            .spacing(12);
+
+                            // Head Gestures section
+                            let gestures = &self.head_gestures;
+                            let head_gestures_section = column![
+                                container(
+                                    text("Head Gestures").size(20).style(
+                                        |theme: &Theme| {
+                                            let mut style = text::Style::default();
+                                            style.color = Some(theme.palette().primary);
+                                            style
+                                        }
+                                    )
+                                )
+                                .padding(Padding {
+                                    top: 0.0,
+                                    bottom: 0.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                }),
+                                container(
+                                    column![
+                                        row![
+                                            column![
+                                                text("Nod and shake").size(16),
+                                                text("Nod or shake your head to trigger an action. Works while head tracking is turned on for the AirPods.").size(12).style(
+                                                    |theme: &Theme| {
+                                                        let mut style = text::Style::default();
+                                                        style.color = Some(theme.palette().text.scale_alpha(0.7));
+                                                        style
+                                                    }
+                                                ).width(Length::Fill)
+                                            ].width(Length::Fill),
+                                            toggler(gestures.enabled)
+                                                .on_toggle({
+                                                    let gestures = gestures.clone();
+                                                    move |is_enabled| {
+                                                        Message::HeadGesturesChanged(HeadGestureSettings {
+                                                            enabled: is_enabled,
+                                                            ..gestures.clone()
+                                                        })
+                                                    }
+                                                })
+                                                .spacing(0)
+                                                .size(20)
+                                        ]
+                                        .align_y(Center)
+                                        .spacing(12),
+                                        head_gesture_action_row("Nod", &self.nod_action_state, gestures.nod, {
+                                            let gestures = gestures.clone();
+                                            move |action| Message::HeadGesturesChanged(HeadGestureSettings {
+                                                nod: action,
+                                                ..gestures.clone()
+                                            })
+                                        }),
+                                        head_gesture_action_row("Shake", &self.shake_action_state, gestures.shake, {
+                                            let gestures = gestures.clone();
+                                            move |action| Message::HeadGesturesChanged(HeadGestureSettings {
+                                                shake: action,
+                                                ..gestures.clone()
+                                            })
+                                        }),
+                                        text("There is no common desktop API for calls and notifications, so those actions run a command of your choice. They can be picked for a gesture once their command is set.")
+                                            .size(12)
+                                            .style(|theme: &Theme| {
+                                                let mut style = text::Style::default();
+                                                style.color = Some(theme.palette().text.scale_alpha(0.7));
+                                                style
+                                            }),
+                                        head_gesture_command_row("Accept call", &gestures.accept_call_command, {
+                                            let gestures = gestures.clone();
+                                            move |command| Message::HeadGestureCommandInput(HeadGestureSettings {
+                                                accept_call_command: command,
+                                                ..gestures.clone()
+                                            })
+                                        }),
+                                        head_gesture_command_row("Decline call", &gestures.decline_call_command, {
+                                            let gestures = gestures.clone();
+                                            move |command| Message::HeadGestureCommandInput(HeadGestureSettings {
+                                                decline_call_command: command,
+                                                ..gestures.clone()
+                                            })
+                                        }),
+                                        head_gesture_command_row("Dismiss notification", &gestures.dismiss_notification_command, {
+                                            let gestures = gestures.clone();
+                                            move |command| Message::HeadGestureCommandInput(HeadGestureSettings {
+                                                dismiss_notification_command: command,
+                                                ..gestures.clone()
+                                            })
+                                        })
+                                    ]
+                                    .spacing(8)
+                                )
+                                .padding(Padding {
+                                    top: 12.0,
+                                    bottom: 12.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                })
+                                .style(|theme: &Theme| {
+                                    let mut style = container::Style::default();
+                                    style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+                                    let mut border = Border::default();
+                                    border.color = theme.palette().primary.scale_alpha(0.5);
+                                    style.border = border.rounded(16);
+                                    style
+                                })
+                            ]
+                            .spacing(12);
+
+                            // OpenTrack section
+                            let opentrack = &self.opentrack;
+                            let opentrack_section = column![
+                                container(
+                                    text("OpenTrack").size(20).style(
+                                        |theme: &Theme| {
+                                            let mut style = text::Style::default();
+                                            style.color = Some(theme.palette().primary);
+                                            style
+                                        }
+                                    )
+                                )
+                                .padding(Padding {
+                                    top: 0.0,
+                                    bottom: 0.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                }),
+                                container(
+                                    column![
+                                        row![
+                                            column![
+                                                text("Send head pose to OpenTrack").size(16),
+                                                text("Streams yaw and pitch to OpenTrack's \"UDP over network\" input on this computer while head tracking is on. Use the Recenter button on the device page to reset the forward direction.").size(12).style(
+                                                    |theme: &Theme| {
+                                                        let mut style = text::Style::default();
+                                                        style.color = Some(theme.palette().text.scale_alpha(0.7));
+                                                        style
+                                                    }
+                                                ).width(Length::Fill)
+                                            ].width(Length::Fill),
+                                            toggler(opentrack.enabled)
+                                                .on_toggle({
+                                                    let opentrack = opentrack.clone();
+                                                    move |is_enabled| {
+                                                        Message::OpenTrackChanged(OpenTrackSettings {
+                                                            enabled: is_enabled,
+                                                            ..opentrack.clone()
+                                                        })
+                                                    }
+                                                })
+                                                .spacing(0)
+                                                .size(20)
+                                        ]
+                                        .align_y(Center)
+                                        .spacing(12),
+                                        row![
+                                            text("UDP port").size(16),
+                                            Space::with_width(Length::Fill),
+                                            text_input(&DEFAULT_OPENTRACK_PORT.to_string(), &self.opentrack_port_input)
+                                                .on_input(Message::OpenTrackPortInput)
+                                                .on_submit(Message::SaveHeadTrackingSettings)
+                                                .padding(Padding{
+                                                    top: 5.0,
+                                                    bottom: 5.0,
+                                                    left: 10.0,
+                                                    right: 10.0,
+                                                })
+                                                .style(
+                                                    |theme: &Theme, _status| {
+                                                        text_input::Style {
+                                                            background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+                                                            border: Border {
+                                                                width: 1.0,
+                                                                color: theme.palette().text.scale_alpha(0.3),
+                                                                radius: Radius::from(4.0)
+                                                            },
+                                                            icon: Default::default(),
+                                                            placeholder: theme.palette().text.scale_alpha(0.5),
+                                                            value: theme.palette().text,
+                                                            selection: Default::default(),
+                                                        }
+                                                    }
+                                                )
+                                                .width(Length::from(200))
+                                        ]
+                                        .align_y(Center),
+                                        row![
+                                            text("Smoothing").size(16),
+                                            Space::with_width(Length::Fill),
+                                            slider(0.0..=MAX_SMOOTHING, opentrack.smoothing, {
+                                                let opentrack = opentrack.clone();
+                                                move |smoothing| {
+                                                    Message::OpenTrackSmoothingChanged(OpenTrackSettings {
+                                                        smoothing,
+                                                        ..opentrack.clone()
+                                                    })
+                                                }
+                                            })
+                                            .step(0.05)
+                                            .on_release(Message::SaveHeadTrackingSettings)
+                                            .width(Length::from(200))
+                                        ]
+                                        .align_y(Center)
+                                    ]
+                                    .spacing(8)
+                                )
+                                .padding(Padding {
+                                    top: 12.0,
+                                    bottom: 12.0,
+                                    left: 18.0,
+                                    right: 18.0,
+                                })
+                                .style(|theme: &Theme| {
+                                    let mut style = container::Style::default();
+                                    style.background = Some(Background::Color(theme.palette().primary.scale_alpha(0.1)));
+                                    let mut border = Border::default();
+                                    border.color = theme.palette().primary.scale_alpha(0.5);
+                                    style.border = border.rounded(16);
+                                    style
+                                })
+                            ]
+                            .spacing(12);
+
                             container(
-                                column![
-                                    appearance_settings_col,
-                                    Space::with_height(Length::from(20)),
-                                    tray_text_mode_toggle,
-                                    Space::with_height(Length::from(20)),
-                                    seamless_switching_section
-                                ]
+                                scrollable(
+                                    column![
+                                        appearance_settings_col,
+                                        Space::with_height(Length::from(20)),
+                                        tray_text_mode_toggle,
+                                        Space::with_height(Length::from(20)),
+                                        adapter_section,
+                                        Space::with_height(Length::from(20)),
+                                        battery_provider_section,
+                                        Space::with_height(Length::from(20)),
+                                        notifications_section,
+                                        Space::with_height(Length::from(20)),
+                                        seamless_switching_section,
+                                        Space::with_height(Length::from(20)),
+                                        head_gestures_section,
+                                        Space::with_height(Length::from(20)),
+                                        opentrack_section
+                                    ]
+                                )
                             )
                                 .padding(20)
                                 .width(Length::Fill)
@@ -1346,16 +2424,23 @@ async fn wait_for_message(ui_rx: Arc<Mutex<UnboundedReceiver<BluetoothUIMessage>
         }
     }
 }
+/// Paired devices by name, empty when the adapter isn't available.
 async fn load_paired_devices() -> HashMap<String, Address> {
+    paired_devices().await.unwrap_or_else(|e| {
+        error!("Failed to load paired devices: {}", e);
+        HashMap::new()
+    })
+}
+
+async fn paired_devices() -> bluer::Result<HashMap<String, Address>> {
     let mut devices = HashMap::new();
 
-    let session = Session::new().await.ok().unwrap();
-    let adapter = session.default_adapter().await.ok().unwrap();
-    let addresses = adapter.device_addresses().await.ok().unwrap();
-    for addr in addresses {
-        let device = adapter.device(addr).ok().unwrap();
-        let paired = device.is_paired().await.ok().unwrap();
-        if paired {
+    let session = Session::new().await?;
+    let adapter = open_adapter(&session).await?;
+    for addr in adapter.device_addresses().await? {
+        let device = adapter.device(addr)?;
+        // a device can go away while it is looked at, skip it then
+        if device.is_paired().await.unwrap_or(false) {
             let name = device
                 .name()
                 .await
@@ -1366,5 +2451,152 @@ async fn load_paired_devices() -> HashMap<String, Address> {
         }
     }
 
-    devices
+    Ok(devices)
+}
+
+fn head_gesture_action_row<'a>(
+    label: &'a str,
+    state: &'a combo_box::State<HeadGestureAction>,
+    selected: HeadGestureAction,
+    on_selected: impl Fn(HeadGestureAction) -> Message + 'static,
+) -> Element<'a, Message> {
+    row![
+        text(label).size(16),
+        Space::with_width(Length::Fill),
+        combo_box(state, "Select action", Some(&selected), on_selected)
+            .input_style(|theme: &Theme, _status| text_input::Style {
+                background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+                border: Border {
+                    width: 1.0,
+                    color: theme.palette().text.scale_alpha(0.3),
+                    radius: Radius::from(4.0),
+                },
+                icon: Default::default(),
+                placeholder: theme.palette().text,
+                value: theme.palette().text,
+                selection: Default::default(),
+            })
+            .menu_style(|theme: &Theme| menu::Style {
+                background: Background::Color(theme.palette().background),
+                border: Border {
+                    width: 1.0,
+                    color: theme.palette().text,
+                    radius: Radius::from(4.0),
+                },
+                text_color: theme.palette().text,
+                selected_text_color: theme.palette().text,
+                selected_background: Background::Color(theme.palette().primary.scale_alpha(0.3)),
+            })
+            .padding(Padding {
+                top: 5.0,
+                bottom: 5.0,
+                left: 10.0,
+                right: 10.0,
+            })
+            .width(Length::from(200))
+    ]
+    .align_y(Center)
+    .into()
+}
+
+fn notification_toggle_row<'a>(
+    label: &'a str,
+    description: String,
+    enabled: bool,
+    on_toggle: impl Fn(bool) -> Message + 'a,
+) -> Element<'a, Message> {
+    row![
+        column![
+            text(label).size(16),
+            text(description)
+                .size(12)
+                .style(|theme: &Theme| {
+                    let mut style = text::Style::default();
+                    style.color = Some(theme.palette().text.scale_alpha(0.7));
+                    style
+                })
+                .width(Length::Fill)
+        ]
+        .width(Length::Fill),
+        toggler(enabled).on_toggle(on_toggle).spacing(0).size(20)
+    ]
+    .align_y(Center)
+    .spacing(12)
+    .into()
+}
+
+fn head_gesture_command_row<'a>(
+    label: &'a str,
+    value: &str,
+    on_input: impl Fn(String) -> Message + 'a,
+) -> Element<'a, Message> {
+    row![
+        text(label).size(14),
+        Space::with_width(Length::Fill),
+        text_input("Shell command", value)
+            .on_input(on_input)
+            .on_submit(Message::SaveHeadTrackingSettings)
+            .size(14)
+            .padding(Padding {
+                top: 5.0,
+                bottom: 5.0,
+                left: 10.0,
+                right: 10.0,
+            })
+            .style(|theme: &Theme, _status| text_input::Style {
+                background: Background::Color(theme.palette().primary.scale_alpha(0.2)),
+                border: Border {
+                    width: 1.0,
+                    color: theme.palette().text.scale_alpha(0.3),
+                    radius: Radius::from(4.0),
+                },
+                icon: Default::default(),
+                placeholder: theme.palette().text.scale_alpha(0.5),
+                value: theme.palette().text,
+                selection: Default::default(),
+            })
+            .width(Length::from(300))
+    ]
+    .align_y(Center)
+    .into()
+}
+
+/// Reads the ATT backed AirPods settings, the results arrive as messages.
+fn read_airpods_att_values(mac: &str, att_manager: Arc<ATTManager>) -> Task<Message> {
+    let transparency = {
+        let mac = mac.to_string();
+        let att_manager = att_manager.clone();
+        Task::perform(
+            async move {
+                read_transparency_settings(&att_manager)
+                    .await
+                    .map_err(|e| e.to_string())
+            },
+            move |result| Message::TransparencyRead(mac.clone(), result),
+        )
+    };
+    let loud_sound_reduction = {
+        let mac = mac.to_string();
+        let att_manager = att_manager.clone();
+        Task::perform(
+            async move {
+                read_loud_sound_reduction(&att_manager)
+                    .await
+                    .map_err(|e| e.to_string())
+            },
+            move |result| Message::LoudSoundReductionRead(mac.clone(), result),
+        )
+    };
+    let hearing_aid = {
+        let mac = mac.to_string();
+        Task::perform(
+            async move {
+                read_hearing_aid_settings(&att_manager)
+                    .await
+                    .map_err(|e| e.to_string())
+            },
+            move |result| Message::HearingAidRead(mac.clone(), result),
+        )
+    };
+    Task::batch(vec![transparency, loud_sound_reduction, hearing_aid])
 }
@@ -2,13 +2,20 @@ use aes::Aes128;
 use aes::cipher::generic_array::GenericArray;
 use aes::cipher::{BlockEncrypt, KeyInit};
 use iced::Theme;
+use log::{debug, error, info};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::OnceLock;
+use tokio::sync::watch;
 
 const BLUEZ_CONFIG_PATH: &str = "/etc/bluetooth/main.conf";
 const APPLE_DEVICE_ID: &str = "bluetooth:004C:0000:0000";
 
+// set while simulating, so simulated devices stay out of the user's data
+static DATA_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
 /// Status of the BlueZ DeviceID configuration for seamless switching
 #[derive(Debug, Clone, PartialEq)]
 pub enum DeviceIdStatus {
@@ -126,7 +133,61 @@ pub fn configure_device_id() -> Result<(), String> {
     }
 }
 
+/// Whether LibrePods could open the ATT channel (L2CAP PSM 0x1F) to the AirPods
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttChannelStatus {
+    /// Connected, ATT based features are available
+    Available,
+    /// The AirPods only accept ATT from Apple devices, and the DeviceID in the BlueZ config
+    /// doesn't say we are one
+    BlockedByDeviceId(DeviceIdStatus),
+    /// The connection failed even though the BlueZ config looks right
+    Unreachable(String),
+}
+
+impl std::fmt::Display for AttChannelStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttChannelStatus::Available => write!(f, "Available"),
+            AttChannelStatus::BlockedByDeviceId(status) => write!(
+                f,
+                "Blocked by DeviceID in {} ({})",
+                BLUEZ_CONFIG_PATH, status
+            ),
+            AttChannelStatus::Unreachable(e) => write!(f, "Unreachable: {}", e),
+        }
+    }
+}
+
+/// Find out which BlueZ setting is keeping the ATT connection from succeeding
+pub fn check_att_channel_status(error: &bluer::Error) -> AttChannelStatus {
+    match check_device_id_status() {
+        DeviceIdStatus::Configured => AttChannelStatus::Unreachable(error.to_string()),
+        status => AttChannelStatus::BlockedByDeviceId(status),
+    }
+}
+
+/// Keeps the device data (devices.json and the desired settings) in `dir` instead of the user's
+/// data directory for the rest of the process. Only the first call has an effect, the
+/// directory in use is returned.
+pub fn override_data_dir(dir: PathBuf) -> PathBuf {
+    DATA_DIR_OVERRIDE.get_or_init(|| dir).clone()
+}
+
+/// Points the device data at a directory of its own for the tests of this process.
+#[cfg(test)]
+pub fn use_test_data_dir() -> PathBuf {
+    let dir = override_data_dir(
+        std::env::temp_dir().join(format!("librepods-test-{}", std::process::id())),
+    );
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 pub fn get_devices_path() -> PathBuf {
+    if let Some(dir) = DATA_DIR_OVERRIDE.get() {
+        return dir.join("devices.json");
+    }
     let data_dir = std::env::var("XDG_DATA_HOME")
         .unwrap_or_else(|_| format!("{}/.local/share", std::env::var("HOME").unwrap_or_default()));
     PathBuf::from(data_dir)
@@ -134,6 +195,10 @@ pub fn get_devices_path() -> PathBuf {
         .join("devices.json")
 }
 
+pub fn get_desired_settings_path() -> PathBuf {
+    get_devices_path().with_file_name("desired_settings.json")
+}
+
 pub fn get_preferences_path() -> PathBuf {
     let config_dir = std::env::var("XDG_CONFIG_HOME")
         .unwrap_or_else(|_| format!("{}/.local/share", std::env::var("HOME").unwrap_or_default()));
@@ -150,6 +215,121 @@ pub fn get_app_settings_path() -> PathBuf {
         .join("app_settings.json")
 }
 
+/// Read a single top-level key from the app settings file
+pub fn load_app_setting<T: DeserializeOwned>(key: &str) -> Option<T> {
+    std::fs::read_to_string(get_app_settings_path())
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v.get(key).cloned())
+        .and_then(|v| serde_json::from_value(v).ok())
+}
+
+/// Write a single top-level key to the app settings file, keeping every other key intact
+pub fn save_app_setting(key: &str, value: serde_json::Value) {
+    let app_settings_path = get_app_settings_path();
+    let mut settings = std::fs::read_to_string(&app_settings_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .filter(|v| v.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    settings[key] = value;
+    debug!(
+        "Writing settings to {}: {}",
+        app_settings_path.to_str().unwrap(),
+        settings
+    );
+    if let Some(parent) = app_settings_path.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    if let Err(e) = std::fs::write(&app_settings_path, settings.to_string()) {
+        error!("Failed to write app settings: {}", e);
+    }
+}
+
+/// An app setting read once and then shared through a watch channel: the UI publishes its
+/// changes and the tasks using the setting always see the latest value, without going back to
+/// the settings file.
+pub struct SharedSetting<T> {
+    sender: OnceLock<watch::Sender<T>>,
+    load: fn() -> T,
+}
+
+impl<T: Clone> SharedSetting<T> {
+    pub const fn new(load: fn() -> T) -> Self {
+        Self {
+            sender: OnceLock::new(),
+            load,
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.sender().borrow().clone()
+    }
+
+    pub fn watch(&self) -> watch::Receiver<T> {
+        self.sender().subscribe()
+    }
+
+    /// Hands a new value to everyone watching, saving it is up to the caller.
+    pub fn publish(&self, value: T) {
+        self.sender().send_replace(value);
+    }
+
+    fn sender(&self) -> &watch::Sender<T> {
+        self.sender
+            .get_or_init(|| watch::Sender::new((self.load)()))
+    }
+}
+
+/// Read a single per-device key from the preferences file, which maps device addresses to
+/// their preferences
+pub fn load_device_preference<T: DeserializeOwned>(mac: &str, key: &str) -> Option<T> {
+    std::fs::read_to_string(get_preferences_path())
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v.get(mac).and_then(|prefs| prefs.get(key)).cloned())
+        .and_then(|v| serde_json::from_value(v).ok())
+}
+
+/// Write a single per-device key to the preferences file, keeping every other key intact
+pub fn save_device_preference(mac: &str, key: &str, value: serde_json::Value) {
+    let preferences_path = get_preferences_path();
+    let mut preferences = std::fs::read_to_string(&preferences_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .filter(|v| v.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    if !preferences[mac].is_object() {
+        preferences[mac] = serde_json::json!({});
+    }
+    preferences[mac][key] = value;
+    debug!(
+        "Writing preferences to {}: {}",
+        preferences_path.to_str().unwrap(),
+        preferences
+    );
+    if let Some(parent) = preferences_path.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    if let Err(e) = std::fs::write(&preferences_path, preferences.to_string()) {
+        error!("Failed to write preferences: {}", e);
+    }
+}
+
+/// Run a user-configured shell command without waiting for it to finish
+pub fn run_command(command: &str) {
+    info!("Running command: {}", command);
+    match Command::new("sh").arg("-c").arg(command).spawn() {
+        Ok(mut child) => {
+            // reap the child in the background so it doesn't linger as a zombie
+            std::thread::spawn(move || {
+                let _ = child.wait();
+            });
+        }
+        Err(e) => error!("Failed to run command '{}': {}", command, e),
+    }
+}
+
 fn e(key: &[u8; 16], data: &[u8; 16]) -> [u8; 16] {
     let mut swapped_key = *key;
     swapped_key.reverse();